@@ -0,0 +1,108 @@
+// Error responses are returned as they are, see the same allow in lib.rs
+#![allow(clippy::result_large_err)]
+
+use std::collections::HashMap;
+use std::env;
+use std::io::Read;
+use std::sync::Arc;
+use std::thread;
+
+use lambda_http::{Response, Body};
+use tiny_http::{Header, Server};
+
+use commentable_rs::utils::http::bad_request;
+use commentable_rs::utils::router::{route, ProxyEvent};
+
+// A standalone HTTP server exposing every endpoint without AWS Lambda or API Gateway.
+// Configure it with:
+//   COMMENTABLE_RS_ADDRESS - the address to listen on (default: 127.0.0.1:3000)
+//   COMMENTABLE_RS_THREADS - the number of worker threads (default: 4)
+//   COMMENTABLE_RS_MAX_BODY_BYTES - the largest accepted request body (default: 1 MiB, API Gateway allows 10 MiB)
+
+static DEFAULT_ADDRESS: &str = "127.0.0.1:3000";
+static DEFAULT_THREADS: usize = 4;
+static DEFAULT_MAX_BODY_BYTES: u64 = 1024 * 1024;
+
+fn max_body_bytes() -> u64 {
+  env::var("COMMENTABLE_RS_MAX_BODY_BYTES").ok()
+    .and_then(|bytes| bytes.parse().ok())
+    .unwrap_or(DEFAULT_MAX_BODY_BYTES)
+}
+
+fn into_proxy_event(request: &mut tiny_http::Request) -> Result<ProxyEvent, Response<Body>> {
+  let max_body_bytes = max_body_bytes();
+  let mut body = vec![];
+  // One byte over the limit is enough to tell that the body is too large, without reading the rest of it
+  request.as_reader().take(max_body_bytes + 1).read_to_end(&mut body).map_err(bad_request)?;
+  if body.len() as u64 > max_body_bytes {
+    return Err(bad_request(format!("Invalid request body: it can't be larger than {} bytes", max_body_bytes)));
+  }
+  let body = String::from_utf8(body)
+    .map_err(|_| bad_request("Invalid request body: it must be UTF-8 encoded"))?;
+
+  let mut url = request.url().splitn(2, '?');
+  let path = url.next().unwrap_or("/").to_string();
+  let query_string_parameters = serde_urlencoded::from_str::<HashMap<String, String>>(url.next().unwrap_or(""))
+    .unwrap_or_default();
+  let headers = request
+    .headers()
+    .iter()
+    .map(|header| (header.field.as_str().to_string(), header.value.as_str().to_string()))
+    .collect();
+
+  Ok(ProxyEvent {
+    path,
+    http_method: request.method().as_str().to_string(),
+    headers,
+    query_string_parameters,
+    path_parameters: HashMap::new(),
+    body: if body.is_empty() { None } else { Some(body) },
+    is_base64_encoded: false,
+  })
+}
+
+fn respond(mut request: tiny_http::Request) {
+  let response = into_proxy_event(&mut request)
+    .and_then(|event| event.into_request())
+    .map(route)
+    .unwrap_or_else(|error_response| error_response);
+
+  let mut http_response = tiny_http::Response::from_data(match response.body() {
+    Body::Empty => vec![],
+    Body::Text(text) => text.clone().into_bytes(),
+    Body::Binary(bytes) => bytes.clone(),
+  }).with_status_code(response.status().as_u16());
+
+  for (name, value) in response.headers() {
+    if let Ok(header) = Header::from_bytes(name.as_str().as_bytes(), value.as_bytes()) {
+      http_response.add_header(header);
+    }
+  }
+
+  if let Err(err) = request.respond(http_response) {
+    eprintln!("Error sending response: {}", err);
+  }
+}
+
+fn main() {
+  let address = env::var("COMMENTABLE_RS_ADDRESS").unwrap_or_else(|_| DEFAULT_ADDRESS.to_string());
+  let threads = env::var("COMMENTABLE_RS_THREADS").ok()
+    .and_then(|threads| threads.parse().ok())
+    .unwrap_or(DEFAULT_THREADS);
+
+  let server = Arc::new(Server::http(&address).expect("Couldn't start the server"));
+  println!("Commentable.rs is listening on http://{}", address);
+
+  let workers = (0..threads).map(|_| {
+    let server = server.clone();
+    thread::spawn(move || {
+      for request in server.incoming_requests() {
+        respond(request);
+      }
+    })
+  }).collect::<Vec<_>>();
+
+  for worker in workers {
+    let _ = worker.join();
+  }
+}
@@ -0,0 +1,10 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::count_comments::CountComments;
+
+fn main() {
+  lambda!(|request, _|
+    CountComments::respond_to(request)
+      .or_else(Ok)
+  );
+}
@@ -0,0 +1,10 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::list_revisions::ListRevisions;
+
+fn main() {
+  lambda!(|request, _|
+    ListRevisions::respond_to(request)
+      .or_else(Ok)
+  );
+}
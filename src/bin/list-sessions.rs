@@ -0,0 +1,7 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::list_sessions::ListSessions;
+
+fn main() {
+  lambda!(|request, _| ListSessions::respond_to(request).or_else(Ok));
+}
@@ -0,0 +1,7 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::logout::Logout;
+
+fn main() {
+  lambda!(|request, _| Logout::respond_to(request).or_else(Ok));
+}
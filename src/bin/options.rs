@@ -1,7 +1,7 @@
 use lambda_http::lambda;
 
-use commentable_rs::utils::http::ok;
+use commentable_rs::handlers::options::options;
 
 fn main() {
-  lambda!(|_, _| Ok(ok("")));
+  lambda!(|request, _| Ok(options(request)));
 }
@@ -0,0 +1,10 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::pin_comment::PinComment;
+
+fn main() {
+  lambda!(|request, _|
+    PinComment::respond_to(request)
+      .or_else(Ok)
+  );
+}
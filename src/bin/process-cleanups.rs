@@ -0,0 +1,48 @@
+use std::env;
+use std::process;
+
+use lambda_runtime::{lambda, error::HandlerError, Context};
+use serde_json::Value;
+
+use commentable_rs::handlers::process_cleanups::{process_cleanups, ProcessedCleanups};
+use commentable_rs::storage;
+
+// Runs the cleanups left queued by comment deletions that failed to remove all reactions & revisions:
+//   cargo run --bin process-cleanups
+// Uses the storage backend selected with COMMENTABLE_RS_STORAGE (DynamoDB by default).
+// Deployed as a Lambda function, it's run on a schedule instead (see lambda/release/template.yml).
+fn main() {
+  if env::var("AWS_LAMBDA_RUNTIME_API").is_ok() {
+    lambda!(handler);
+    return;
+  }
+
+  let db = storage::connect().unwrap_or_else(|err| {
+    eprintln!("Couldn't connect to the storage: {}", err);
+    process::exit(1);
+  });
+
+  match process_cleanups(db.as_ref()) {
+    Ok(cleanups) => {
+      println!("{} cleanup(s) processed", cleanups.processed);
+      if cleanups.failed > 0 {
+        process::exit(1);
+      }
+    },
+    Err(err) => {
+      eprintln!("Couldn't list pending cleanups: {}", err);
+      process::exit(1);
+    },
+  }
+}
+
+// Scheduled events only trigger a run, their contents don't matter.
+// Failed runs are reported as errors, so they show up in the metrics of the function.
+fn handler(_: Value, _: Context) -> Result<ProcessedCleanups, HandlerError> {
+  let db = storage::connect().map_err(|err| HandlerError::from(err.to_string().as_str()))?;
+  let cleanups = process_cleanups(db.as_ref()).map_err(|err| HandlerError::from(err.to_string().as_str()))?;
+  if cleanups.failed > 0 {
+    return Err(HandlerError::from(format!("{} of {} cleanup(s) failed", cleanups.failed, cleanups.processed).as_str()));
+  }
+  Ok(cleanups)
+}
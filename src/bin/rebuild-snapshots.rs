@@ -0,0 +1,36 @@
+use std::env;
+use std::process;
+
+use commentable_rs::models::snapshot::Snapshot;
+use commentable_rs::storage;
+
+// Rebuilds the snapshots of the given commentables from their comments (see models/snapshot.rs):
+//   cargo run --bin rebuild-snapshots -- <commentable_id>...
+// Uses the storage backend selected with COMMENTABLE_RS_STORAGE (DynamoDB by default).
+fn main() {
+  let commentable_ids = env::args().skip(1).collect::<Vec<String>>();
+  if commentable_ids.is_empty() {
+    eprintln!("Usage: rebuild-snapshots <commentable_id>...");
+    process::exit(1);
+  }
+
+  let db = storage::connect().unwrap_or_else(|err| {
+    eprintln!("Couldn't connect to the storage: {}", err);
+    process::exit(1);
+  });
+
+  let mut failed = false;
+  for commentable_id in commentable_ids {
+    match Snapshot::rebuild(db.as_ref(), commentable_id.clone()) {
+      Ok(Some(thread)) => println!("{}: rebuilt with {} comment(s)", commentable_id, thread.comments.len()),
+      Ok(None) => println!("{}: too large to be snapshotted, it will be listed from comments", commentable_id),
+      Err(err) => {
+        eprintln!("{}: {}", commentable_id, err);
+        failed = true;
+      },
+    }
+  }
+  if failed {
+    process::exit(1);
+  }
+}
@@ -0,0 +1,7 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::refresh_auth::refresh_auth;
+
+fn main() {
+  lambda!(|request, _| Ok(refresh_auth(request)));
+}
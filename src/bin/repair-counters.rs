@@ -0,0 +1,67 @@
+use std::env;
+use std::process;
+
+use commentable_rs::models::comment::Comment;
+use commentable_rs::models::counters::Counters;
+use commentable_rs::models::reaction::Reaction;
+use commentable_rs::models::snapshot::Snapshot;
+use commentable_rs::storage;
+use commentable_rs::utils::config;
+
+// Removes orphaned replies of the given commentables, recomputes their comment counters and the reaction counters
+// stored with comments, and adds their top-level comments created before the top-level index to the index:
+//   cargo run --bin repair-counters -- <commentable_id>...
+// With --all, every commentable with comments is repaired (found by scanning the whole table):
+//   cargo run --bin repair-counters -- --all
+// Uses the storage backend selected with COMMENTABLE_RS_STORAGE (DynamoDB by default).
+fn main() {
+  let args = env::args().skip(1).collect::<Vec<String>>();
+  if args.is_empty() {
+    eprintln!("Usage: repair-counters <commentable_id>... | --all");
+    process::exit(1);
+  }
+
+  let db = storage::connect().unwrap_or_else(|err| {
+    eprintln!("Couldn't connect to the storage: {}", err);
+    process::exit(1);
+  });
+
+  let commentable_ids = if args == ["--all"] {
+    Comment::commentable_ids(db.as_ref()).unwrap_or_else(|err| {
+      eprintln!("Couldn't list commentables: {}", err);
+      process::exit(1);
+    }).into_iter().collect()
+  } else {
+    args
+  };
+
+  let mut failed = false;
+  for commentable_id in commentable_ids {
+    let result = Comment::remove_orphans(db.as_ref(), commentable_id.clone()).and_then(|removed| {
+      println!("{}: removed {} orphaned comment(s)", commentable_id, removed);
+      Comment::index_top_level(db.as_ref(), commentable_id.clone())
+    }).and_then(|indexed| {
+      println!("{}: indexed {} top-level comment(s)", commentable_id, indexed);
+      Counters::recount(db.as_ref(), commentable_id.clone())
+    }).and_then(|counters| {
+      println!("{}: {} comment(s), {} top-level", commentable_id, counters.total_comments, counters.top_level_comments);
+      Reaction::repair_counts(db.as_ref(), commentable_id.clone())
+    }).and_then(|repaired| {
+      // Snapshots contain reaction counts as well
+      if repaired > 0 && config::snapshots_enabled() {
+        Snapshot::rebuild(db.as_ref(), commentable_id.clone())?;
+      }
+      Ok(repaired)
+    });
+    match result {
+      Ok(repaired) => println!("{}: repaired reaction counters of {} comment(s)", commentable_id, repaired),
+      Err(err) => {
+        eprintln!("{}: {}", commentable_id, err);
+        failed = true;
+      },
+    }
+  }
+  if failed {
+    process::exit(1);
+  }
+}
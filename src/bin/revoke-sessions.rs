@@ -0,0 +1,7 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::revoke_sessions::RevokeSessions;
+
+fn main() {
+  lambda!(|request, _| RevokeSessions::respond_to(request).or_else(Ok));
+}
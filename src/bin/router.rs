@@ -0,0 +1,8 @@
+use lambda_http::lambda;
+
+use commentable_rs::utils::router::route;
+
+// Serves every endpoint from a single Lambda function (see lambda/release/router.yml)
+fn main() {
+  lambda!(|request, _| Ok(route(request)));
+}
@@ -0,0 +1,10 @@
+use lambda_http::lambda;
+
+use commentable_rs::handlers::update_commentable::UpdateCommentable;
+
+fn main() {
+  lambda!(|request, _|
+    UpdateCommentable::respond_to(request)
+      .or_else(Ok)
+  );
+}
@@ -0,0 +1,12 @@
+use lambda_runtime::{lambda, error::HandlerError, Context};
+
+use commentable_rs::handlers::websocket::{websocket, WebSocketEvent, WebSocketResponse};
+
+fn handler(event: WebSocketEvent, _: Context) -> Result<WebSocketResponse, HandlerError> {
+  Ok(websocket(event))
+}
+
+// Handles the routes of the WebSocket API (see lambda/release/template.yml)
+fn main() {
+  lambda!(handler);
+}
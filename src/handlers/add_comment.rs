@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::Utc;
+use lambda_http::{Request, Response, Body, RequestExt};
+use maplit::hashmap;
+use serde::{Serialize, Deserialize};
+
+use crate::push::{self, Event, Push};
+use crate::storage::{self, Storage};
+use crate::utils::db::{sortable_timestamp, CommentableId, DynamoDbAttributes, DynamoDbModel, IntoDynamoDbAttributes, Transaction, VERSION_FIELD_NAME};
+use crate::utils::http::{ok, bad_request, internal_server_error, HttpError};
+use crate::utils::current_user::CurrentUser;
+use crate::utils::current_commentable::CurrentCommentable;
+use crate::models::{
+  user::{AuthToken, User, UserId},
+  comment::{comment_id, Comment, CommentId, TOP_LEVEL_ID_FIELD_NAME},
+  commentable::Commentable,
+  counters::{Counters, COUNTERS_ID},
+  snapshot::Snapshot,
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+  replies_to: Option<CommentId>,
+  body: String,
+}
+
+pub struct AddComment {
+  db: Arc<dyn Storage>,
+  push: Arc<dyn Push>,
+  commentable_id: CommentableId,
+  params: Params,
+  current_user: Option<User>,
+  current_commentable: Option<Commentable>,
+  comment: Option<Comment>,
+}
+
+#[derive(Serialize, Clone)]
+struct UserJson {
+  id: UserId,
+  name: String,
+  picture_url: String,
+}
+
+#[derive(Serialize)]
+struct CommentJson {
+  id: CommentId,
+  body: String,
+  user: Option<UserJson>,
+  replies: Vec<()>,
+  reactions: HashMap<(), ()>,
+  user_reactions: Vec<()>,
+  edited_at: Option<String>,
+  version: i64,
+  created_at: String,
+}
+
+impl CurrentUser for AddComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl CurrentCommentable for AddComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn current_commentable(&self) -> Option<&Commentable> {
+    self.current_commentable.as_ref()
+  }
+
+  fn set_current_commentable(&mut self, commentable: Option<Commentable>) {
+    self.current_commentable = commentable;
+  }
+}
+
+impl AddComment {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate()?
+        .fetch_current_user()?
+        .fetch_current_commentable()?
+        .create_missing_commentable()?
+        .check_accepts_comments()?
+        .check_reply()?
+        .save()?
+        .update_snapshot()
+        .notify()
+        .serialize()
+    } else {
+      Err(bad_request("Invalid params: 'id' is required."))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        push: push::connect().map_err(internal_server_error)?,
+        comment: None,
+        current_user: None,
+        current_commentable: None,
+        commentable_id,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request("auth_token is required"))
+    } else if self.params.body.trim().is_empty() {
+      Err(bad_request("body is required"))
+    } else {
+      Ok(self)
+    }
+  }
+
+  // Metadata of older commentables is dated by their first comment, so it has to be saved before the new one
+  pub fn create_missing_commentable(&mut self) -> Result<&mut Self, HttpError> {
+    if self.current_commentable.is_none() {
+      let commentable = Commentable::create_for_commentable(self.db.as_ref(), self.commentable_id.clone())
+        .map_err(internal_server_error)?;
+      self.current_commentable = Some(commentable);
+    }
+    Ok(self)
+  }
+
+  pub fn check_reply(&mut self) -> Result<&mut Self, HttpError> {
+    if let Some(comment_id) = &self.params.replies_to {
+      match Comment::find(self.db.as_ref(), self.commentable_id.clone(), comment_id.clone()) {
+        Ok(Some(_)) => Ok(self),
+        Ok(None) => Err(bad_request("replies_to is not a valid comment ID")),
+        Err(err) => Err(internal_server_error(err)),
+      }
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn save(&mut self) -> Result<&mut Self, HttpError> {
+    let current_user_id = &self.current_user.as_ref().unwrap().id;
+    let now = Utc::now();
+    let id = comment_id(&self.commentable_id, current_user_id);
+    let mut attributes = IntoDynamoDbAttributes {
+      attributes: hashmap!{
+        String::from("primary_key") => self.commentable_id.clone().into(),
+        String::from("id") => id.clone().into(),
+        String::from("user_id") => current_user_id.clone().into(),
+        String::from("body") => self.params.body.clone().into(),
+        String::from("created_at") => now.to_rfc3339().into(),
+        String::from("changed_at") => sortable_timestamp(now).into(),
+        String::from(VERSION_FIELD_NAME) => 1i64.into(),
+      }
+    };
+    // String::from("replies_to") = self.params.replies_to.clone().into(),
+    if let Some(parent_comment_id) = self.params.replies_to.clone() {
+      attributes.attributes.insert(String::from("replies_to"), parent_comment_id.into());
+    } else {
+      attributes.attributes.insert(String::from(TOP_LEVEL_ID_FIELD_NAME), id.into());
+    }
+    let attributes: DynamoDbAttributes = attributes.into();
+    let comment = Comment::new(attributes.clone()).map_err(internal_server_error)?;
+
+    // The comment is counted in the same transaction, so the counters can't miss it
+    Transaction::default()
+      .put(attributes, None)
+      .update(self.commentable_id.clone(), COUNTERS_ID.to_string(), Counters::count_comment_update(&comment, 1))
+      .commit(self.db.as_ref())
+      .map_err(internal_server_error)?;
+    self.comment = Some(comment);
+    Ok(self)
+  }
+
+  pub fn update_snapshot(&mut self) -> &mut Self {
+    // The unwraps are safe because we check for comment & user presence in #save
+    let (comment, user) = (self.comment.as_ref().unwrap(), self.current_user.as_ref().unwrap());
+    if let Err(err) = Snapshot::update(self.db.as_ref(), self.commentable_id.clone(), |thread| thread.add_comment(comment, user)) {
+      eprintln!("Error updating the snapshot of {}: {}", self.commentable_id, err);
+    }
+    self
+  }
+
+  pub fn notify(&mut self) -> &mut Self {
+    // The unwrap is safe because we check for comment presence in #save
+    let comment = self.comment.as_ref().unwrap();
+    push::broadcast(self.db.as_ref(), self.push.as_ref(), &self.commentable_id, &Event::CommentAdded { comment });
+    self
+  }
+
+  pub fn serialize(&mut self) -> Result<Response<Body>, HttpError> {
+    // The unwraps are safe because we check for comment presence in #save
+    let comment = self.comment.as_ref().unwrap();
+    let user = self.current_user.as_ref().unwrap();
+    Ok(ok(serde_json::to_string(&CommentJson {
+      id: comment.id.clone(),
+      body: comment.body.clone(),
+      user: Some(UserJson {
+        id: user.id.clone(),
+        name: user.name.clone(),
+        picture_url: user.picture_url.clone(),
+      }),
+      replies: vec![],
+      reactions: hashmap!{},
+      user_reactions: vec![],
+      edited_at: None,
+      version: comment.version,
+      created_at: comment.created_at.to_string(),
+    }).unwrap()))
+  }
+}
@@ -0,0 +1,131 @@
+use chrono::Utc;
+use lambda_http::{Request, Response, Body, RequestExt};
+use maplit::hashmap;
+use serde::{Serialize, Deserialize};
+
+use crate::storage;
+use crate::utils::config;
+use crate::utils::http::{ok, bad_request, unauthorized, internal_server_error};
+use crate::utils::db::{DynamoDbModel, IntoDynamoDbAttributes};
+use crate::utils::jwt::{invalid, unverified_issuer, JwtError};
+use crate::utils::oidc::{self, AuthData, Provider};
+use crate::models::{
+  session::{Session, SessionId, Tokens},
+  user::User,
+};
+
+static UNKNOWN_DEVICE: &str = "Unknown device";
+
+#[derive(Deserialize)]
+struct Params {
+  id_token: String,
+  // Shown in the list of sessions, the User-Agent header is used when missing
+  device: Option<String>,
+}
+
+// The signed in user, along with the tokens of the new session
+#[derive(Serialize)]
+struct SignedInJson<'a> {
+  #[serde(flatten)]
+  user: &'a User,
+  session_id: &'a SessionId,
+  auth_token: &'a str,
+  auth_token_expires_at: String,
+  refresh_token: &'a str,
+  refresh_token_expires_at: String,
+}
+
+pub fn signed_in(user: &User, session: &Session, tokens: &Tokens) -> Response<Body> {
+  let json = SignedInJson {
+    user,
+    session_id: &session.id,
+    auth_token: &tokens.auth_token,
+    auth_token_expires_at: session.auth_token_expires_at.to_rfc3339(),
+    refresh_token: &tokens.refresh_token,
+    refresh_token_expires_at: session.refresh_token_expires_at.to_rfc3339(),
+  };
+  match serde_json::to_string(&json) {
+    Ok(json) => ok(json),
+    Err(err) => internal_server_error(err),
+  }
+}
+
+impl From<AuthData> for IntoDynamoDbAttributes {
+  fn from(auth_data: AuthData) -> Self {
+    IntoDynamoDbAttributes {
+      attributes: hashmap!{
+        String::from("primary_key") => auth_data.user_id.clone().into(),
+        String::from("id") => auth_data.user_id.into(),
+        String::from("provider") => auth_data.provider.into(),
+        String::from("email") => auth_data.email.into(),
+        String::from("name") => auth_data.name.into(),
+        String::from("picture_url") => auth_data.picture.into(),
+        String::from("created_at") => Utc::now().to_rfc3339().into(),
+      }
+    }
+  }
+}
+
+pub fn auth(request: Request) -> Response<Body> {
+  match oidc::providers() {
+    Ok(ref providers) if providers.is_empty() => internal_server_error(format!(
+      "Signing in requires {} or {} to be set",
+      config::GOOGLE_CLIENT_IDS_ENV_VAR,
+      config::OIDC_PROVIDERS_ENV_VAR,
+    )),
+    Ok(providers) => auth_with(request, &providers),
+    Err(err) => internal_server_error(err),
+  }
+}
+
+// Signs in with an ID token of one of the given providers (e.g. one using locally generated keys)
+pub fn auth_with(request: Request, providers: &[Provider]) -> Response<Body> {
+  if let Ok(Some(params)) = request.payload::<Params>() {
+    let provider = match unverified_issuer(&params.id_token)
+      .and_then(|issuer| providers.iter().find(|provider| provider.accepts_issuer(&issuer))) {
+      Some(provider) => provider,
+      None => return unauthorized(invalid("unexpected issuer")),
+    };
+    // Validate the token offline, against the cached keys of the issuer
+    let auth_data = match provider.verifier().verify(&params.id_token).and_then(|claims| provider.auth_data(claims)) {
+      Ok(auth_data) => auth_data,
+      Err(err @ JwtError::Invalid(_)) => return unauthorized(err),
+      Err(err) => return internal_server_error(err),
+    };
+    let db = match storage::connect() {
+      Ok(db) => db,
+      Err(err) => return internal_server_error(err),
+    };
+    // Look for an existing user (see Provider#auth_data)
+    let user_id = auth_data.user_id.clone();
+    let mut user = match User::find(db.as_ref(), user_id.clone(), user_id.clone()) {
+      Ok(Some(user)) => user,
+      // Create a new user
+      Ok(None) => match User::create(db.as_ref(), auth_data.into()) {
+        Ok(user) => user,
+        Err(err) => return internal_server_error(format!("Error creating a user: {}", err)),
+      },
+      Err(err) => return internal_server_error(format!("Error finding a user: {}", err)),
+    };
+    // Signing in ends the session the user had before sessions were introduced
+    if user.legacy_auth_token.is_some() {
+      if let Err(err) = user.remove_legacy_tokens(db.as_ref()) {
+        eprintln!("Error removing legacy tokens of {}: {}", user_id, err);
+      }
+    }
+    // Every sign in starts a new session, other devices stay signed in
+    let label = params.device
+      .or_else(|| request.headers().get("User-Agent").and_then(|agent| agent.to_str().ok()).map(String::from))
+      .filter(|label| !label.trim().is_empty())
+      .unwrap_or_else(|| UNKNOWN_DEVICE.to_string());
+    if let Err(err) = Session::remove_stale(db.as_ref(), user_id.clone()) {
+      eprintln!("Error removing stale sessions of {}: {}", user_id, err);
+    }
+    match Session::start(db.as_ref(), &user_id, label.trim()) {
+      Ok((session, tokens)) => signed_in(&user, &session, &tokens),
+      Err(err) => internal_server_error(format!("Error starting a session: {}", err)),
+    }
+  } else {
+    bad_request("Invalid params.")
+  }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::{Serialize, Deserialize};
+
+use crate::storage::{self, Storage};
+use crate::models::counters::Counters;
+use crate::utils::db::CommentableId;
+use crate::utils::http::{ok, bad_request, internal_server_error, HttpError};
+
+// The max number of commentables counted in a single request
+static MAX_COMMENTABLE_IDS: usize = 100;
+
+#[derive(Deserialize)]
+struct Params {
+  commentable_ids: Vec<CommentableId>,
+}
+
+#[derive(Serialize, Default)]
+struct CountJson {
+  total: i64,
+  top_level: i64,
+}
+
+pub struct CountComments {
+  db: Arc<dyn Storage>,
+  params: Params,
+  counts: HashMap<CommentableId, CountJson>,
+}
+
+impl CountComments {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    Self::new(request)?
+      .validate()?
+      .fetch_counters()?
+      .serialize()
+  }
+
+  pub fn new(request: Request) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        counts: HashMap::new(),
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.commentable_ids.is_empty() || self.params.commentable_ids.len() > MAX_COMMENTABLE_IDS {
+      Err(bad_request(format!("Invalid parameters: between 1 and {} commentable_ids are required.", MAX_COMMENTABLE_IDS)))
+    } else if self.params.commentable_ids.iter().any(|id| id.trim().is_empty()) {
+      Err(bad_request("Invalid parameters: commentable_ids can't be blank."))
+    } else {
+      self.params.commentable_ids.sort();
+      self.params.commentable_ids.dedup();
+      Ok(self)
+    }
+  }
+
+  pub fn fetch_counters(&mut self) -> Result<&mut Self, HttpError> {
+    let counters = Counters::batch_find_for_commentables(self.db.as_ref(), self.params.commentable_ids.clone())
+      .map_err(internal_server_error)?;
+    // Commentables without counters don't have any comments
+    self.counts = self.params.commentable_ids.iter().map(|id| (id.clone(), CountJson::default())).collect();
+    for counter in counters {
+      self.counts.insert(counter.primary_key, CountJson {
+        total: counter.total_comments,
+        top_level: counter.top_level_comments,
+      });
+    }
+    Ok(self)
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(serde_json::to_string(&self.counts).map_err(internal_server_error)?))
+  }
+}
@@ -0,0 +1,209 @@
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::Deserialize;
+
+use crate::push::{self, Event, Push};
+use crate::storage::{self, Condition, Storage};
+use crate::utils::db::{DynamoDbModel, CommentableId, DbError, Transaction, MAX_TRANSACTION_WRITES, VERSION_FIELD_NAME};
+use crate::utils::http::{ok, bad_request, conflict, error_code, forbidden, internal_server_error, HttpError};
+use crate::utils::current_user::CurrentUser;
+use crate::utils::current_comment::CurrentComment;
+use crate::models::{
+  user::{AuthToken, User},
+  cleanup::Cleanup,
+  comment::{CommentId, Comment},
+  commentable::Commentable,
+  counters::{Counters, COUNTERS_ID},
+  reaction::Reaction,
+  revision::Revision,
+  snapshot::Snapshot,
+  tombstone::Tombstone,
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+  comment_id: CommentId,
+}
+
+pub struct DeleteComment {
+  db: Arc<dyn Storage>,
+  push: Arc<dyn Push>,
+  commentable_id: CommentableId,
+  params: Params,
+  current_user: Option<User>,
+  comment: Option<Comment>,
+  has_replies: bool,
+}
+
+impl CurrentUser for DeleteComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl CurrentComment for DeleteComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn comment_id(&self) -> CommentId {
+    self.params.comment_id.clone()
+  }
+
+  fn set_current_comment(&mut self, comment: Comment) {
+    self.comment = Some(comment);
+  }
+}
+
+impl DeleteComment {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate_params()?
+        .fetch_current_user()?
+        .fetch_current_comment()?
+        .authorize()?
+        .check_replies()?
+        .delete_or_erase()?
+        .update_snapshot()
+        .notify()
+        .serialize()
+    } else {
+      Err(bad_request("Invalid path parameters: 'id' is required."))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        push: push::connect().map_err(internal_server_error)?,
+        comment: None,
+        current_user: None,
+        has_replies: false,
+        commentable_id,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request("Parameter 'auth_token' is required."))
+    } else if self.params.comment_id.trim().is_empty() {
+      Err(bad_request("Parameter 'comment_id' is required."))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn authorize(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwraps are safe because presence is guaranteed by calls
+    // to #fetch_current_user and #fetch_current_comment
+    if self.comment.as_ref().unwrap().user_id == Some(self.current_user.as_ref().unwrap().id.clone()) {
+      Ok(self)
+    } else {
+      Err(forbidden("Cannot delete comment."))
+    }
+  }
+
+  pub fn check_replies(&mut self) -> Result<&mut Self, HttpError> {
+    match self.comment.as_ref().unwrap().has_replies(self.db.as_ref()) {
+      Ok(has_replies) => self.has_replies = has_replies,
+      Err(error) => return Err(internal_server_error(error))
+    }
+    Ok(self)
+  }
+
+  // The comment, its counters, reactions & revisions are changed in a single transaction.
+  // Reactions & revisions which don't fit the transaction are queued for a cleanup instead.
+  pub fn delete_or_erase(&mut self) -> Result<&mut Self, HttpError> {
+    let (db, commentable_id, comment_id) = (self.db.as_ref(), self.commentable_id.clone(), self.params.comment_id.clone());
+    // The unwrap is safe because presence is guaranteed by #fetch_current_comment
+    let comment = self.comment.as_ref().unwrap();
+
+    // Erased comments aren't counted either
+    let mut transaction = Transaction::default()
+      .update(commentable_id.clone(), COUNTERS_ID.to_string(), Counters::count_comment_update(comment, -1));
+    if self.has_replies {
+      transaction = transaction.update(commentable_id.clone(), comment_id.clone(), comment.erase_update());
+    } else {
+      transaction = transaction
+        .delete(commentable_id.clone(), comment_id.clone(), Some(Condition::NumberEquals(VERSION_FIELD_NAME.to_string(), comment.version)))
+        .put(Tombstone::attributes_for_comment(commentable_id.clone(), comment_id.clone()), None);
+    }
+
+    let mut related_keys = Reaction::keys_for_comment(db, commentable_id.clone(), comment_id.clone())
+      .map_err(internal_server_error)?;
+    related_keys.append(&mut Revision::keys_for_comment(db, commentable_id.clone(), &comment_id).map_err(internal_server_error)?);
+    let cleanup = if transaction.len() + related_keys.len() <= MAX_TRANSACTION_WRITES {
+      transaction = transaction.delete_all(related_keys);
+      None
+    } else {
+      let cleanup = Cleanup::for_comment(commentable_id.clone(), comment_id.clone());
+      transaction = transaction.put(cleanup.attributes(), None);
+      Some(cleanup)
+    };
+
+    match transaction.commit(db) {
+      Ok(()) => (),
+      // The comment was changed (or deleted) after it was fetched
+      Err(DbError::ConditionFailed) => return Err(conflict(error_code(
+        "version_conflict",
+        "The comment has been changed since it was loaded.",
+      ))),
+      Err(err) => return Err(internal_server_error(err)),
+    }
+
+    // The comment is already gone at this point, so the remaining steps don't fail the request
+    if let Some(cleanup) = cleanup {
+      if let Err(err) = cleanup.run(db) {
+        eprintln!("Error cleaning up after comment {} of {} (queued as {}): {}", comment_id, commentable_id, cleanup.id, err);
+      }
+    }
+    if let Err(err) = Commentable::unpin_deleted_comment(db, commentable_id.clone(), &comment_id) {
+      eprintln!("Error unpinning comment {} of {}: {}", comment_id, commentable_id, err);
+    }
+    self.comment = if self.has_replies {
+      Comment::find(db, commentable_id, comment_id).map_err(internal_server_error)?
+    } else {
+      None
+    };
+    Ok(self)
+  }
+
+  pub fn update_snapshot(&mut self) -> &mut Self {
+    // Only erased comments are still present at this point
+    let (comment_id, erased) = (&self.params.comment_id, self.comment.as_ref());
+    if let Err(err) = Snapshot::update(self.db.as_ref(), self.commentable_id.clone(), |thread| thread.delete_comment(comment_id, erased)) {
+      eprintln!("Error updating the snapshot of {}: {}", self.commentable_id, err);
+    }
+    self
+  }
+
+  pub fn notify(&mut self) -> &mut Self {
+    let event = Event::CommentDeleted { comment_id: &self.params.comment_id, erased: self.has_replies };
+    push::broadcast(self.db.as_ref(), self.push.as_ref(), &self.commentable_id, &event);
+    self
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(serde_json::to_string(&self.comment).unwrap()))
+  }
+}
@@ -0,0 +1,194 @@
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::Deserialize;
+
+use crate::push::{self, Event, Push};
+use crate::storage::{self, Condition, Storage};
+use crate::utils::db::{DynamoDbModel, CommentableId, DbError, Transaction};
+use crate::utils::http::{ok, bad_request, internal_server_error, HttpError};
+use crate::utils::current_user::CurrentUser;
+use crate::utils::current_comment::CurrentComment;
+use crate::utils::current_commentable::CurrentCommentable;
+use crate::models::{
+  user::{AuthToken, User, UserId},
+  comment::{CommentId, Comment},
+  commentable::Commentable,
+  reaction::{reaction_id, Reaction, ReactionType},
+  snapshot::Snapshot,
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+  comment_id: CommentId,
+  reaction_type: ReactionType,
+}
+
+pub struct DeleteReaction {
+  db: Arc<dyn Storage>,
+  push: Arc<dyn Push>,
+  commentable_id: CommentableId,
+  params: Params,
+  current_user: Option<User>,
+  current_comment: Option<Comment>,
+  current_commentable: Option<Commentable>,
+  reaction: Option<Reaction>,
+}
+
+impl CurrentUser for DeleteReaction {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl CurrentComment for DeleteReaction {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn comment_id(&self) -> CommentId {
+    self.params.comment_id.clone()
+  }
+
+  fn set_current_comment(&mut self, comment: Comment) {
+    self.current_comment = Some(comment);
+  }
+}
+
+impl CurrentCommentable for DeleteReaction {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn current_commentable(&self) -> Option<&Commentable> {
+    self.current_commentable.as_ref()
+  }
+
+  fn set_current_commentable(&mut self, commentable: Option<Commentable>) {
+    self.current_commentable = commentable;
+  }
+}
+
+impl DeleteReaction {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate_params()?
+        .fetch_current_user()?
+        .fetch_current_comment()?
+        .fetch_current_commentable()?
+        .check_not_locked()?
+        .fetch_reaction()?
+        .delete()?
+        .update_snapshot()
+        .notify()
+        .serialize()
+    } else {
+      Err(bad_request("Invalid path parameters: 'id' is required."))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        push: push::connect().map_err(internal_server_error)?,
+        commentable_id,
+        current_comment: None,
+        current_user: None,
+        current_commentable: None,
+        reaction: None,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  fn current_user_id(&self) -> &UserId {
+    &self.current_user.as_ref().unwrap().id
+  }
+
+  fn current_comment_id(&self) -> &CommentId {
+    &self.current_comment.as_ref().unwrap().id
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request("Invalid request parameters: auth_token is required"))
+    } else if self.params.comment_id.trim().is_empty() {
+      Err(bad_request("Invalid request parameters: comment_id is required"))
+    } else if self.params.reaction_type.trim().is_empty() {
+      Err(bad_request("Invalid request parameters: reaction_type is required"))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn fetch_reaction(&mut self) -> Result<&mut Self, HttpError> {
+    let id = reaction_id(self.current_comment_id(), self.current_user_id(), &self.params.reaction_type);
+
+    match Reaction::find(self.db.as_ref(), self.commentable_id.clone(), id) {
+      Ok(Some(reaction)) => self.reaction = Some(reaction),
+      Ok(None) => return Err(bad_request("Could not delete reaction.")),
+      Err(err) => return Err(internal_server_error(err)),
+    }
+
+    Ok(self)
+  }
+
+  pub fn delete(&mut self) -> Result<&mut Self, HttpError> {
+    let id = reaction_id(self.current_comment_id(), self.current_user_id(), &self.params.reaction_type);
+
+    // The reaction has to exist, so a reaction deleted twice at once isn't uncounted twice,
+    // and so does the comment (see Comment::count_reaction_update)
+    let deleted = Transaction::default()
+      .delete(self.commentable_id.clone(), id, Some(Condition::Exists))
+      .update(self.commentable_id.clone(), self.current_comment_id().clone(), Comment::count_reaction_update(&self.params.reaction_type, -1))
+      .commit(self.db.as_ref());
+    match deleted {
+      Ok(()) => Ok(self),
+      Err(DbError::ConditionFailed) => Err(bad_request("Could not delete reaction.")),
+      Err(err) => Err(internal_server_error(err)),
+    }
+  }
+
+  pub fn update_snapshot(&mut self) -> &mut Self {
+    let (comment_id, reaction_type) = (self.current_comment_id(), &self.params.reaction_type);
+    if let Err(err) = Snapshot::update(self.db.as_ref(), self.commentable_id.clone(), |thread| thread.count_reaction(comment_id, reaction_type, -1)) {
+      eprintln!("Error updating the snapshot of {}: {}", self.commentable_id, err);
+    }
+    self
+  }
+
+  pub fn notify(&mut self) -> &mut Self {
+    let event = Event::ReactionRemoved {
+      comment_id: self.current_comment_id(),
+      reaction_type: &self.params.reaction_type,
+      user_id: self.current_user_id(),
+    };
+    push::broadcast(self.db.as_ref(), self.push.as_ref(), &self.commentable_id, &event);
+    self
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(""))
+  }
+}
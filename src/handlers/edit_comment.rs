@@ -0,0 +1,267 @@
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use maplit::hashmap;
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::push::{self, Event, Push};
+use crate::storage::{self, Condition, Storage, Update};
+use crate::utils::db::{attribute_value, sortable_timestamp, DynamoDbModel, CommentableId, DbError, Transaction, VERSION_FIELD_NAME};
+use crate::utils::config;
+use crate::utils::http::{
+  bad_request,
+  conflict,
+  error_code,
+  forbidden,
+  internal_server_error,
+  missing_request_param,
+  missing_path_param,
+  not_found,
+  ok,
+  HttpError,
+};
+use crate::utils::current_user::CurrentUser;
+use crate::utils::current_comment::CurrentComment;
+use crate::utils::current_commentable::CurrentCommentable;
+use crate::models::{
+  user::{AuthToken, User},
+  comment::{CommentId, Comment},
+  commentable::Commentable,
+  revision::Revision,
+  snapshot::Snapshot,
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+  comment_id: CommentId,
+  body: String,
+  // The version of the comment the edit is based on (optional).
+  // Edits of comments changed in the meantime are rejected with 409 Conflict.
+  version: Option<i64>,
+}
+
+pub struct EditComment {
+  db: Arc<dyn Storage>,
+  push: Arc<dyn Push>,
+  commentable_id: CommentableId,
+  params: Params,
+  current_user: Option<User>,
+  current_comment: Option<Comment>,
+  // The revision & the edited comment share the same time
+  edited_at: DateTime<Utc>,
+  current_commentable: Option<Commentable>,
+}
+
+impl CurrentUser for EditComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl CurrentComment for EditComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn comment_id(&self) -> CommentId {
+    self.params.comment_id.clone()
+  }
+
+  fn set_current_comment(&mut self, comment: Comment) {
+    self.current_comment = Some(comment);
+  }
+}
+
+impl CurrentCommentable for EditComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn current_commentable(&self) -> Option<&Commentable> {
+    self.current_commentable.as_ref()
+  }
+
+  fn set_current_commentable(&mut self, commentable: Option<Commentable>) {
+    self.current_commentable = commentable;
+  }
+}
+
+impl EditComment {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate_params()?
+        .fetch_current_user()?
+        .fetch_current_comment()?
+        .fetch_current_commentable()?
+        .check_not_locked()?
+        .authorize()?
+        .check_edit_policy()?
+        .check_version()?
+        .update()?
+        .update_snapshot()
+        .notify()
+        .serialize()
+    } else {
+      Err(bad_request(missing_path_param("id")))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        push: push::connect().map_err(internal_server_error)?,
+        current_comment: None,
+        current_user: None,
+        edited_at: Utc::now(),
+        current_commentable: None,
+        commentable_id,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request(missing_request_param("auth_token")))
+    } else if self.params.comment_id.trim().is_empty() {
+      Err(bad_request(missing_request_param("comment_id")))
+    } else if self.params.body.trim().is_empty() {
+      Err(bad_request(missing_request_param("body")))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn authorize(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwraps are safe because presence is guaranteed by calls
+    // to #fetch_current_user and #fetch_current_comment
+    if self.current_comment.as_ref().unwrap().user_id == Some(self.current_user.as_ref().unwrap().id.clone()) {
+      Ok(self)
+    } else {
+      Err(forbidden("Cannot update comment"))
+    }
+  }
+
+  // Site admins can edit their comments regardless of the policy
+  pub fn check_edit_policy(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwraps are safe because presence is guaranteed by calls
+    // to #fetch_current_user and #fetch_current_comment
+    if self.current_user.as_ref().unwrap().is_admin() {
+      return Ok(self);
+    }
+    let comment = self.current_comment.as_ref().unwrap();
+    if let Some(minutes) = config::edit_window_minutes() {
+      if comment.created_at + Duration::minutes(minutes) < self.edited_at {
+        return Err(forbidden(error_code(
+          "edit_window_expired",
+          &format!("Comments can only be edited within {} minutes.", minutes),
+        )));
+      }
+    }
+    if config::edit_without_replies() && comment.has_replies(self.db.as_ref()).map_err(internal_server_error)? {
+      return Err(forbidden(error_code("comment_has_replies", "Comments with replies can't be edited.")));
+    }
+    Ok(self)
+  }
+
+  // Rejects outdated edits early, #update checks the version again when saving the edit
+  pub fn check_version(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwrap is safe because presence is guaranteed by #fetch_current_comment
+    let comment = self.current_comment.as_ref().unwrap();
+    match self.params.version {
+      Some(version) if version != comment.version => Err(version_conflict(comment)),
+      _ => Ok(self),
+    }
+  }
+
+  // The edit is saved along with the revision of the replaced body, both conditioned on the version
+  // of the fetched comment. Comments changed in the meantime are never overwritten, not even by edits
+  // without a version, because the edit policy was only checked against the fetched comment.
+  pub fn update(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwrap is safe because presence is guaranteed by #fetch_current_comment
+    let comment = self.current_comment.as_ref().unwrap();
+    let update = Update::set(hashmap!{
+      String::from("body") => attribute_value(self.params.body.clone()),
+      String::from("edited_at") => attribute_value(self.edited_at.to_rfc3339()),
+      String::from("changed_at") => attribute_value(sortable_timestamp(self.edited_at)),
+    })
+      .add("edit_count", 1)
+      .add(VERSION_FIELD_NAME, 1)
+      .condition(Condition::NumberEquals(VERSION_FIELD_NAME.to_string(), comment.version));
+    let saved = Transaction::default()
+      .put(Revision::attributes_for_comment(comment, self.edited_at), Some(Condition::NotExists))
+      .update(self.commentable_id.clone(), self.comment_id(), update)
+      .commit(self.db.as_ref());
+
+    match saved {
+      Ok(()) => {
+        let comment = self.current_comment.as_mut().unwrap();
+        comment.body = self.params.body.clone();
+        comment.edited_at = Some(self.edited_at);
+        comment.edit_count += 1;
+        comment.version += 1;
+        Ok(self)
+      },
+      // The comment was changed after it was fetched
+      Err(DbError::ConditionFailed) => match Comment::find(self.db.as_ref(), self.commentable_id.clone(), self.comment_id()) {
+        Ok(Some(changed)) => Err(version_conflict(&changed)),
+        Ok(None) => Err(not_found("Comment not found")),
+        Err(err) => Err(internal_server_error(err)),
+      },
+      Err(err) => Err(internal_server_error(err)),
+    }
+  }
+
+  pub fn update_snapshot(&mut self) -> &mut Self {
+    // The unwrap is safe because the updated comment is set in #update
+    let comment = self.current_comment.as_ref().unwrap();
+    if let Err(err) = Snapshot::update(self.db.as_ref(), self.commentable_id.clone(), |thread| thread.edit_comment(comment)) {
+      eprintln!("Error updating the snapshot of {}: {}", self.commentable_id, err);
+    }
+    self
+  }
+
+  pub fn notify(&mut self) -> &mut Self {
+    // The unwrap is safe because the updated comment is set in #update
+    let comment = self.current_comment.as_ref().unwrap();
+    push::broadcast(self.db.as_ref(), self.push.as_ref(), &self.commentable_id, &Event::CommentEdited { comment });
+    self
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(serde_json::to_string(&self.current_comment).unwrap()))
+  }
+}
+
+// Sends the current body & version, so that clients can merge the edit and retry
+fn version_conflict(comment: &Comment) -> HttpError {
+  conflict(json!({
+    "error": "version_conflict",
+    "message": "The comment has been changed since it was loaded.",
+    "body": comment.body,
+    "version": comment.version,
+  }))
+}
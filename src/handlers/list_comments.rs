@@ -0,0 +1,775 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::mem;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::{Serialize, Deserialize};
+
+use crate::storage::{self, Storage};
+use crate::models::comment::{Comment as CommentRecord, CommentId};
+use crate::models::commentable::{Commentable, CommentableStatus};
+use crate::models::user::{AuthToken, User, UserId};
+use crate::models::reaction::{Reaction, ReactionType};
+use crate::models::snapshot::{Snapshot, Thread};
+use crate::models::tombstone::{Tombstone, TOMBSTONE_TTL_DAYS};
+use crate::utils::config;
+use crate::utils::current_user::CurrentUser;
+use crate::utils::current_commentable::CurrentCommentable;
+use crate::utils::db::{sortable_timestamp, CommentableId, DynamoDbModel, DynamoDbListableModel};
+use crate::utils::http::{ok, bad_request, gone, internal_server_error, HttpError};
+
+type ReactionCount = i64;
+
+static MAX_LIMIT: usize = 100;
+// Sync tokens overlap with the previous sync by a few seconds, to account for clock skew between
+// Lambda instances and eventually consistent reads (clients may receive the same change twice)
+static SYNC_OVERLAP_SECONDS: i64 = 10;
+
+#[derive(Debug)]
+struct Comment {
+  id: CommentId,
+  body: String,
+  user_id: Option<UserId>,
+  replies_to: Option<CommentId>,
+  replies: Vec<CommentId>,
+  replies_cursor: Option<String>,
+  reactions: HashMap<ReactionType, ReactionCount>,
+  user_reactions: Vec<ReactionType>,
+  edited_at: Option<String>,
+  version: i64,
+  created_at: String,
+}
+
+#[derive(Serialize, Clone)]
+struct UserJson {
+  id: UserId,
+  name: String,
+  picture_url: String,
+}
+
+// Replies are appended to the serialized comment (see #serialize_comments)
+#[derive(Serialize)]
+struct CommentJson {
+  id: CommentId,
+  body: String,
+  user: Option<UserJson>,
+  // The comment this one replies to, which differs from the parent comment for flattened replies
+  replies_to: Option<CommentId>,
+  pinned: bool,
+  has_more_replies: bool,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  replies_cursor: Option<String>,
+  reactions: HashMap<ReactionType, ReactionCount>,
+  user_reactions: Vec<ReactionType>,
+  edited_at: Option<String>,
+  version: i64,
+  created_at: String,
+}
+
+// An opaque pagination cursor, pointing at the last comment of the previous page
+// (or at the position of the next page for rankings, which can't be paginated by ID)
+#[derive(Serialize, Deserialize, Default)]
+struct Cursor {
+  #[serde(skip_serializing_if = "Option::is_none")]
+  after: Option<CommentId>,
+  #[serde(skip_serializing_if = "Option::is_none")]
+  offset: Option<usize>,
+}
+
+impl Cursor {
+  fn after(id: CommentId) -> String {
+    Cursor { after: Some(id), ..Default::default() }.encode()
+  }
+
+  fn offset(offset: usize) -> String {
+    Cursor { offset: Some(offset), ..Default::default() }.encode()
+  }
+
+  fn encode(&self) -> String {
+    // Serializing a struct with optional String & usize fields can't fail
+    let json = serde_json::to_string(self).unwrap();
+    base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+  }
+
+  fn decode(cursor: &str) -> Option<Self> {
+    base64::decode_config(cursor, base64::URL_SAFE_NO_PAD).ok()
+      .and_then(|json| serde_json::from_slice::<Cursor>(&json).ok())
+  }
+}
+
+// An opaque token pointing at the time of the previous sync
+#[derive(Serialize, Deserialize)]
+struct SyncToken {
+  since: String,
+}
+
+impl SyncToken {
+  fn encode(since: DateTime<Utc>) -> String {
+    // Serializing a struct with a single String field can't fail
+    let json = serde_json::to_string(&SyncToken { since: sortable_timestamp(since) }).unwrap();
+    base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+  }
+
+  fn decode(token: &str) -> Option<DateTime<Utc>> {
+    base64::decode_config(token, base64::URL_SAFE_NO_PAD).ok()
+      .and_then(|json| serde_json::from_slice::<SyncToken>(&json).ok())
+      .and_then(|token| DateTime::parse_from_rfc3339(&token.since).ok())
+      .map(|since| since.with_timezone(&Utc))
+  }
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "snake_case")]
+enum Sort {
+  #[default]
+  Oldest,
+  Newest,
+  // By the total number of reactions (or the number of reactions of a given type)
+  Top,
+  // By the number of direct replies
+  MostReplied,
+}
+
+impl Sort {
+  // Rankings need every comment of the commentable to be loaded
+  fn is_ranking(self) -> bool {
+    self == Sort::Top || self == Sort::MostReplied
+  }
+}
+
+#[derive(Deserialize, Default)]
+struct Params {
+  auth_token: Option<AuthToken>,
+  // Max number of top-level comments (or replies, see below) to return, all of them if missing
+  limit: Option<usize>,
+  // The cursor returned with the previous page
+  cursor: Option<String>,
+  // Page over replies to this comment instead of top-level comments
+  replies_to: Option<CommentId>,
+  // Max number of replies to return for each comment, all of them if missing
+  replies_limit: Option<usize>,
+  #[serde(default)]
+  sort: Sort,
+  // Rank by the number of reactions of this type when sorting by "top"
+  reaction_type: Option<ReactionType>,
+  // Sort replies in the same way as top-level comments (they're listed oldest first otherwise)
+  #[serde(default)]
+  sort_replies: bool,
+  // Only return comments changed after this time (RFC 3339) or since the sync with the given token
+  since: Option<String>,
+  sync_token: Option<String>,
+}
+
+pub struct ListComments {
+  db: Arc<dyn Storage>,
+  params: Params,
+  commentable_id: CommentableId,
+  // IDs of the returned top-level comments (or replies if `replies_to` is present), in order
+  roots: Vec<CommentId>,
+  // IDs of pinned comments, which are listed before other top-level comments on the first page
+  pinned: Vec<CommentId>,
+  cursor: Option<String>,
+  // Set for incremental syncs only
+  since: Option<DateTime<Utc>>,
+  deleted: Vec<CommentId>,
+  sync_token: String,
+  comments: BTreeMap<CommentId, Comment>,
+  users: HashMap<UserId, UserJson>,
+  // Comments & users listed from a snapshot don't have to be fetched
+  from_snapshot: bool,
+  // IDs of every reply of the commentable, by the comment they reply to (see #fetch_replies)
+  reply_ids: Option<HashMap<CommentId, Vec<CommentId>>>,
+  current_user: Option<User>,
+  current_commentable: Option<Commentable>,
+}
+
+impl CurrentUser for ListComments {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<String> {
+    self.params.auth_token.clone()
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl CurrentCommentable for ListComments {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn current_commentable(&self) -> Option<&Commentable> {
+    self.current_commentable.as_ref()
+  }
+
+  fn set_current_commentable(&mut self, commentable: Option<Commentable>) {
+    self.current_commentable = commentable;
+  }
+}
+
+impl ListComments {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate()?
+        .try_fetch_current_user()
+        .fetch_current_commentable()?
+        .build_missing_commentable()?
+        .fetch_comments()?
+        .fetch_pinned_comments()?
+        .fetch_user_reactions()?
+        .sort_comments()
+        .fetch_users()?
+        .serialize()
+    } else {
+      Err(bad_request("Invalid path parameters: 'id' is required."))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(params) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        commentable_id,
+        roots: vec![],
+        pinned: vec![],
+        cursor: None,
+        since: None,
+        deleted: vec![],
+        sync_token: SyncToken::encode(Utc::now() - Duration::seconds(SYNC_OVERLAP_SECONDS)),
+        comments: BTreeMap::new(),
+        users: HashMap::new(),
+        from_snapshot: false,
+        reply_ids: None,
+        current_user: None,
+        current_commentable: None,
+        params: params.unwrap_or_default(),
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  // Commentables without metadata are closed automatically too, based on their first comment
+  pub fn build_missing_commentable(&mut self) -> Result<&mut Self, HttpError> {
+    if self.current_commentable.is_none() && config::auto_close_days().is_some() {
+      let commentable = Commentable::build_for_commentable(self.db.as_ref(), self.commentable_id.clone())
+        .map_err(internal_server_error)?;
+      self.current_commentable = Some(commentable);
+    }
+    Ok(self)
+  }
+
+  pub fn validate(&mut self) -> Result<&mut Self, HttpError> {
+    for limit in self.params.limit.iter().chain(self.params.replies_limit.iter()) {
+      if *limit == 0 || *limit > MAX_LIMIT {
+        return Err(bad_request(format!("Invalid parameters: limits have to be between 1 and {}.", MAX_LIMIT)));
+      }
+    }
+    if self.params.since.is_some() || self.params.sync_token.is_some() {
+      return self.validate_since();
+    }
+    if let Some(cursor) = self.params.cursor.as_ref() {
+      let is_valid = match Cursor::decode(cursor) {
+        Some(cursor) if self.roots_sort().is_ranking() => cursor.offset.is_some(),
+        Some(cursor) => cursor.after.is_some(),
+        None => false,
+      };
+      if !is_valid {
+        return Err(bad_request("Invalid parameters: 'cursor' is malformed."));
+      }
+    }
+    Ok(self)
+  }
+
+  fn validate_since(&mut self) -> Result<&mut Self, HttpError> {
+    if self.is_paginated() {
+      return Err(bad_request("Invalid parameters: 'since' and 'sync_token' can't be paginated."));
+    }
+    let since = match (self.params.since.as_ref(), self.params.sync_token.as_ref()) {
+      (Some(since), None) => DateTime::parse_from_rfc3339(since).ok().map(|since| since.with_timezone(&Utc)),
+      (None, Some(token)) => SyncToken::decode(token),
+      _ => None,
+    }.ok_or_else(|| bad_request("Invalid parameters: 'since' or 'sync_token' is malformed."))?;
+
+    // Deleted comments can't be synced once their tombstones expire
+    if since < Utc::now() - Duration::days(TOMBSTONE_TTL_DAYS) {
+      return Err(gone("Sync token expired, all comments have to be listed again."));
+    }
+    self.since = Some(since);
+    Ok(self)
+  }
+
+  fn is_sync(&self) -> bool {
+    self.since.is_some()
+  }
+
+  // Without any pagination parameters, the whole partition is listed in a single query
+  fn is_paginated(&self) -> bool {
+    self.params.limit.is_some() || self.params.cursor.is_some() ||
+      self.params.replies_to.is_some() || self.params.replies_limit.is_some()
+  }
+
+  fn loads_all(&self) -> bool {
+    !self.is_sync() && (!self.is_paginated() || self.roots_sort().is_ranking())
+  }
+
+  fn replies_sort(&self) -> Sort {
+    if self.params.sort_replies { self.params.sort } else { Sort::Oldest }
+  }
+
+  // Replies paginated with `replies_to` are sorted like any other replies
+  fn roots_sort(&self) -> Sort {
+    if self.params.replies_to.is_some() { self.replies_sort() } else { self.params.sort }
+  }
+
+  pub fn fetch_comments(&mut self) -> Result<&mut Self, HttpError> {
+    if let Some(since) = self.since {
+      return self.fetch_changes(sortable_timestamp(since));
+    }
+    if self.loads_all() && config::snapshots_enabled() {
+      if let Some(thread) = self.fetch_snapshot()? {
+        return self.parse_thread(thread);
+      }
+    }
+    if self.loads_all() {
+      let comments = CommentRecord::list(self.db.as_ref(), self.commentable_id.clone()).map_err(internal_server_error)?;
+      self.roots = comments
+        .iter()
+        .filter(|comment| comment.replies_to == self.params.replies_to)
+        .map(|comment| comment.id.clone())
+        .collect();
+      return self.parse_comments(comments);
+    }
+
+    let start_after = self.params.cursor.as_ref().and_then(|cursor| Cursor::decode(cursor)).and_then(|cursor| cursor.after);
+    let newest_first = self.roots_sort() == Sort::Newest;
+    let (page, last_evaluated_id) = match self.params.replies_to.clone() {
+      Some(comment_id) => CommentRecord::replies_page(
+        self.db.as_ref(), self.commentable_id.clone(), comment_id, self.params.limit, start_after, newest_first,
+      ),
+      None => CommentRecord::top_level_page(
+        self.db.as_ref(), self.commentable_id.clone(), self.params.limit, start_after, newest_first,
+      ),
+    }.map_err(internal_server_error)?;
+
+    self.roots = page.iter().map(|comment| comment.id.clone()).collect();
+    self.cursor = last_evaluated_id.map(Cursor::after);
+    self.parse_comments(page)?;
+    self.fetch_replies(self.roots.clone())
+  }
+
+  // Loads replies (and replies to replies) of the given comments. Replies are picked level by level from the IDs
+  // of every reply of the commentable, so that only the listed ones have to be fetched, with a single batch read.
+  fn fetch_replies(&mut self, mut parent_ids: Vec<CommentId>) -> Result<&mut Self, HttpError> {
+    if parent_ids.is_empty() {
+      return Ok(self);
+    }
+    if self.reply_ids.is_none() {
+      let reply_ids = CommentRecord::reply_ids(self.db.as_ref(), self.commentable_id.clone()).map_err(internal_server_error)?;
+      self.reply_ids = Some(reply_ids);
+    }
+    let all_reply_ids = self.reply_ids.as_ref().unwrap(); // safe unwrap
+
+    let mut keys = vec![];
+    let mut cursors = vec![];
+    while !parent_ids.is_empty() {
+      let mut next_parent_ids = vec![];
+      for parent_id in parent_ids {
+        let mut reply_ids = all_reply_ids.get(&parent_id).cloned().unwrap_or_default();
+        if self.replies_sort() == Sort::Newest {
+          reply_ids.reverse();
+        }
+        if let Some(limit) = self.params.replies_limit.filter(|limit| reply_ids.len() > *limit) {
+          reply_ids.truncate(limit);
+          cursors.push((parent_id, Cursor::after(reply_ids[limit - 1].clone())));
+        }
+        next_parent_ids.extend(reply_ids);
+      }
+      keys.extend(next_parent_ids.iter().map(|id| (self.commentable_id.clone(), id.clone())));
+      parent_ids = next_parent_ids;
+    }
+
+    let replies = CommentRecord::batch_find(self.db.as_ref(), keys).map_err(internal_server_error)?;
+    self.parse_comments(replies)?;
+    for (parent_id, cursor) in cursors {
+      if let Some(parent) = self.comments.get_mut(&parent_id) {
+        parent.replies_cursor = Some(cursor);
+      }
+    }
+    Ok(self)
+  }
+
+  // Pinned comments come first on the first page of top-level comments, so paginated lists load them separately
+  pub fn fetch_pinned_comments(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.replies_to.is_some() {
+      return Ok(self);
+    }
+    self.pinned = self.current_commentable.as_ref()
+      .map(|commentable| commentable.pinned_comment_ids.clone())
+      .unwrap_or_default();
+    if self.loads_all() || self.is_sync() || self.params.cursor.is_some() {
+      return Ok(self);
+    }
+
+    let keys = self.pinned
+      .iter()
+      .filter(|id| !self.comments.contains_key(*id))
+      .map(|id| (self.commentable_id.clone(), id.clone()))
+      .collect();
+    let comments = CommentRecord::batch_find(self.db.as_ref(), keys).map_err(internal_server_error)?;
+    let ids = comments.iter().map(|comment| comment.id.clone()).collect();
+    self.parse_comments(comments)?;
+    self.fetch_replies(ids)
+  }
+
+  // Lists every comment with a single read, building the snapshot first if it doesn't exist yet.
+  // This is the only write made while listing: the missing snapshot is saved (conditioned on not existing,
+  // see Snapshot#rebuild), so only the first listing after enabling snapshots pays for building it.
+  // Returns None for threads too large to be snapshotted, which are listed from comments instead.
+  fn fetch_snapshot(&self) -> Result<Option<Thread>, HttpError> {
+    let snapshot = Snapshot::find_for_commentable(self.db.as_ref(), self.commentable_id.clone())
+      .map_err(internal_server_error)?;
+    match snapshot {
+      Some(snapshot) => Ok(snapshot.thread),
+      None => Snapshot::rebuild(self.db.as_ref(), self.commentable_id.clone()).map_err(internal_server_error),
+    }
+  }
+
+  // Changed comments are returned as a flat list, clients can find their place using `replies_to`
+  fn fetch_changes(&mut self, since: String) -> Result<&mut Self, HttpError> {
+    let comments = CommentRecord::changed_since(self.db.as_ref(), self.commentable_id.clone(), since.clone())
+      .map_err(internal_server_error)?;
+    self.deleted = Tombstone::list_since(self.db.as_ref(), self.commentable_id.clone(), since)
+      .map_err(internal_server_error)?
+      .into_iter()
+      .map(|tombstone| tombstone.deleted_id)
+      .collect();
+    self.roots = comments.iter().map(|comment| comment.id.clone()).collect();
+    self.parse_comments(comments)
+  }
+
+  // Reaction counts are stored with comments, so only reactions of the current user have to be fetched
+  pub fn fetch_user_reactions(&mut self) -> Result<&mut Self, HttpError> {
+    if let Some(current_user) = &self.current_user {
+      match Reaction::list_for_user(self.db.as_ref(), self.commentable_id.clone(), current_user.id.clone()) {
+        Ok(reactions) => self.parse_user_reactions(reactions),
+        Err(err) => Err(internal_server_error(err)),
+      }
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn sort_comments(&mut self) -> &mut Self {
+    if self.is_sync() {
+      return self;
+    }
+    self.link_replies();
+
+    let mut roots = mem::take(&mut self.roots);
+    roots.retain(|id| !self.pinned.contains(id));
+    self.sort_ids(&mut roots, self.roots_sort());
+    let replies_sort = self.replies_sort();
+    let ids = self.comments.keys().cloned().collect::<Vec<CommentId>>();
+    for id in ids {
+      let mut replies = mem::take(&mut self.comments.get_mut(&id).unwrap().replies); // safe unwrap
+      self.sort_ids(&mut replies, replies_sort);
+      self.comments.get_mut(&id).unwrap().replies = replies; // safe unwrap
+    }
+    self.roots = roots;
+
+    // Rankings are paginated after sorting every comment
+    if self.loads_all() && self.is_paginated() {
+      self.paginate_loaded();
+    }
+    if self.params.cursor.is_none() {
+      let mut pinned = self.pinned.iter().filter(|id| self.comments.contains_key(*id)).cloned().collect::<Vec<CommentId>>();
+      self.sort_ids(&mut pinned, self.roots_sort());
+      pinned.append(&mut self.roots);
+      self.roots = pinned;
+    }
+    if let Some(max_depth) = config::max_depth() {
+      self.flatten_replies(max_depth);
+    }
+    self
+  }
+
+  // Moves replies nested deeper than `max_depth` levels (counting from the listed comments)
+  // to the replies of their ancestor at the last level that can still have replies
+  fn flatten_replies(&mut self, max_depth: usize) {
+    let replies_sort = self.replies_sort();
+    let mut pending = self.roots.iter().map(|id| (id.clone(), 1)).collect::<Vec<(CommentId, usize)>>();
+
+    while let Some((id, depth)) = pending.pop() {
+      let replies = match self.comments.get(&id) {
+        Some(comment) => comment.replies.clone(),
+        None => continue,
+      };
+      if depth < max_depth {
+        pending.extend(replies.into_iter().map(|reply_id| (reply_id, depth + 1)));
+        continue;
+      }
+
+      let mut descendants = vec![];
+      let mut nested = replies;
+      while let Some(reply_id) = nested.pop() {
+        if let Some(reply) = self.comments.get_mut(&reply_id) {
+          nested.append(&mut reply.replies);
+        }
+        descendants.push(reply_id);
+      }
+      self.sort_ids(&mut descendants, replies_sort);
+      self.comments.get_mut(&id).unwrap().replies = descendants; // safe unwrap
+    }
+  }
+
+  fn paginate_loaded(&mut self) {
+    let offset = self.params.cursor.as_ref()
+      .and_then(|cursor| Cursor::decode(cursor))
+      .and_then(|cursor| cursor.offset)
+      .unwrap_or(0);
+    let limit = self.params.limit.unwrap_or(usize::MAX);
+    if self.roots.len().saturating_sub(offset) > limit {
+      self.cursor = Some(Cursor::offset(offset + limit));
+    }
+    self.roots = self.roots.iter().skip(offset).take(limit).cloned().collect();
+
+    if let Some(replies_limit) = self.params.replies_limit {
+      let is_ranking = self.replies_sort().is_ranking();
+      for comment in self.comments.values_mut() {
+        if comment.replies.len() > replies_limit {
+          comment.replies.truncate(replies_limit);
+          comment.replies_cursor = Some(if is_ranking {
+            Cursor::offset(replies_limit)
+          } else {
+            Cursor::after(comment.replies[replies_limit - 1].clone())
+          });
+        }
+      }
+    }
+
+    // Drop comments that didn't make it to the current page (pinned comments are added to the first one later)
+    let mut visible = BTreeMap::new();
+    let mut pending = self.roots.iter().chain(self.pinned.iter()).cloned().collect::<Vec<CommentId>>();
+    while let Some(id) = pending.pop() {
+      if let Some(comment) = self.comments.remove(&id) {
+        pending.extend(comment.replies.iter().cloned());
+        visible.insert(id, comment);
+      }
+    }
+    self.comments = visible;
+  }
+
+  fn sort_ids(&self, ids: &mut [CommentId], sort: Sort) {
+    match sort {
+      Sort::Oldest => ids.sort(),
+      Sort::Newest => ids.sort_by(|a, b| b.cmp(a)),
+      Sort::Top | Sort::MostReplied => {
+        // Highest score first, oldest first among equal scores
+        ids.sort_by_key(|id| (Reverse(self.score(id, sort)), id.clone()));
+      },
+    }
+  }
+
+  fn score(&self, id: &CommentId, sort: Sort) -> i64 {
+    let comment = match self.comments.get(id) {
+      Some(comment) => comment,
+      None => return 0,
+    };
+    match (sort, self.params.reaction_type.as_ref()) {
+      (Sort::MostReplied, _) => comment.replies.len() as i64,
+      (_, Some(reaction_type)) => comment.reactions.get(reaction_type).cloned().unwrap_or(0),
+      (_, None) => comment.reactions.values().sum(),
+    }
+  }
+
+  pub fn fetch_users(&mut self) -> Result<&mut Self, HttpError> {
+    if self.from_snapshot {
+      return Ok(self);
+    }
+    let user_ids = self.comments.values().filter_map(|comment| comment.user_id.as_ref()).collect();
+    match User::batch_get(self.db.as_ref(), user_ids) {
+      Ok(users) => self.parse_users(users),
+      Err(err) => Err(internal_server_error(err)),
+    }
+  }
+
+  pub fn serialize(&mut self) -> Result<Response<Body>, HttpError> {
+    let comments = self.serialize_comments()?;
+    let cursor = serde_json::to_string(&self.cursor).map_err(internal_server_error)?;
+    let sync_token = serde_json::to_string(&self.sync_token).map_err(internal_server_error)?;
+    // Commentables without metadata are open
+    let status = self.current_commentable.as_ref().map_or(CommentableStatus::Open, |commentable| commentable.status);
+    let status = serde_json::to_string(&status).map_err(internal_server_error)?;
+    let auto_closed = self.current_commentable.as_ref().filter(|commentable| commentable.is_auto_closed()).is_some();
+
+    if self.is_sync() {
+      let deleted = serde_json::to_string(&self.deleted).map_err(internal_server_error)?;
+      Ok(ok(format!(
+        "{{\"comments\":{},\"deleted\":{},\"status\":{},\"auto_closed\":{},\"sync_token\":{}}}",
+        comments, deleted, status, auto_closed, sync_token,
+      )))
+    } else {
+      Ok(ok(format!(
+        "{{\"comments\":{},\"cursor\":{},\"status\":{},\"auto_closed\":{},\"sync_token\":{}}}",
+        comments, cursor, status, auto_closed, sync_token,
+      )))
+    }
+  }
+
+  fn parse_comments(&mut self, comments: Vec<CommentRecord>) -> Result<&mut Self, HttpError> {
+    // IDs created within the same millisecond can sort a reply before its parent
+    let ids = comments.iter().map(|comment| comment.id.clone()).collect::<HashSet<CommentId>>();
+    for comment in comments {
+      if let Some(parent_id) = comment.replies_to.as_ref() {
+        // Orphaned comments are skipped, listing doesn't write (bin/repair-counters removes them)
+        if self.loads_all() && !ids.contains(parent_id) {
+          continue;
+        }
+      }
+      self.comments.insert(comment.id.clone(), Comment {
+        id: comment.id,
+        user_id: comment.user_id,
+        body: comment.body,
+        replies_to: comment.replies_to,
+        replies: vec![],
+        replies_cursor: None,
+        reactions: comment.reaction_counts,
+        user_reactions: vec![],
+        edited_at: comment.edited_at.map(|edited_at| edited_at.to_string()),
+        version: comment.version,
+        created_at: comment.created_at.to_string(),
+      });
+    }
+    Ok(self)
+  }
+
+  fn parse_thread(&mut self, thread: Thread) -> Result<&mut Self, HttpError> {
+    for (id, comment) in thread.comments {
+      if comment.replies_to == self.params.replies_to {
+        self.roots.push(id.clone());
+      }
+      self.comments.insert(id.clone(), Comment {
+        id,
+        user_id: comment.user_id,
+        body: comment.body,
+        replies_to: comment.replies_to,
+        replies: vec![],
+        replies_cursor: None,
+        reactions: comment.reaction_counts,
+        user_reactions: vec![],
+        edited_at: comment.edited_at,
+        version: comment.version,
+        created_at: comment.created_at,
+      });
+    }
+    self.users = thread.users
+      .into_iter()
+      .map(|(id, user)| (id.clone(), UserJson { id, name: user.name, picture_url: user.picture_url }))
+      .collect();
+    self.from_snapshot = true;
+    Ok(self)
+  }
+
+  // Attach replies to their parents
+  fn link_replies(&mut self) {
+    let replies = self.comments
+      .values()
+      .filter_map(|comment| comment.replies_to.clone().map(|parent_id| (parent_id, comment.id.clone())))
+      .collect::<Vec<(CommentId, CommentId)>>();
+
+    for (parent_id, reply_id) in replies {
+      if let Some(parent) = self.comments.get_mut(&parent_id) {
+        parent.replies.push(reply_id);
+      }
+    }
+  }
+
+  fn parse_users(&mut self, mut users: Vec<User>) -> Result<&mut Self, HttpError> {
+    self.users = users
+      .drain(..)
+      .map(|user| (user.id.clone(), UserJson { id: user.id, name: user.name, picture_url: user.picture_url }))
+      .collect::<HashMap<UserId, UserJson>>();
+    Ok(self)
+  }
+
+  fn parse_user_reactions(&mut self, reactions: Vec<Reaction>) -> Result<&mut Self, HttpError> {
+    for reaction in reactions {
+      if let Some(comment) = self.comments.get_mut(&reaction.comment_id) {
+        comment.user_reactions.push(reaction.reaction_type);
+      }
+    }
+    Ok(self)
+  }
+
+  // Serializes the comment tree without recursion, so that deeply nested threads can't overflow the stack
+  fn serialize_comments(&self) -> Result<String, HttpError> {
+    let mut json = String::from("[");
+    // Replies left to serialize (in reverse order) for each open "replies" array
+    let mut pending = vec![self.roots.iter().rev().collect::<Vec<&CommentId>>()];
+    let mut is_first = true;
+
+    while let Some(replies) = pending.last_mut() {
+      match replies.pop() {
+        Some(id) => {
+          let comment = self.comments.get(id).unwrap(); // safe unwrap
+          let object = serde_json::to_string(&self.serialize_comment(comment)?).map_err(internal_server_error)?;
+          if !is_first {
+            json.push(',');
+          }
+          // Leave the object open and add its replies before closing it
+          json.push_str(&object[..object.len() - 1]);
+          json.push_str(",\"replies\":[");
+          pending.push(comment.replies.iter().rev().collect());
+          is_first = true;
+        },
+        None => {
+          pending.pop();
+          json.push_str(if pending.is_empty() { "]" } else { "]}" });
+          is_first = false;
+        },
+      }
+    }
+
+    Ok(json)
+  }
+
+  fn serialize_comment(&self, comment: &Comment) -> Result<CommentJson, HttpError> {
+    Ok(CommentJson {
+      id: comment.id.clone(),
+      body: comment.body.clone(),
+      user: match comment.user_id.as_ref() {
+        Some(user_id) =>
+          Some(self.users
+            .get(user_id)
+            .ok_or(internal_server_error(format!(
+              "Couldn't find a user with ID: {}. Reference in comment: {:?}",
+              &user_id,
+              &comment,
+            )))?
+            .clone()),
+        None => None,
+      },
+      replies_to: comment.replies_to.clone(),
+      pinned: self.pinned.contains(&comment.id),
+      has_more_replies: comment.replies_cursor.is_some(),
+      replies_cursor: comment.replies_cursor.clone(),
+      reactions: comment.reactions.clone(),
+      user_reactions: comment.user_reactions.clone(),
+      edited_at: comment.edited_at.clone(),
+      version: comment.version,
+      created_at: comment.created_at.clone(),
+    })
+  }
+}
@@ -0,0 +1,144 @@
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::{Serialize, Deserialize};
+
+use crate::storage::{self, Storage};
+use crate::utils::db::CommentableId;
+use crate::utils::http::{
+  bad_request,
+  forbidden,
+  internal_server_error,
+  missing_path_param,
+  missing_request_param,
+  ok,
+  HttpError,
+};
+use crate::utils::current_user::CurrentUser;
+use crate::utils::current_comment::CurrentComment;
+use crate::models::{
+  user::{AuthToken, User},
+  comment::{CommentId, Comment},
+  revision::Revision,
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+  comment_id: CommentId,
+}
+
+#[derive(Serialize)]
+struct RevisionJson {
+  body: String,
+  // The time of the edit which replaced this body
+  edited_at: String,
+}
+
+// Previous bodies of a comment, visible to its author and to site admins
+pub struct ListRevisions {
+  db: Arc<dyn Storage>,
+  commentable_id: CommentableId,
+  params: Params,
+  current_user: Option<User>,
+  current_comment: Option<Comment>,
+  revisions: Vec<Revision>,
+}
+
+impl CurrentUser for ListRevisions {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl CurrentComment for ListRevisions {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn comment_id(&self) -> CommentId {
+    self.params.comment_id.clone()
+  }
+
+  fn set_current_comment(&mut self, comment: Comment) {
+    self.current_comment = Some(comment);
+  }
+}
+
+impl ListRevisions {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate_params()?
+        .fetch_current_user()?
+        .fetch_current_comment()?
+        .authorize()?
+        .fetch_revisions()?
+        .serialize()
+    } else {
+      Err(bad_request(missing_path_param("id")))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        current_user: None,
+        current_comment: None,
+        revisions: vec![],
+        commentable_id,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request(missing_request_param("auth_token")))
+    } else if self.params.comment_id.trim().is_empty() {
+      Err(bad_request(missing_request_param("comment_id")))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn authorize(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwraps are safe because presence is guaranteed by calls
+    // to #fetch_current_user and #fetch_current_comment
+    let current_user = self.current_user.as_ref().unwrap();
+    if self.current_comment.as_ref().unwrap().user_id.as_ref() == Some(&current_user.id) || current_user.is_admin() {
+      Ok(self)
+    } else {
+      Err(forbidden("Cannot list revisions of the comment"))
+    }
+  }
+
+  pub fn fetch_revisions(&mut self) -> Result<&mut Self, HttpError> {
+    self.revisions = Revision::list_for_comment(self.db.as_ref(), self.commentable_id.clone(), &self.params.comment_id)
+      .map_err(internal_server_error)?;
+    Ok(self)
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    let revisions = self.revisions
+      .iter()
+      .map(|revision| RevisionJson { body: revision.body.clone(), edited_at: revision.created_at.to_string() })
+      .collect::<Vec<RevisionJson>>();
+    Ok(ok(serde_json::to_string(&revisions).map_err(internal_server_error)?))
+  }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::{Serialize, Deserialize};
+
+use crate::storage::{self, Storage};
+use crate::utils::http::{
+  bad_request,
+  internal_server_error,
+  missing_request_param,
+  ok,
+  HttpError,
+};
+use crate::utils::current_user::CurrentUser;
+use crate::models::{
+  session::{Session, SessionId},
+  user::{AuthToken, User},
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+}
+
+#[derive(Serialize)]
+struct SessionJson {
+  id: SessionId,
+  label: String,
+  created_at: String,
+  last_used_at: String,
+  // The session making the request
+  current: bool,
+}
+
+// Devices the current user is signed in with, most recently used first
+pub struct ListSessions {
+  db: Arc<dyn Storage>,
+  params: Params,
+  current_user: Option<User>,
+  sessions: Vec<Session>,
+}
+
+impl CurrentUser for ListSessions {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl ListSessions {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    Self::new(request)?
+      .validate_params()?
+      .fetch_current_user()?
+      .fetch_sessions()?
+      .serialize()
+  }
+
+  pub fn new(request: Request) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        current_user: None,
+        sessions: vec![],
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request(missing_request_param("auth_token")))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn fetch_sessions(&mut self) -> Result<&mut Self, HttpError> {
+    // Sessions which can't be refreshed anymore are as good as logged out
+    self.sessions = Session::list(self.db.as_ref(), self.user_id()?)
+      .map_err(internal_server_error)?
+      .into_iter()
+      .filter(|session| !session.refresh_token_expired())
+      .collect();
+    self.sessions.sort_by_key(|session| std::cmp::Reverse(session.last_used_at));
+    Ok(self)
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    let current_session_id = self.session_id()?;
+    let sessions = self.sessions
+      .iter()
+      .map(|session| SessionJson {
+        id: session.id.clone(),
+        label: session.label.clone(),
+        created_at: session.created_at.to_string(),
+        last_used_at: session.last_used_at.to_string(),
+        current: current_session_id.as_ref() == Some(&session.id),
+      })
+      .collect::<Vec<SessionJson>>();
+    Ok(ok(serde_json::to_string(&sessions).map_err(internal_server_error)?))
+  }
+}
@@ -0,0 +1,91 @@
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::Deserialize;
+
+use crate::storage::{self, Storage};
+use crate::utils::db::DynamoDbModel;
+use crate::utils::http::{
+  bad_request,
+  internal_server_error,
+  missing_request_param,
+  ok,
+  HttpError,
+};
+use crate::utils::current_user::CurrentUser;
+use crate::models::{
+  session::Session,
+  user::{AuthToken, User},
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+}
+
+// Ends the session making the request, other devices stay signed in
+pub struct Logout {
+  db: Arc<dyn Storage>,
+  params: Params,
+  current_user: Option<User>,
+}
+
+impl CurrentUser for Logout {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl Logout {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    Self::new(request)?
+      .validate_params()?
+      .fetch_current_user()?
+      .end_session()?
+      .serialize()
+  }
+
+  pub fn new(request: Request) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        current_user: None,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request(missing_request_param("auth_token")))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn end_session(&mut self) -> Result<&mut Self, HttpError> {
+    match self.session_id()? {
+      Some(session_id) => Session::delete(self.db.as_ref(), self.user_id()?, session_id),
+      // Tokens issued before sessions were introduced are stored with the user
+      None => match self.current_user.as_mut() {
+        Some(user) => user.remove_legacy_tokens(self.db.as_ref()),
+        None => Ok(()),
+      },
+    }.map_err(internal_server_error)?;
+    Ok(self)
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(""))
+  }
+}
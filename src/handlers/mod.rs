@@ -0,0 +1,18 @@
+pub mod auth;
+pub mod refresh_auth;
+pub mod list_sessions;
+pub mod logout;
+pub mod revoke_sessions;
+pub mod options;
+pub mod list_comments;
+pub mod add_comment;
+pub mod edit_comment;
+pub mod delete_comment;
+pub mod add_reaction;
+pub mod delete_reaction;
+pub mod count_comments;
+pub mod update_commentable;
+pub mod pin_comment;
+pub mod list_revisions;
+pub mod websocket;
+pub mod process_cleanups;
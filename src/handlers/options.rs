@@ -0,0 +1,8 @@
+use lambda_http::{Request, Response, Body};
+
+use crate::utils::http::ok;
+
+// Responds to CORS preflight requests, the CORS headers are added by utils::http
+pub fn options(_request: Request) -> Response<Body> {
+  ok("")
+}
@@ -0,0 +1,184 @@
+use std::sync::Arc;
+
+use chrono::Utc;
+use lambda_http::{Request, Response, Body, RequestExt};
+use maplit::hashmap;
+use serde::Deserialize;
+
+use crate::push::{self, Event, Push};
+use crate::storage::{self, Condition, Storage, Update};
+use crate::utils::db::{attribute_value, sortable_timestamp, CommentableId, DbError, DynamoDbModel, VERSION_FIELD_NAME};
+use crate::utils::http::{
+  bad_request,
+  forbidden,
+  internal_server_error,
+  missing_path_param,
+  missing_request_param,
+  not_found,
+  ok,
+  HttpError,
+};
+use crate::utils::current_user::CurrentUser;
+use crate::utils::current_comment::CurrentComment;
+use crate::models::{
+  user::{AuthToken, User},
+  comment::{CommentId, Comment},
+  commentable::Commentable,
+};
+
+fn default_pinned() -> bool {
+  true
+}
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+  comment_id: CommentId,
+  // Set to false to unpin the comment
+  #[serde(default = "default_pinned")]
+  pinned: bool,
+}
+
+// Lets site admins pin top-level comments, which are listed before any other comments
+pub struct PinComment {
+  db: Arc<dyn Storage>,
+  push: Arc<dyn Push>,
+  commentable_id: CommentableId,
+  params: Params,
+  current_user: Option<User>,
+  current_comment: Option<Comment>,
+  commentable: Option<Commentable>,
+}
+
+impl CurrentUser for PinComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl CurrentComment for PinComment {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn commentable_id(&self) -> CommentableId {
+    self.commentable_id.clone()
+  }
+
+  fn comment_id(&self) -> CommentId {
+    self.params.comment_id.clone()
+  }
+
+  fn set_current_comment(&mut self, comment: Comment) {
+    self.current_comment = Some(comment);
+  }
+}
+
+impl PinComment {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate_params()?
+        .fetch_current_user()?
+        .authorize()?
+        .fetch_current_comment()?
+        .check_top_level()?
+        .touch_comment()?
+        .pin()?
+        .notify()
+        .serialize()
+    } else {
+      Err(bad_request(missing_path_param("id")))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        push: push::connect().map_err(internal_server_error)?,
+        current_user: None,
+        current_comment: None,
+        commentable: None,
+        commentable_id,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request(missing_request_param("auth_token")))
+    } else if self.params.comment_id.trim().is_empty() {
+      Err(bad_request(missing_request_param("comment_id")))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn authorize(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwrap is safe because presence is guaranteed by #fetch_current_user
+    if self.current_user.as_ref().unwrap().is_admin() {
+      Ok(self)
+    } else {
+      Err(forbidden("Only admins can pin comments"))
+    }
+  }
+
+  pub fn check_top_level(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwrap is safe because presence is guaranteed by #fetch_current_comment
+    if self.current_comment.as_ref().unwrap().replies_to.is_none() {
+      Ok(self)
+    } else {
+      Err(bad_request("Only top-level comments can be pinned"))
+    }
+  }
+
+  pub fn pin(&mut self) -> Result<&mut Self, HttpError> {
+    match Commentable::pin_comment(self.db.as_ref(), self.commentable_id.clone(), &self.params.comment_id, self.params.pinned) {
+      Ok(commentable) => {
+        self.commentable = Some(commentable);
+        Ok(self)
+      },
+      Err(err) => Err(internal_server_error(err)),
+    }
+  }
+
+  // Lets incremental syncs of comments/list pick up the change.
+  // Done before pinning, so that comments deleted in the meantime aren't pinned (or recreated by the update).
+  pub fn touch_comment(&mut self) -> Result<&mut Self, HttpError> {
+    let touched = Comment::update(
+      self.db.as_ref(),
+      self.commentable_id.clone(),
+      self.params.comment_id.clone(),
+      Update::set(hashmap!{ String::from("changed_at") => attribute_value(sortable_timestamp(Utc::now())) })
+        .add(VERSION_FIELD_NAME, 1)
+        .condition(Condition::Exists),
+    );
+    match touched {
+      Ok(_) => Ok(self),
+      Err(DbError::ConditionFailed) => Err(not_found("Comment not found")),
+      Err(err) => Err(internal_server_error(err)),
+    }
+  }
+
+  pub fn notify(&mut self) -> &mut Self {
+    let event = Event::CommentPinned { comment_id: &self.params.comment_id, pinned: self.params.pinned };
+    push::broadcast(self.db.as_ref(), self.push.as_ref(), &self.commentable_id, &event);
+    self
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(serde_json::to_string(&self.commentable).map_err(internal_server_error)?))
+  }
+}
@@ -0,0 +1,28 @@
+use serde::Serialize;
+
+use crate::models::cleanup::Cleanup;
+use crate::storage::Storage;
+use crate::utils::db::DbError;
+
+#[derive(Serialize)]
+pub struct ProcessedCleanups {
+  pub processed: usize,
+  pub failed: usize,
+}
+
+// Runs the cleanups left queued by comment deletions that failed to remove all reactions & revisions
+// (see models/cleanup.rs). Cleanups which fail again stay queued until the next run.
+pub fn process_cleanups(db: &dyn Storage) -> Result<ProcessedCleanups, DbError> {
+  let cleanups = Cleanup::list_pending(db)?;
+  let mut failed = 0;
+  for cleanup in &cleanups {
+    match cleanup.run(db) {
+      Ok(()) => println!("{}: cleaned up after comment {}", cleanup.commentable_id, cleanup.cleanup_of),
+      Err(err) => {
+        eprintln!("{}: comment {}: {}", cleanup.commentable_id, cleanup.cleanup_of, err);
+        failed += 1;
+      },
+    }
+  }
+  Ok(ProcessedCleanups { processed: cleanups.len(), failed })
+}
@@ -0,0 +1,57 @@
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::Deserialize;
+
+use crate::storage;
+use crate::utils::http::{bad_request, unauthorized, internal_server_error, error_code};
+use crate::utils::db::{DbError, DynamoDbModel};
+use crate::handlers::auth::signed_in;
+use crate::models::{
+  session::{parse_token, Session},
+  user::User,
+};
+
+#[derive(Deserialize)]
+struct Params {
+  refresh_token: String,
+}
+
+fn invalid_refresh_token() -> Response<Body> {
+  unauthorized(error_code("invalid_refresh_token", "Invalid refresh token."))
+}
+
+// Exchanges a refresh token for new access & refresh tokens of the same session
+// (the refresh token can only be used once)
+pub fn refresh_auth(request: Request) -> Response<Body> {
+  if let Ok(Some(params)) = request.payload::<Params>() {
+    let (user_id, session_id) = match parse_token(&params.refresh_token) {
+      Some(key) => key,
+      None => return invalid_refresh_token(),
+    };
+    let db = match storage::connect() {
+      Ok(db) => db,
+      Err(err) => return internal_server_error(err),
+    };
+    let session = match Session::find(db.as_ref(), user_id.clone(), session_id) {
+      Ok(Some(ref session)) if !session.refresh_token_matches(&params.refresh_token) => return invalid_refresh_token(),
+      // Users have to sign in again with Google
+      Ok(Some(ref session)) if session.refresh_token_expired() =>
+        return unauthorized(error_code("refresh_token_expired", "The refresh token has expired.")),
+      Ok(Some(session)) => session,
+      Ok(None) => return invalid_refresh_token(),
+      Err(err) => return internal_server_error(format!("Error finding a session: {}", err)),
+    };
+    let user = match User::find(db.as_ref(), user_id.clone(), user_id) {
+      Ok(Some(user)) => user,
+      Ok(None) => return invalid_refresh_token(),
+      Err(err) => return internal_server_error(format!("Error finding a user: {}", err)),
+    };
+    match session.refresh(db.as_ref()) {
+      Ok((session, tokens)) => signed_in(&user, &session, &tokens),
+      // The session has ended or the token has been refreshed by another request in the meantime
+      Err(DbError::ConditionFailed) => invalid_refresh_token(),
+      Err(err) => internal_server_error(format!("Error refreshing a session: {}", err)),
+    }
+  } else {
+    bad_request("Invalid params.")
+  }
+}
@@ -0,0 +1,83 @@
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use serde::Deserialize;
+
+use crate::storage::{self, Storage};
+use crate::utils::http::{
+  bad_request,
+  internal_server_error,
+  missing_request_param,
+  ok,
+  HttpError,
+};
+use crate::utils::current_user::CurrentUser;
+use crate::models::{
+  session::Session,
+  user::{AuthToken, User},
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+}
+
+// Ends every session of the current user (e.g. after a token was stolen), including the one making the request
+pub struct RevokeSessions {
+  db: Arc<dyn Storage>,
+  params: Params,
+  current_user: Option<User>,
+}
+
+impl CurrentUser for RevokeSessions {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl RevokeSessions {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    Self::new(request)?
+      .validate_params()?
+      .fetch_current_user()?
+      .revoke_sessions()?
+      .serialize()
+  }
+
+  pub fn new(request: Request) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        current_user: None,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request(missing_request_param("auth_token")))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn revoke_sessions(&mut self) -> Result<&mut Self, HttpError> {
+    Session::remove_all(self.db.as_ref(), self.user_id()?).map_err(internal_server_error)?;
+    Ok(self)
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(""))
+  }
+}
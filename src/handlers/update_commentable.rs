@@ -0,0 +1,163 @@
+use std::sync::Arc;
+
+use lambda_http::{Request, Response, Body, RequestExt};
+use chrono::DateTime;
+use serde::{Deserialize, Deserializer};
+
+use crate::push::{self, Event, Push};
+use crate::storage::{self, Storage, Update};
+use crate::utils::db::{attribute_value, CommentableId};
+use crate::utils::http::{
+  bad_request,
+  forbidden,
+  internal_server_error,
+  missing_path_param,
+  missing_request_param,
+  ok,
+  HttpError,
+};
+use crate::utils::current_user::CurrentUser;
+use crate::models::{
+  user::{AuthToken, User},
+  commentable::{Commentable, CommentableStatus},
+};
+
+#[derive(Deserialize)]
+struct Params {
+  auth_token: AuthToken,
+  status: Option<CommentableStatus>,
+  title: Option<String>,
+  url: Option<String>,
+  published_at: Option<String>,
+  // null falls back to the site-wide setting
+  #[serde(default, deserialize_with = "deserialize_some")]
+  auto_close_days: Option<Option<i64>>,
+}
+
+// Tells null values (Some(None)) apart from missing ones (None)
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where T: Deserialize<'de>, D: Deserializer<'de> {
+  T::deserialize(deserializer).map(Some)
+}
+
+// Lets site admins lock, unlock or close a commentable and change its metadata
+pub struct UpdateCommentable {
+  db: Arc<dyn Storage>,
+  push: Arc<dyn Push>,
+  commentable_id: CommentableId,
+  params: Params,
+  current_user: Option<User>,
+  commentable: Option<Commentable>,
+}
+
+impl CurrentUser for UpdateCommentable {
+  fn db(&self) -> &dyn Storage {
+    self.db.as_ref()
+  }
+
+  fn auth_token(&self) -> Option<AuthToken> {
+    Some(self.params.auth_token.clone())
+  }
+
+  fn set_current_user(&mut self, user: Option<User>) {
+    self.current_user = user;
+  }
+}
+
+impl UpdateCommentable {
+  pub fn respond_to(request: Request) -> Result<Response<Body>, HttpError> {
+    if let Some(commentable_id) = request.path_parameters().get("id") {
+      Self::new(request, commentable_id.to_string())?
+        .validate_params()?
+        .fetch_current_user()?
+        .authorize()?
+        .update()?
+        .notify()
+        .serialize()
+    } else {
+      Err(bad_request(missing_path_param("id")))
+    }
+  }
+
+  pub fn new(request: Request, commentable_id: CommentableId) -> Result<Self, HttpError> {
+    if let Ok(Some(params)) = request.payload::<Params>() {
+      Ok(Self {
+        db: storage::connect().map_err(internal_server_error)?,
+        push: push::connect().map_err(internal_server_error)?,
+        current_user: None,
+        commentable: None,
+        commentable_id,
+        params,
+      })
+    } else {
+      Err(bad_request("Invalid parameters"))
+    }
+  }
+
+  pub fn validate_params(&mut self) -> Result<&mut Self, HttpError> {
+    if self.params.auth_token.trim().is_empty() {
+      Err(bad_request(missing_request_param("auth_token")))
+    } else if self.params.status.is_none()
+      && self.params.title.is_none()
+      && self.params.url.is_none()
+      && self.params.published_at.is_none()
+      && self.params.auto_close_days.is_none() {
+      Err(bad_request("Invalid request parameters: status, title, url, published_at or auto_close_days is required"))
+    } else if self.params.published_at.iter().any(|time| !time.trim().is_empty() && DateTime::parse_from_rfc3339(time).is_err()) {
+      Err(bad_request("Invalid request parameters: published_at has to be an RFC 3339 timestamp"))
+    } else if self.params.auto_close_days.iter().flatten().any(|days| *days < 0) {
+      Err(bad_request("Invalid request parameters: auto_close_days can't be negative"))
+    } else {
+      Ok(self)
+    }
+  }
+
+  pub fn authorize(&mut self) -> Result<&mut Self, HttpError> {
+    // The unwrap is safe because presence is guaranteed by #fetch_current_user
+    if self.current_user.as_ref().unwrap().is_admin() {
+      Ok(self)
+    } else {
+      Err(forbidden("Only admins can update commentables"))
+    }
+  }
+
+  pub fn update(&mut self) -> Result<&mut Self, HttpError> {
+    let mut update = Update::default();
+    if let Some(status) = self.params.status {
+      update.set.insert(String::from("status"), attribute_value(status.as_str().to_string()));
+    }
+    match self.params.auto_close_days {
+      Some(Some(days)) => { update.set.insert(String::from("auto_close_days"), attribute_value(days)); },
+      Some(None) => update = update.remove("auto_close_days"),
+      None => (),
+    }
+    // Blank values clear the title, URL & publish date
+    let fields = &[("title", &self.params.title), ("url", &self.params.url), ("published_at", &self.params.published_at)];
+    for (field_name, value) in fields {
+      match value.as_ref().map(|value| value.trim()) {
+        Some("") => update = update.remove(field_name),
+        Some(value) => { update.set.insert(field_name.to_string(), attribute_value(value.to_string())); },
+        None => (),
+      }
+    }
+
+    match Commentable::update_for_commentable(self.db.as_ref(), self.commentable_id.clone(), update) {
+      Ok(commentable) => {
+        self.commentable = Some(commentable);
+        Ok(self)
+      },
+      Err(err) => Err(internal_server_error(err)),
+    }
+  }
+
+  pub fn notify(&mut self) -> &mut Self {
+    // The unwrap is safe because the commentable is set in #update
+    let commentable = self.commentable.as_ref().unwrap();
+    push::broadcast(self.db.as_ref(), self.push.as_ref(), &self.commentable_id, &Event::CommentableUpdated { commentable });
+    self
+  }
+
+  pub fn serialize(&self) -> Result<Response<Body>, HttpError> {
+    Ok(ok(serde_json::to_string(&self.commentable).map_err(internal_server_error)?))
+  }
+}
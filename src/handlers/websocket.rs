@@ -0,0 +1,72 @@
+use serde::{Serialize, Deserialize};
+
+use crate::storage;
+use crate::models::connection::{Connection, ConnectionId};
+use crate::utils::db::CommentableId;
+
+// The parts of API Gateway WebSocket events used by the handler
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebSocketEvent {
+  pub request_context: RequestContext,
+  pub body: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RequestContext {
+  pub route_key: String,
+  pub connection_id: ConnectionId,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct WebSocketResponse {
+  pub status_code: u16,
+  pub body: String,
+}
+
+// Messages sent by clients, routed by the "action" field
+#[derive(Deserialize)]
+struct Params {
+  commentable_id: CommentableId,
+}
+
+fn response(status_code: u16, body: &str) -> WebSocketResponse {
+  WebSocketResponse { status_code, body: body.to_string() }
+}
+
+// Handles connections to the WebSocket API. Clients subscribe to updates of a commentable by sending
+// {"action": "subscribe", "commentable_id": "..."} and stop receiving them with the "unsubscribe" action.
+pub fn websocket(event: WebSocketEvent) -> WebSocketResponse {
+  let db = match storage::connect() {
+    Ok(db) => db,
+    Err(err) => return response(500, &err.to_string()),
+  };
+  let connection_id = event.request_context.connection_id;
+
+  let result = match event.request_context.route_key.as_str() {
+    "$connect" => Ok(()),
+    "$disconnect" => Connection::disconnect(db.as_ref(), connection_id),
+    "subscribe" | "unsubscribe" => match commentable_id(event.body) {
+      Some(commentable_id) if event.request_context.route_key == "subscribe" =>
+        Connection::subscribe(db.as_ref(), connection_id, commentable_id),
+      Some(commentable_id) => Connection::unsubscribe(db.as_ref(), connection_id, commentable_id),
+      None => return response(400, "Invalid parameters: commentable_id is required"),
+    },
+    _ => return response(400, "Unknown action"),
+  };
+
+  match result {
+    Ok(()) => response(200, ""),
+    Err(err) => response(500, &err.to_string()),
+  }
+}
+
+fn commentable_id(body: Option<String>) -> Option<CommentableId> {
+  body
+    .and_then(|body| serde_json::from_str::<Params>(&body).ok())
+    .map(|params| params.commentable_id)
+    .filter(|commentable_id| !commentable_id.trim().is_empty())
+}
+
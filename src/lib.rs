@@ -1,2 +1,10 @@
+// Handlers return HTTP error responses as the Err variant of their chained steps
+// (Result<&mut Self, HttpError>), so that every step can end the request with `?`.
+// The responses are large, but they're created once per request, so boxing them isn't worth the noise.
+#![allow(clippy::result_large_err)]
+
 pub mod utils;
 pub mod models;
+pub mod handlers;
+pub mod storage;
+pub mod push;
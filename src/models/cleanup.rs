@@ -0,0 +1,86 @@
+use chrono::{DateTime, Utc};
+use maplit::hashmap;
+use serde::Serialize;
+
+use crate::models::comment::CommentId;
+use crate::models::reaction::Reaction;
+use crate::models::revision::Revision;
+use crate::storage::{Query, Storage};
+use crate::utils::db::{
+  CommentableId,
+  DynamoDbModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  IntoDynamoDbAttributes,
+  hash,
+};
+
+// Pending cleanups share a single partition, so they can be listed without scanning the table
+pub static CLEANUP_QUEUE_KEY: &str = "CLEANUP_QUEUE";
+pub static CLEANUP_ID_PREFIX: &str = "CLEANUP_";
+
+// A queued removal of the reactions & revisions of a deleted (or erased) comment, used when there are
+// too many of them to remove in the same transaction as the comment. Cleanups are run right after the
+// comment is deleted, the ones that failed stay queued until bin/process-cleanups.rs runs them again.
+// The comment ID isn't stored as "comment_id", which would add cleanups to the reactions index.
+#[derive(Serialize, Debug)]
+pub struct Cleanup {
+  pub primary_key: String,
+  pub id: String,
+  pub commentable_id: CommentableId,
+  pub cleanup_of: CommentId,
+  pub created_at: DateTime<Utc>,
+}
+
+impl DynamoDbModel for Cleanup {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      commentable_id: attributes.string("commentable_id")?,
+      cleanup_of: attributes.string("cleanup_of")?,
+      created_at: attributes.timestamp("created_at")?,
+    })
+  }
+}
+
+impl Cleanup {
+  // Builds the cleanup without queueing it, see #attributes
+  pub fn for_comment(commentable_id: CommentableId, comment_id: CommentId) -> Self {
+    Self {
+      primary_key: CLEANUP_QUEUE_KEY.to_string(),
+      id: format!("{}{}", CLEANUP_ID_PREFIX, hash(&format!("{}{}", commentable_id, comment_id))),
+      commentable_id,
+      cleanup_of: comment_id,
+      created_at: Utc::now(),
+    }
+  }
+
+  // Queued in the same transaction as the deletion of the comment
+  pub fn attributes(&self) -> IntoDynamoDbAttributes {
+    IntoDynamoDbAttributes {
+      attributes: hashmap!{
+        String::from("primary_key") => self.primary_key.clone().into(),
+        String::from("id") => self.id.clone().into(),
+        String::from("commentable_id") => self.commentable_id.clone().into(),
+        String::from("cleanup_of") => self.cleanup_of.clone().into(),
+        String::from("created_at") => self.created_at.to_rfc3339().into(),
+      }
+    }
+  }
+
+  pub fn list_pending(db: &dyn Storage) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::id_prefix(CLEANUP_QUEUE_KEY.to_string(), CLEANUP_ID_PREFIX.to_string()))?
+      .into_iter()
+      .map(Self::new)
+      .collect()
+  }
+
+  // Removes the reactions & revisions, and then the cleanup itself (so failed cleanups can be run again)
+  pub fn run(&self, db: &dyn Storage) -> Result<(), DbError> {
+    Reaction::remove_all_for_comment(db, self.commentable_id.clone(), self.cleanup_of.clone())?;
+    Revision::remove_all_for_comment(db, self.commentable_id.clone(), &self.cleanup_of)?;
+    Self::delete(db, self.primary_key.clone(), self.id.clone())
+  }
+}
@@ -1,17 +1,15 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
 use chrono::{DateTime, Utc};
 use maplit::hashmap;
-use rusoto_dynamodb::{
-  DynamoDb,
-  DynamoDbClient,
-  QueryInput,
-  UpdateItemInput,
-};
 use serde::Serialize;
 
+use crate::models::reaction::{Reaction, ReactionType};
+use crate::models::revision::Revision;
+use crate::models::tombstone::Tombstone;
 use crate::models::user::UserId;
+use crate::storage::{item_key, number_attribute, string_attribute, Condition, Filter, Index, Query, Storage, Update};
 use crate::utils::db::{
-  COMMENTABLE_RS_TABLE_NAME,
-  REPLIES_INDEX_NAME,
   CommentableId,
   DynamoDbModel,
   DynamoDbListableModel,
@@ -20,11 +18,19 @@ use crate::utils::db::{
   DbError,
   attribute_value,
   hash,
+  record_keys,
+  sortable_timestamp,
+  Transaction,
+  VERSION_FIELD_NAME,
 };
 
 pub type CommentId = String;
 
 pub static COMMENT_ID_PREFIX: &str = "COMMENT_";
+// Top-level comments store a copy of their ID in this attribute, which is the sort key of the top-level index
+pub static TOP_LEVEL_ID_FIELD_NAME: &str = "top_level_id";
+// Reactions of each type are counted in a separate attribute of the comment (reaction_count_{type})
+pub static REACTION_COUNT_PREFIX: &str = "reaction_count_";
 
 #[derive(Serialize, Debug)]
 pub struct Comment {
@@ -34,6 +40,12 @@ pub struct Comment {
   pub replies_to: Option<CommentId>,
   pub body: String,
   pub is_deleted: Option<bool>,
+  pub reaction_counts: HashMap<ReactionType, i64>,
+  // Previous bodies of edited comments are kept as revisions (see models/revision.rs)
+  pub edited_at: Option<DateTime<Utc>>,
+  pub edit_count: i64,
+  // Bumped on every write, so that edits based on an outdated comment can be rejected
+  pub version: i64,
   pub created_at: DateTime<Utc>,
 }
 
@@ -45,7 +57,11 @@ impl DynamoDbModel for Comment {
       user_id: attributes.optional_string("user_id"),
       replies_to: attributes.optional_string("replies_to"),
       body: attributes.string("body")?,
-      is_deleted: None,
+      is_deleted: attributes.optional_bool("is_deleted"),
+      reaction_counts: reaction_counts(&attributes),
+      edited_at: attributes.optional_timestamp("edited_at")?,
+      edit_count: attributes.optional_number("edit_count").unwrap_or(0),
+      version: attributes.optional_number(VERSION_FIELD_NAME).unwrap_or(0),
       created_at: attributes.timestamp("created_at")?,
     })
   }
@@ -58,46 +74,296 @@ impl DynamoDbListableModel for Comment {
 }
 
 impl Comment {
-  pub fn has_replies(&self, db: &DynamoDbClient) -> Result<bool, DbError> {
-    let replies = Self::query(&db, QueryInput {
-      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-      index_name: Some(REPLIES_INDEX_NAME.to_string()),
-      key_condition_expression: String::from("primary_key = :v1 and replies_to = :v2").into(),
-      expression_attribute_values: hashmap!{
-        String::from(":v1") => attribute_value(self.primary_key.clone()),
-        String::from(":v2") => attribute_value(self.id.clone()),
-      }.into(),
-      ..Default::default()
-    })?;
-
-    if replies.len() > 0 { Ok(true) } else { Ok(false) }
-  }
-
-  pub fn erase(&mut self, db: &DynamoDbClient) -> Result<(), DbError> {
-    db.update_item(UpdateItemInput {
-      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-      key: hashmap!{
-        String::from("primary_key") => attribute_value(self.primary_key.clone()),
-        String::from("id") => attribute_value(self.id.clone()),
+  // A page of top-level comments, ordered by creation time.
+  // Read from the top-level index, so that replies aren't read (and filtered out) along the way.
+  pub fn top_level_page(
+    db: &dyn Storage,
+    commentable_id: CommentableId,
+    limit: Option<usize>,
+    start_after: Option<CommentId>,
+    newest_first: bool,
+  ) -> Result<(Vec<Self>, Option<CommentId>), DbError> {
+    let page = db.query_page(Query::in_index(Index::TopLevel, commentable_id)
+      .page(limit, start_after)
+      .descending(newest_first))?;
+    let mut comments = Self::batch_find(db, record_keys(page.items)?)?;
+    sort_by_id(&mut comments, newest_first);
+    Ok((comments, page.last_evaluated_id))
+  }
+
+  // IDs of replies of every comment of the commentable, by the comment they reply to (oldest first).
+  // Only keys are read, so listing comments can fetch just the replies it returns.
+  pub fn reply_ids(db: &dyn Storage, commentable_id: CommentableId) -> Result<HashMap<CommentId, Vec<CommentId>>, DbError> {
+    let mut reply_ids: HashMap<CommentId, Vec<CommentId>> = HashMap::new();
+    for item in db.query(Query::in_index(Index::Replies, commentable_id))? {
+      if let (Some(parent_id), Some(id)) = (string_attribute(&item, "replies_to"), string_attribute(&item, "id")) {
+        reply_ids.entry(parent_id.to_string()).or_default().push(id.to_string());
+      }
+    }
+    for ids in reply_ids.values_mut() {
+      ids.sort();
+    }
+    Ok(reply_ids)
+  }
+
+  // Adds top-level comments created before the top-level index to the index.
+  // Returns the number of indexed comments.
+  pub fn index_top_level(db: &dyn Storage, commentable_id: CommentableId) -> Result<usize, DbError> {
+    let mut indexed = 0;
+    for item in Self::query(db, Query::id_prefix(commentable_id, Self::id_prefix()))? {
+      if item.contains_key("replies_to") || item.contains_key(TOP_LEVEL_ID_FIELD_NAME) {
+        continue;
+      }
+      let (key, id) = item_key(&item)?;
+      db.update(key, id.clone(), Update::set(hashmap!{
+        String::from(TOP_LEVEL_ID_FIELD_NAME) => attribute_value(id),
+      }).condition(Condition::Exists))?;
+      indexed += 1;
+    }
+    Ok(indexed)
+  }
+
+  // IDs of every commentable with comments, found by scanning the whole table (see Storage#scan_page)
+  pub fn commentable_ids(db: &dyn Storage) -> Result<BTreeSet<CommentableId>, DbError> {
+    let mut commentable_ids = BTreeSet::new();
+    let mut start_after = None;
+    loop {
+      let page = db.scan_page(start_after)?;
+      for item in page.items {
+        let (key, id) = item_key(&item)?;
+        if id.starts_with(COMMENT_ID_PREFIX) {
+          commentable_ids.insert(key);
+        }
+      }
+      match page.last_evaluated_key {
+        Some(key) => start_after = Some(key),
+        None => return Ok(commentable_ids),
+      }
+    }
+  }
+
+  // Removes replies whose parent no longer exists (with their replies, reactions & revisions),
+  // which can be left behind by a reply saved while its parent was being deleted.
+  // Comment counters aren't updated, so they have to be recounted afterwards (see Counters#recount).
+  // Returns the number of removed comments.
+  pub fn remove_orphans(db: &dyn Storage, commentable_id: CommentableId) -> Result<usize, DbError> {
+    let comments = Self::list(db, commentable_id.clone())?;
+    let mut kept = comments
+      .iter()
+      .filter(|comment| comment.replies_to.is_none())
+      .map(|comment| comment.id.clone())
+      .collect::<HashSet<CommentId>>();
+    // Replies are kept once their parent is, so chains of replies are kept as a whole
+    loop {
+      let replies = comments
+        .iter()
+        .filter(|comment| !kept.contains(&comment.id))
+        .filter(|comment| comment.replies_to.as_ref().is_some_and(|parent_id| kept.contains(parent_id)))
+        .map(|comment| comment.id.clone())
+        .collect::<Vec<CommentId>>();
+      if replies.is_empty() {
+        break;
+      }
+      kept.extend(replies);
+    }
+
+    let mut removed = 0;
+    for comment in comments.into_iter().filter(|comment| !kept.contains(&comment.id)) {
+      Transaction::default()
+        .delete(commentable_id.clone(), comment.id.clone(), None)
+        .put(Tombstone::attributes_for_comment(commentable_id.clone(), comment.id.clone()), None)
+        .commit(db)?;
+      Reaction::remove_all_for_comment(db, commentable_id.clone(), comment.id.clone())?;
+      Revision::remove_all_for_comment(db, commentable_id.clone(), &comment.id)?;
+      removed += 1;
+    }
+    Ok(removed)
+  }
+
+  // A page of direct replies to the comment, ordered by creation time.
+  // All replies to the comment share the same key in the replies index, which doesn't order them,
+  // so the page is cut from the IDs of all of them (only keys are read) sorted like the table.
+  pub fn replies_page(
+    db: &dyn Storage,
+    commentable_id: CommentableId,
+    comment_id: CommentId,
+    limit: Option<usize>,
+    start_after: Option<CommentId>,
+    newest_first: bool,
+  ) -> Result<(Vec<Self>, Option<CommentId>), DbError> {
+    let mut ids = record_keys(db.query(Query::index(Index::Replies, commentable_id.clone(), comment_id))?)?
+      .into_iter()
+      .map(|(_, id)| id)
+      .collect::<Vec<CommentId>>();
+    ids.sort();
+    if newest_first {
+      ids.reverse();
+    }
+    let mut page = ids
+      .into_iter()
+      .filter(|id| match &start_after {
+        Some(start_after) if newest_first => id < start_after,
+        Some(start_after) => id > start_after,
+        None => true,
+      })
+      .collect::<Vec<CommentId>>();
+    let last_evaluated_id = match limit {
+      Some(limit) if page.len() > limit => {
+        page.truncate(limit);
+        page.last().cloned()
       },
-      update_expression: Some(String::from("SET is_deleted = :is_deleted, body = :body REMOVE user_id")),
-      expression_attribute_values: Some(hashmap!{
-        String::from(":is_deleted") => attribute_value(true),
-        String::from(":body") => attribute_value("This comment has been deleted.".to_string()),
-      }),
-      ..Default::default()
-    }).sync()
-      .map_err(|err| DbError::Error(err.to_string()))
-      .and_then(|_| {
-        self.body = "This comment has been deleted.".to_string();
-        self.is_deleted = Some(true);
-        self.user_id = None;
-        Ok(())
+      _ => None,
+    };
+
+    let mut replies = Self::batch_find(db, page.into_iter().map(|id| (commentable_id.clone(), id)).collect())?;
+    sort_by_id(&mut replies, newest_first);
+    Ok((replies, last_evaluated_id))
+  }
+
+  pub fn has_replies(&self, db: &dyn Storage) -> Result<bool, DbError> {
+    let replies = Self::query(db, Query::index(Index::Replies, self.primary_key.clone(), self.id.clone()))?;
+
+    Ok(!replies.is_empty())
+  }
+
+  // Comments created, edited or deleted (see Tombstone) after the given sortable timestamp.
+  // Reactions added or removed after that time mark their comments as changed as well.
+  pub fn changed_since(db: &dyn Storage, commentable_id: CommentableId, since: String) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::id_prefix(commentable_id, Self::id_prefix())
+      .filter(Filter::AttributeGreaterThan(String::from("changed_at"), since)))?
+      .into_iter()
+      .map(Self::new)
+      .collect()
+  }
+
+  // Adds to the counter of reactions of the given type, written in the same transaction as the reaction.
+  // The comment is marked as changed too, so clients syncing comments get the new counts.
+  // The comment has to exist, so that a comment deleted in the meantime isn't recreated with only its counters.
+  pub fn count_reaction_update(reaction_type: &ReactionType, value: i64) -> Update {
+    Update::set(hashmap!{
+      String::from("changed_at") => attribute_value(sortable_timestamp(Utc::now())),
+    }).add(&reaction_count_attribute(reaction_type), value).add(VERSION_FIELD_NAME, 1).condition(Condition::Exists)
+  }
+
+  // Replaces all reaction counters, used to repair counters that went out of sync
+  pub fn set_reaction_counts(&mut self, db: &dyn Storage, counts: HashMap<ReactionType, i64>) -> Result<(), DbError> {
+    let mut attributes = counts
+      .iter()
+      .map(|(reaction_type, count)| (reaction_count_attribute(reaction_type), attribute_value(*count)))
+      .collect::<DynamoDbAttributes>();
+    attributes.insert(String::from("changed_at"), attribute_value(sortable_timestamp(Utc::now())));
+    let mut update = Update::set(attributes).add(VERSION_FIELD_NAME, 1);
+    for reaction_type in self.reaction_counts.keys().filter(|reaction_type| !counts.contains_key(*reaction_type)) {
+      update = update.remove(&reaction_count_attribute(reaction_type));
+    }
+    db.update(self.primary_key.clone(), self.id.clone(), update)
+      .map(|attributes| {
+        self.reaction_counts = counts;
+        self.version = number_attribute(&attributes, VERSION_FIELD_NAME).unwrap_or(0);
       })
   }
+
+  // Erases the body, author & reactions of the comment, keeping its place in the thread.
+  // Only applied when the comment hasn't changed since it was loaded.
+  pub fn erase_update(&self) -> Update {
+    let mut update = Update::set(hashmap!{
+      String::from("is_deleted") => attribute_value(true),
+      String::from("body") => attribute_value("This comment has been deleted.".to_string()),
+      String::from("changed_at") => attribute_value(sortable_timestamp(Utc::now())),
+    }).remove("user_id").remove("edited_at").remove("edit_count").add(VERSION_FIELD_NAME, 1);
+    // Reactions of erased comments are removed as well
+    for reaction_type in self.reaction_counts.keys() {
+      update = update.remove(&reaction_count_attribute(reaction_type));
+    }
+    update.condition(Condition::NumberEquals(VERSION_FIELD_NAME.to_string(), self.version))
+  }
+}
+
+// Batch reads don't keep the order of the keys
+fn sort_by_id(comments: &mut [Comment], newest_first: bool) {
+  if newest_first {
+    comments.sort_by(|a, b| b.id.cmp(&a.id));
+  } else {
+    comments.sort_by(|a, b| a.id.cmp(&b.id));
+  }
+}
+
+pub fn reaction_count_attribute(reaction_type: &str) -> String {
+  format!("{}{}", REACTION_COUNT_PREFIX, reaction_type)
+}
+
+// Missing counters mean no reactions, and so do counters that dropped to 0
+fn reaction_counts(attributes: &DynamoDbAttributes) -> HashMap<ReactionType, i64> {
+  attributes
+    .keys()
+    .filter_map(|name| name.strip_prefix(REACTION_COUNT_PREFIX))
+    .filter_map(|reaction_type| {
+      number_attribute(attributes, &reaction_count_attribute(reaction_type))
+        .filter(|count| *count > 0)
+        .map(|count| (reaction_type.to_string(), count))
+    })
+    .collect()
 }
 
 pub fn comment_id(commentable_id: &CommentableId, user_id: &UserId) -> String {
-  let id = hash(&format!("{}{}{}", commentable_id, user_id, Utc::now().to_string()));
+  let id = hash(&format!("{}{}{}", commentable_id, user_id, Utc::now()));
   format!("{}{}{}", COMMENT_ID_PREFIX, Utc::now().timestamp_millis(), id)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::MemoryStorage;
+
+  // A reaction saved like AddReaction#save does, after its comment has been deleted
+  #[test]
+  fn reactions_of_deleted_comments_are_not_saved() {
+    let db = MemoryStorage::new();
+    let reaction = hashmap!{
+      String::from("primary_key") => attribute_value(String::from("page")),
+      String::from("id") => attribute_value(String::from("REACTION_deleted_like")),
+      String::from("comment_id") => attribute_value(String::from("COMMENT_deleted")),
+    };
+    let saved = Transaction::default()
+      .put(reaction, Some(Condition::NotExists))
+      .update(String::from("page"), String::from("COMMENT_deleted"), Comment::count_reaction_update(&String::from("like"), 1))
+      .commit(&db);
+
+    match saved {
+      Err(DbError::ConditionFailed) => (),
+      _ => panic!("the reaction of a deleted comment has been saved"),
+    }
+    assert!(db.get(String::from("page"), String::from("COMMENT_deleted")).unwrap().is_none());
+    assert!(db.get(String::from("page"), String::from("REACTION_deleted_like")).unwrap().is_none());
+  }
+
+  fn save_comment(db: &MemoryStorage, id: &str, replies_to: Option<&str>) {
+    let mut comment = hashmap!{
+      String::from("primary_key") => attribute_value(String::from("page")),
+      String::from("id") => attribute_value(id.to_string()),
+      String::from("body") => attribute_value(String::from("body")),
+      String::from("created_at") => attribute_value(Utc::now().to_rfc3339()),
+    };
+    if let Some(replies_to) = replies_to {
+      comment.insert(String::from("replies_to"), attribute_value(replies_to.to_string()));
+    }
+    db.put(comment).unwrap();
+  }
+
+  #[test]
+  fn orphans_are_removed_with_their_replies() {
+    let db = MemoryStorage::new();
+    save_comment(&db, "COMMENT_1", None);
+    save_comment(&db, "COMMENT_2", Some("COMMENT_1"));
+    save_comment(&db, "COMMENT_3", Some("COMMENT_2"));
+    // A reply saved while its parent was being deleted, and a reply to it
+    save_comment(&db, "COMMENT_5", Some("COMMENT_4"));
+    save_comment(&db, "COMMENT_6", Some("COMMENT_5"));
+
+    assert_eq!(Comment::remove_orphans(&db, String::from("page")).unwrap(), 2);
+    let ids = Comment::list(&db, String::from("page")).unwrap().into_iter().map(|comment| comment.id).collect::<Vec<CommentId>>();
+    assert_eq!(ids, vec!["COMMENT_1", "COMMENT_2", "COMMENT_3"]);
+    let deleted = Tombstone::list_since(&db, String::from("page"), String::new()).unwrap();
+    assert_eq!(deleted.len(), 2);
+  }
+}
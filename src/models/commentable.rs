@@ -0,0 +1,221 @@
+use std::str::FromStr;
+
+use chrono::{DateTime, Duration, Utc};
+use maplit::hashmap;
+use serde::{Serialize, Deserialize};
+
+use crate::models::comment::{Comment, CommentId};
+use crate::storage::{Query, Storage, Update};
+use crate::utils::config;
+use crate::utils::db::{
+  CommentableId,
+  DynamoDbModel,
+  DynamoDbListableModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  IntoDynamoDbAttributes,
+  attribute_value,
+};
+
+pub static COMMENTABLE_ID: &str = "COMMENTABLE";
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommentableStatus {
+  // Anything can be added, edited and deleted
+  Open,
+  // Closed to new comments and replies, existing comments can still be edited and reacted to
+  Closed,
+  // Read-only, only deleting comments is allowed
+  Locked,
+}
+
+impl CommentableStatus {
+  pub fn as_str(self) -> &'static str {
+    match self {
+      CommentableStatus::Open => "open",
+      CommentableStatus::Closed => "closed",
+      CommentableStatus::Locked => "locked",
+    }
+  }
+}
+
+impl FromStr for CommentableStatus {
+  type Err = DbError;
+
+  fn from_str(status: &str) -> Result<Self, Self::Err> {
+    match status {
+      "open" => Ok(CommentableStatus::Open),
+      "closed" => Ok(CommentableStatus::Closed),
+      "locked" => Ok(CommentableStatus::Locked),
+      _ => Err(DbError::RecordInvalid(format!("Unknown commentable status '{}'.", status))),
+    }
+  }
+}
+
+// Metadata of a commentable, created along with its first comment (or by commentable/update).
+// Commentables without metadata (e.g. with comments added before it was introduced) are open.
+#[derive(Serialize, Debug)]
+pub struct Commentable {
+  pub primary_key: CommentableId,
+  pub id: String,
+  pub title: Option<String>,
+  pub url: Option<String>,
+  pub status: CommentableStatus,
+  // Open commentables are closed to new comments this many days after being published,
+  // overriding the site-wide setting (0 disables closing this commentable)
+  pub auto_close_days: Option<i64>,
+  pub published_at: Option<DateTime<Utc>>,
+  // Top-level comments listed before any other comments
+  pub pinned_comment_ids: Vec<CommentId>,
+  // The time of the first comment
+  pub created_at: DateTime<Utc>,
+}
+
+impl DynamoDbModel for Commentable {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      title: attributes.optional_string("title"),
+      url: attributes.optional_string("url"),
+      status: attributes.string("status")?.parse()?,
+      auto_close_days: attributes.optional_number("auto_close_days"),
+      published_at: attributes.optional_timestamp("published_at")?,
+      pinned_comment_ids: attributes.string_set("pinned_comment_ids"),
+      created_at: attributes.timestamp("created_at")?,
+    })
+  }
+}
+
+impl Commentable {
+  pub fn find_for_commentable(db: &dyn Storage, commentable_id: CommentableId) -> Result<Option<Self>, DbError> {
+    Self::find(db, commentable_id, COMMENTABLE_ID.to_string())
+  }
+
+  // The metadata a commentable without one would get, without saving it
+  pub fn build_for_commentable(db: &dyn Storage, commentable_id: CommentableId) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: commentable_id.clone(),
+      id: COMMENTABLE_ID.to_string(),
+      title: None,
+      url: None,
+      status: CommentableStatus::Open,
+      auto_close_days: None,
+      published_at: None,
+      pinned_comment_ids: vec![],
+      created_at: first_comment_at(db, commentable_id)?.unwrap_or_else(Utc::now),
+    })
+  }
+
+  // Metadata created in the meantime (e.g. by a concurrent first comment or commentable/update) is kept and returned,
+  // so that the status & pins set by site admins aren't overwritten
+  pub fn create_for_commentable(db: &dyn Storage, commentable_id: CommentableId) -> Result<Self, DbError> {
+    let created_at = first_comment_at(db, commentable_id.clone())?.unwrap_or_else(Utc::now);
+    let created = Self::create_if_absent(db, IntoDynamoDbAttributes {
+      attributes: hashmap!{
+        String::from("primary_key") => commentable_id.clone().into(),
+        String::from("id") => COMMENTABLE_ID.to_string().into(),
+        String::from("status") => CommentableStatus::Open.as_str().to_string().into(),
+        String::from("created_at") => created_at.to_rfc3339().into(),
+      }
+    });
+    match created {
+      Err(DbError::ConditionFailed) => Self::find_for_commentable(db, commentable_id)?
+        .ok_or_else(|| DbError::Error(String::from("The commentable has been removed in the meantime."))),
+      created => created,
+    }
+  }
+
+  // Updates the metadata, creating it first for commentables without one
+  pub fn update_for_commentable(db: &dyn Storage, commentable_id: CommentableId, mut update: Update) -> Result<Self, DbError> {
+    if Self::find_for_commentable(db, commentable_id.clone())?.is_none() {
+      let created_at = first_comment_at(db, commentable_id.clone())?.unwrap_or_else(Utc::now);
+      update.set.entry(String::from("status")).or_insert_with(|| attribute_value(CommentableStatus::Open.as_str().to_string()));
+      update.set.insert(String::from("created_at"), attribute_value(created_at.to_rfc3339()));
+    }
+    Self::update(db, commentable_id, COMMENTABLE_ID.to_string(), update)
+  }
+
+  // Pins or unpins a top-level comment, the pins are only changed by site admins so they aren't updated atomically
+  pub fn pin_comment(db: &dyn Storage, commentable_id: CommentableId, comment_id: &CommentId, pinned: bool) -> Result<Self, DbError> {
+    let mut pinned_comment_ids = Self::find_for_commentable(db, commentable_id.clone())?
+      .map(|commentable| commentable.pinned_comment_ids)
+      .unwrap_or_default();
+    pinned_comment_ids.retain(|id| id != comment_id);
+    if pinned {
+      pinned_comment_ids.push(comment_id.clone());
+    }
+    Self::update_for_commentable(db, commentable_id, pinned_comment_ids_update(pinned_comment_ids))
+  }
+
+  // Deleted comments are unpinned, leaving commentables without the comment untouched
+  pub fn unpin_deleted_comment(db: &dyn Storage, commentable_id: CommentableId, comment_id: &CommentId) -> Result<(), DbError> {
+    if let Some(commentable) = Self::find_for_commentable(db, commentable_id.clone())? {
+      if commentable.pinned_comment_ids.contains(comment_id) {
+        let pinned_comment_ids = commentable.pinned_comment_ids.into_iter().filter(|id| id != comment_id).collect();
+        Self::update(db, commentable_id, COMMENTABLE_ID.to_string(), pinned_comment_ids_update(pinned_comment_ids))?;
+      }
+    }
+    Ok(())
+  }
+
+  pub fn is_pinned(&self, comment_id: &CommentId) -> bool {
+    self.pinned_comment_ids.contains(comment_id)
+  }
+
+  // Commentables are closed automatically after a number of days since they were published
+  // (or since their first comment), unless it is disabled both site-wide and for the commentable
+  pub fn closes_at(&self) -> Option<DateTime<Utc>> {
+    self.auto_close_days
+      .or_else(config::auto_close_days)
+      .filter(|days| *days > 0)
+      .map(|days| self.published_at.unwrap_or(self.created_at) + Duration::days(days))
+  }
+
+  pub fn is_auto_closed(&self) -> bool {
+    self.status == CommentableStatus::Open && self.closes_at().filter(|closes_at| *closes_at <= Utc::now()).is_some()
+  }
+
+  pub fn accepts_comments(&self) -> bool {
+    self.status == CommentableStatus::Open && !self.is_auto_closed()
+  }
+
+  pub fn is_read_only(&self) -> bool {
+    self.status == CommentableStatus::Locked
+  }
+}
+
+fn pinned_comment_ids_update(pinned_comment_ids: Vec<CommentId>) -> Update {
+  if pinned_comment_ids.is_empty() {
+    Update::default().remove("pinned_comment_ids")
+  } else {
+    Update::set(hashmap!{ String::from("pinned_comment_ids") => attribute_value(pinned_comment_ids) })
+  }
+}
+
+fn first_comment_at(db: &dyn Storage, commentable_id: CommentableId) -> Result<Option<DateTime<Utc>>, DbError> {
+  // Comment IDs start with the time of creation, so the first one is the oldest comment
+  let (comments, _) = Comment::query_page(db, Query::id_prefix(commentable_id, Comment::id_prefix()).page(Some(1), None))?;
+  Ok(comments.into_iter().next().map(|comment| comment.created_at))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::MemoryStorage;
+
+  #[test]
+  fn existing_commentables_arent_replaced() {
+    let db = MemoryStorage::new();
+    let update = Update::set(hashmap!{ String::from("status") => attribute_value(String::from("locked")) });
+    Commentable::update_for_commentable(&db, String::from("page"), update).unwrap();
+
+    // Like a first comment which didn't find the commentable before it was locked
+    let commentable = Commentable::create_for_commentable(&db, String::from("page")).unwrap();
+    assert_eq!(commentable.status, CommentableStatus::Locked);
+    let commentable = Commentable::find_for_commentable(&db, String::from("page")).unwrap().unwrap();
+    assert_eq!(commentable.status, CommentableStatus::Locked);
+  }
+}
@@ -0,0 +1,104 @@
+use chrono::{Duration, Utc};
+use maplit::hashmap;
+use serde::Serialize;
+
+use crate::storage::{Query, Storage};
+use crate::utils::db::{
+  CommentableId,
+  DynamoDbModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  IntoDynamoDbAttributes,
+  PrimaryKey,
+  SortKey,
+};
+
+pub type ConnectionId = String;
+
+pub static CONNECTION_ID_PREFIX: &str = "CONNECTION_";
+pub static SUBSCRIPTION_ID_PREFIX: &str = "SUBSCRIPTION_";
+// API Gateway closes WebSocket connections after 2 hours, so stale subscriptions expire after that (using DynamoDB TTL)
+pub static CONNECTION_TTL_HOURS: i64 = 2;
+
+// A WebSocket connection subscribed to updates of a commentable.
+// Each subscription is stored twice:
+//   - under the commentable (CONNECTION_{connection_id}), to find subscribers when pushing events,
+//   - under the connection (SUBSCRIPTION_{commentable_id}), to find subscriptions on disconnect.
+#[derive(Serialize, Debug)]
+pub struct Connection {
+  pub primary_key: PrimaryKey,
+  pub id: SortKey,
+  pub connection_id: ConnectionId,
+  pub commentable_id: CommentableId,
+}
+
+impl DynamoDbModel for Connection {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      connection_id: attributes.string("connection_id")?,
+      commentable_id: attributes.string("commentable_id")?,
+    })
+  }
+}
+
+impl Connection {
+  pub fn subscribe(db: &dyn Storage, connection_id: ConnectionId, commentable_id: CommentableId) -> Result<(), DbError> {
+    let expires_at = (Utc::now() + Duration::hours(CONNECTION_TTL_HOURS)).timestamp();
+    for (primary_key, id) in subscription_keys(&connection_id, &commentable_id) {
+      Self::create(db, IntoDynamoDbAttributes {
+        attributes: hashmap!{
+          String::from("primary_key") => primary_key.into(),
+          String::from("id") => id.into(),
+          String::from("connection_id") => connection_id.clone().into(),
+          String::from("commentable_id") => commentable_id.clone().into(),
+          String::from("expires_at") => expires_at.into(),
+        }
+      })?;
+    }
+    Ok(())
+  }
+
+  pub fn unsubscribe(db: &dyn Storage, connection_id: ConnectionId, commentable_id: CommentableId) -> Result<(), DbError> {
+    Self::batch_delete(db, subscription_keys(&connection_id, &commentable_id).to_vec())
+  }
+
+  // Removes all subscriptions of a closed connection
+  pub fn disconnect(db: &dyn Storage, connection_id: ConnectionId) -> Result<(), DbError> {
+    let subscriptions = Self::query(db, Query::id_prefix(connection_key(&connection_id), SUBSCRIPTION_ID_PREFIX.to_string()))?
+      .into_iter()
+      .map(Self::new)
+      .collect::<Result<Vec<Self>, DbError>>()?;
+
+    let keys = subscriptions
+      .iter()
+      .flat_map(|subscription| subscription_keys(&connection_id, &subscription.commentable_id).to_vec())
+      .collect::<Vec<(PrimaryKey, SortKey)>>();
+
+    if keys.is_empty() {
+      Ok(())
+    } else {
+      Self::batch_delete(db, keys)
+    }
+  }
+
+  pub fn list_subscribers(db: &dyn Storage, commentable_id: CommentableId) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::id_prefix(commentable_id, CONNECTION_ID_PREFIX.to_string()))?
+      .into_iter()
+      .map(Self::new)
+      .collect()
+  }
+}
+
+fn connection_key(connection_id: &str) -> PrimaryKey {
+  format!("{}{}", CONNECTION_ID_PREFIX, connection_id)
+}
+
+fn subscription_keys(connection_id: &str, commentable_id: &str) -> [(PrimaryKey, SortKey); 2] {
+  [
+    (commentable_id.to_string(), connection_key(connection_id)),
+    (connection_key(connection_id), format!("{}{}", SUBSCRIPTION_ID_PREFIX, commentable_id)),
+  ]
+}
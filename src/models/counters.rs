@@ -0,0 +1,96 @@
+use maplit::hashmap;
+use serde::Serialize;
+
+use crate::models::comment::Comment;
+use crate::storage::{Condition, Storage, Update};
+use crate::utils::db::{
+  CommentableId,
+  DynamoDbModel,
+  DynamoDbListableModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  attribute_value,
+  VERSION_FIELD_NAME,
+};
+
+pub static COUNTERS_ID: &str = "COUNTERS";
+// Recounts racing with added or deleted comments are retried a few times before giving up
+static MAX_RECOUNT_ATTEMPTS: usize = 3;
+
+// Numbers of comments of a commentable, kept up to date by comments/add and comments/delete.
+// Deleted comments aren't counted, even if they are still listed because of their replies.
+#[derive(Serialize, Debug)]
+pub struct Counters {
+  pub primary_key: CommentableId,
+  pub id: String,
+  pub total_comments: i64,
+  pub top_level_comments: i64,
+  pub version: i64,
+}
+
+impl DynamoDbModel for Counters {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      total_comments: attributes.optional_number("total_comments").unwrap_or(0),
+      top_level_comments: attributes.optional_number("top_level_comments").unwrap_or(0),
+      version: attributes.optional_number(VERSION_FIELD_NAME).unwrap_or(0),
+    })
+  }
+}
+
+impl Counters {
+  // Adds (or subtracts) the comment to the counters of its commentable,
+  // written in the same transaction as the comment itself
+  pub fn count_comment_update(comment: &Comment, value: i64) -> Update {
+    let update = Update::default().add("total_comments", value).add(VERSION_FIELD_NAME, 1);
+    if comment.replies_to.is_none() {
+      update.add("top_level_comments", value)
+    } else {
+      update
+    }
+  }
+
+  // Counters of commentables without comments (or counters) are missing from the results
+  pub fn batch_find_for_commentables(db: &dyn Storage, commentable_ids: Vec<CommentableId>) -> Result<Vec<Self>, DbError> {
+    Self::batch_find(db, commentable_ids.into_iter().map(|id| (id, COUNTERS_ID.to_string())).collect())
+  }
+
+  // Counts comments of the commentable from scratch, used for commentables with comments added
+  // before the counters were introduced or with counters that went out of sync.
+  // The counters are only saved when no comment has been counted since they were read,
+  // otherwise comments are counted again (failing with DbError::ConditionFailed after a few attempts).
+  pub fn recount(db: &dyn Storage, commentable_id: CommentableId) -> Result<Self, DbError> {
+    let mut result = Err(DbError::ConditionFailed);
+    for _ in 0..MAX_RECOUNT_ATTEMPTS {
+      result = Self::try_recount(db, commentable_id.clone());
+      match result {
+        Err(DbError::ConditionFailed) => continue,
+        _ => break,
+      }
+    }
+    result
+  }
+
+  fn try_recount(db: &dyn Storage, commentable_id: CommentableId) -> Result<Self, DbError> {
+    // Read before the comments, so comments counted in the meantime fail the condition
+    let counters = db.get(commentable_id.clone(), COUNTERS_ID.to_string())?.map(Self::new).transpose()?;
+    let comments = Comment::list(db, commentable_id.clone())?
+      .into_iter()
+      .filter(|comment| comment.is_deleted != Some(true))
+      .collect::<Vec<Comment>>();
+    let top_level_comments = comments.iter().filter(|comment| comment.replies_to.is_none()).count() as i64;
+
+    let condition = match counters {
+      Some(counters) => Condition::NumberEquals(VERSION_FIELD_NAME.to_string(), counters.version),
+      None => Condition::NotExists,
+    };
+    let attributes = db.update(commentable_id, COUNTERS_ID.to_string(), Update::set(hashmap!{
+      String::from("total_comments") => attribute_value(comments.len() as i64),
+      String::from("top_level_comments") => attribute_value(top_level_comments),
+    }).add(VERSION_FIELD_NAME, 1).condition(condition))?;
+    Self::new(attributes)
+  }
+}
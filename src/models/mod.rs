@@ -1,3 +1,11 @@
 pub mod user;
+pub mod session;
 pub mod comment;
 pub mod reaction;
+pub mod tombstone;
+pub mod connection;
+pub mod snapshot;
+pub mod counters;
+pub mod revision;
+pub mod commentable;
+pub mod cleanup;
@@ -1,24 +1,20 @@
+use std::collections::HashMap;
+
 use chrono::{DateTime, Utc};
-use maplit::hashmap;
-use rusoto_dynamodb::{
-  DynamoDbClient,
-  QueryInput,
-};
 use serde::Serialize;
 
-use crate::models::comment::CommentId;
+use crate::models::comment::{Comment, CommentId};
 use crate::models::user::UserId;
+use crate::storage::{Index, Query, Storage};
 use crate::utils::db::{
-  COMMENTABLE_RS_TABLE_NAME,
-  REACTIONS_INDEX_NAME,
   CommentableId,
   DynamoDbModel,
   DynamoDbListableModel,
   DynamoDbAttributes,
   DynamoDbRecord,
   DbError,
-  attribute_value,
   hash,
+  record_keys,
 };
 
 pub type ReactionId = String;
@@ -56,23 +52,44 @@ impl DynamoDbListableModel for Reaction {
 }
 
 impl Reaction {
-  pub fn remove_all_for_comment(db: &DynamoDbClient, commentable_id: CommentableId, comment_id: CommentId) -> Result<(), DbError> {
-    let reactions =
-      Self::query(&db, QueryInput {
-        table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-        index_name: Some(REACTIONS_INDEX_NAME.to_string()),
-        key_condition_expression: String::from("primary_key = :v1 and comment_id = :v2").into(),
-        expression_attribute_values: hashmap!{
-          String::from(":v1") => attribute_value(commentable_id),
-          String::from(":v2") => attribute_value(comment_id),
-        }.into(),
-        ..Default::default()
-      })?.drain(..)
-         .map(|mut key: DynamoDbAttributes| (key.string("primary_key").unwrap(), key.string("id").unwrap()))
-         .collect::<Vec<(CommentableId, ReactionId)>>();
+  // All reactions of the user to comments of the commentable
+  pub fn list_for_user(db: &dyn Storage, commentable_id: CommentableId, user_id: UserId) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::index(Index::Users, commentable_id, user_id))?
+      .into_iter()
+      // The index contains comments of the user as well
+      .filter(|item| item.get("id").and_then(|id| id.s.as_ref()).filter(|id| id.starts_with(REACTION_ID_PREFIX)).is_some())
+      .map(Self::new)
+      .collect()
+  }
+
+  // Recounts reactions of every comment of the commentable and fixes counters that are out of sync.
+  // Returns the number of repaired comments.
+  pub fn repair_counts(db: &dyn Storage, commentable_id: CommentableId) -> Result<usize, DbError> {
+    let mut counts: HashMap<CommentId, HashMap<ReactionType, i64>> = HashMap::new();
+    for reaction in Self::list(db, commentable_id.clone())? {
+      *counts.entry(reaction.comment_id).or_default().entry(reaction.reaction_type).or_insert(0) += 1;
+    }
+
+    let mut repaired = 0;
+    for mut comment in Comment::list(db, commentable_id)? {
+      let comment_counts = counts.remove(&comment.id).unwrap_or_default();
+      if comment.reaction_counts != comment_counts {
+        comment.set_reaction_counts(db, comment_counts)?;
+        repaired += 1;
+      }
+    }
+    Ok(repaired)
+  }
+
+  pub fn keys_for_comment(db: &dyn Storage, commentable_id: CommentableId, comment_id: CommentId) -> Result<Vec<(CommentableId, ReactionId)>, DbError> {
+    record_keys(Self::query(db, Query::index(Index::Reactions, commentable_id, comment_id))?)
+  }
+
+  pub fn remove_all_for_comment(db: &dyn Storage, commentable_id: CommentableId, comment_id: CommentId) -> Result<(), DbError> {
+    let reactions = Self::keys_for_comment(db, commentable_id, comment_id)?;
 
-    if reactions.len() > 0 {
-      Reaction::batch_delete(&db, reactions)
+    if !reactions.is_empty() {
+      Reaction::batch_delete(db, reactions)
     } else {
       Ok(())
     }
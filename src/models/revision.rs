@@ -0,0 +1,82 @@
+use chrono::{DateTime, Utc};
+use maplit::hashmap;
+use serde::Serialize;
+
+use crate::models::comment::{Comment, CommentId};
+use crate::storage::{Query, Storage};
+use crate::utils::db::{
+  CommentableId,
+  DynamoDbModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  IntoDynamoDbAttributes,
+  record_keys,
+  sortable_timestamp,
+};
+
+pub static REVISION_ID_PREFIX: &str = "REVISION_";
+
+// The body of a comment before one of its edits.
+// The edited comment ID isn't stored as "comment_id", which would add revisions to the reactions index.
+#[derive(Serialize, Debug)]
+pub struct Revision {
+  pub primary_key: CommentableId,
+  pub id: String,
+  pub revision_of: CommentId,
+  pub body: String,
+  // The time of the edit which replaced this body
+  pub created_at: DateTime<Utc>,
+}
+
+impl DynamoDbModel for Revision {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      revision_of: attributes.string("revision_of")?,
+      body: attributes.string("body")?,
+      created_at: attributes.timestamp("created_at")?,
+    })
+  }
+}
+
+impl Revision {
+  // The current body of the comment, saved along with the edit which replaces it.
+  // Each edit bumps the version, so it tells apart (and orders) revisions of edits made in the same millisecond.
+  pub fn attributes_for_comment(comment: &Comment, edited_at: DateTime<Utc>) -> IntoDynamoDbAttributes {
+    IntoDynamoDbAttributes {
+      attributes: hashmap!{
+        String::from("primary_key") => comment.primary_key.clone().into(),
+        String::from("id") => format!("{}{}_{:010}", id_prefix(&comment.id), sortable_timestamp(edited_at), comment.version).into(),
+        String::from("revision_of") => comment.id.clone().into(),
+        String::from("body") => comment.body.clone().into(),
+        String::from("created_at") => edited_at.to_rfc3339().into(),
+      }
+    }
+  }
+
+  // Revisions of the comment, oldest first
+  pub fn list_for_comment(db: &dyn Storage, commentable_id: CommentableId, comment_id: &CommentId) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::id_prefix(commentable_id, id_prefix(comment_id)))?
+      .into_iter()
+      .map(Self::new)
+      .collect()
+  }
+
+  pub fn keys_for_comment(db: &dyn Storage, commentable_id: CommentableId, comment_id: &CommentId) -> Result<Vec<(CommentableId, String)>, DbError> {
+    record_keys(Self::query(db, Query::id_prefix(commentable_id, id_prefix(comment_id)))?)
+  }
+
+  pub fn remove_all_for_comment(db: &dyn Storage, commentable_id: CommentableId, comment_id: &CommentId) -> Result<(), DbError> {
+    let keys = Self::keys_for_comment(db, commentable_id, comment_id)?;
+    if keys.is_empty() {
+      return Ok(());
+    }
+    Self::batch_delete(db, keys)
+  }
+}
+
+fn id_prefix(comment_id: &CommentId) -> String {
+  format!("{}{}_", REVISION_ID_PREFIX, comment_id)
+}
@@ -0,0 +1,241 @@
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+use maplit::hashmap;
+use ring::constant_time::verify_slices_are_equal;
+use ring::rand::{SecureRandom, SystemRandom};
+use serde::Serialize;
+
+use crate::models::user::{AuthToken, UserId, TOKEN_DELIMITER};
+use crate::storage::{Condition, Query, Storage, Update};
+use crate::utils::config;
+use crate::utils::db::{
+  attribute_value,
+  hash,
+  record_keys,
+  DynamoDbModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  IntoAttributeValue,
+  IntoDynamoDbAttributes,
+};
+
+pub type SessionId = String;
+
+pub static SESSION_ID_PREFIX: &str = "SESSION_";
+
+// Sessions aren't marked as used more often than this, so that most requests don't need a write
+static LAST_USED_PRECISION_MINUTES: i64 = 5;
+static MAX_LABEL_LENGTH: usize = 100;
+// Lengths of random parts of tokens & salts, in bytes
+static SESSION_ID_BYTES: usize = 16;
+static TOKEN_SECRET_BYTES: usize = 32;
+static SALT_BYTES: usize = 16;
+
+// A signed in device of a user, stored along with the user (primary_key = user ID).
+// Tokens start with the hashed email of the user and the random part of the session ID, so that the session
+// can be found without an index: "<hashed email>-=#=-<session ID>-=#=-<random secret>".
+// Only salted hashes of the tokens are stored, the tokens themselves are only known to the client.
+#[derive(Serialize, Debug)]
+pub struct Session {
+  pub primary_key: UserId,
+  pub id: SessionId,
+  // The device (or browser) the user signed in with
+  pub label: String,
+  pub token_salt: String,
+  pub auth_token_hash: String,
+  pub auth_token_expires_at: DateTime<Utc>,
+  pub refresh_token_hash: String,
+  pub refresh_token_expires_at: DateTime<Utc>,
+  pub created_at: DateTime<Utc>,
+  pub last_used_at: DateTime<Utc>,
+}
+
+impl DynamoDbModel for Session {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      label: attributes.string("label")?,
+      token_salt: attributes.string("token_salt")?,
+      auth_token_hash: attributes.string("auth_token_hash")?,
+      auth_token_expires_at: attributes.timestamp("auth_token_expires_at")?,
+      refresh_token_hash: attributes.string("refresh_token_hash")?,
+      refresh_token_expires_at: attributes.timestamp("refresh_token_expires_at")?,
+      created_at: attributes.timestamp("created_at")?,
+      last_used_at: attributes.timestamp("last_used_at")?,
+    })
+  }
+}
+
+// Tokens of a session, returned to the client when they're issued
+pub struct Tokens {
+  pub auth_token: AuthToken,
+  pub refresh_token: String,
+}
+
+// The user & session a token was issued for
+pub fn parse_token(token: &str) -> Option<(UserId, SessionId)> {
+  match token.split(TOKEN_DELIMITER).collect::<Vec<&str>>().as_slice() {
+    [user_hash, session_hash, _] if !user_hash.is_empty() && !session_hash.is_empty() =>
+      Some((format!("USER_{}", user_hash), format!("{}{}", SESSION_ID_PREFIX, session_hash))),
+    _ => None,
+  }
+}
+
+// Hex-encoded bytes from the system's CSPRNG
+fn random_hex(length: usize) -> Result<String, DbError> {
+  let mut bytes = vec![0; length];
+  SystemRandom::new()
+    .fill(&mut bytes)
+    .map_err(|_| DbError::Error(String::from("Error generating random bytes")))?;
+  Ok(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn hash_token(salt: &str, token: &str) -> String {
+  hash(&format!("{}{}", salt, token))
+}
+
+fn new_token(user_id: &UserId, session_id: &SessionId) -> Result<String, DbError> {
+  Ok(format!("{}{}{}{}{}",
+    user_id.trim_start_matches("USER_"),
+    TOKEN_DELIMITER,
+    session_id.trim_start_matches(SESSION_ID_PREFIX),
+    TOKEN_DELIMITER,
+    random_hex(TOKEN_SECRET_BYTES)?,
+  ))
+}
+
+// Fresh access & refresh tokens, expiring after the configured TTLs (see config::access_token_ttl_minutes),
+// along with the attributes storing their hashes
+fn new_tokens(user_id: &UserId, session_id: &SessionId) -> Result<(HashMap<String, IntoAttributeValue>, Tokens), DbError> {
+  let now = Utc::now();
+  let salt = random_hex(SALT_BYTES)?;
+  let tokens = Tokens {
+    auth_token: new_token(user_id, session_id)?,
+    refresh_token: new_token(user_id, session_id)?,
+  };
+  let attributes = hashmap!{
+    String::from("auth_token_hash") => hash_token(&salt, &tokens.auth_token).into(),
+    String::from("auth_token_expires_at") =>
+      (now + Duration::minutes(config::access_token_ttl_minutes())).to_rfc3339().into(),
+    String::from("refresh_token_hash") => hash_token(&salt, &tokens.refresh_token).into(),
+    String::from("refresh_token_expires_at") =>
+      (now + Duration::days(config::refresh_token_ttl_days())).to_rfc3339().into(),
+    String::from("token_salt") => salt.into(),
+  };
+  Ok((attributes, tokens))
+}
+
+// Compares hashes in constant time, so that the time of the comparison doesn't reveal how much of them matched
+fn hashes_match(expected: &str, actual: &str) -> bool {
+  verify_slices_are_equal(expected.as_bytes(), actual.as_bytes()).is_ok()
+}
+
+impl Session {
+  pub fn start(db: &dyn Storage, user_id: &UserId, label: &str) -> Result<(Self, Tokens), DbError> {
+    let now = Utc::now();
+    let session_id = format!("{}{}", SESSION_ID_PREFIX, random_hex(SESSION_ID_BYTES)?);
+    let (tokens_attributes, tokens) = new_tokens(user_id, &session_id)?;
+    let mut attributes = hashmap!{
+      String::from("primary_key") => user_id.clone().into(),
+      String::from("id") => session_id.into(),
+      String::from("label") => label.chars().take(MAX_LABEL_LENGTH).collect::<String>().into(),
+      String::from("created_at") => now.to_rfc3339().into(),
+      String::from("last_used_at") => now.to_rfc3339().into(),
+    };
+    attributes.extend(tokens_attributes);
+    Ok((Self::create_if_absent(db, IntoDynamoDbAttributes { attributes })?, tokens))
+  }
+
+  // Sessions of the user, including expired ones
+  pub fn list(db: &dyn Storage, user_id: UserId) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::id_prefix(user_id, SESSION_ID_PREFIX.to_string()))?
+      .into_iter()
+      .map(Self::new)
+      .collect()
+  }
+
+  // Replaces both tokens of the session, invalidating the previous ones.
+  // Returns DbError::ConditionFailed when the session has ended or the refresh token has been used in the meantime,
+  // so that concurrent requests can't exchange the same refresh token twice.
+  pub fn refresh(&self, db: &dyn Storage) -> Result<(Self, Tokens), DbError> {
+    let (mut attributes, tokens) = new_tokens(&self.primary_key, &self.id)?;
+    attributes.insert(String::from("last_used_at"), Utc::now().to_rfc3339().into());
+    let update = Update::set(IntoDynamoDbAttributes { attributes }.into())
+      .condition(Condition::StringEquals(String::from("refresh_token_hash"), self.refresh_token_hash.clone()));
+    Ok((Self::update(db, self.primary_key.clone(), self.id.clone(), update)?, tokens))
+  }
+
+  // Marks the session as used now
+  pub fn touch(&self, db: &dyn Storage) -> Result<(), DbError> {
+    if self.last_used_at + Duration::minutes(LAST_USED_PRECISION_MINUTES) > Utc::now() {
+      return Ok(());
+    }
+    let update = Update::set(hashmap!{
+      String::from("last_used_at") => attribute_value(Utc::now().to_rfc3339()),
+    });
+    db.update(self.primary_key.clone(), self.id.clone(), update.condition(Condition::Exists)).map(|_| ())
+  }
+
+  pub fn auth_token_matches(&self, token: &str) -> bool {
+    hashes_match(&self.auth_token_hash, &hash_token(&self.token_salt, token))
+  }
+
+  pub fn refresh_token_matches(&self, token: &str) -> bool {
+    hashes_match(&self.refresh_token_hash, &hash_token(&self.token_salt, token))
+  }
+
+  pub fn auth_token_expired(&self) -> bool {
+    self.auth_token_expires_at <= Utc::now()
+  }
+
+  pub fn refresh_token_expired(&self) -> bool {
+    self.refresh_token_expires_at <= Utc::now()
+  }
+
+  // Logs out all devices of the user
+  pub fn remove_all(db: &dyn Storage, user_id: UserId) -> Result<(), DbError> {
+    let keys = record_keys(Self::query(db, Query::id_prefix(user_id, SESSION_ID_PREFIX.to_string()))?)?;
+    if keys.is_empty() {
+      return Ok(());
+    }
+    Self::batch_delete(db, keys)
+  }
+
+  // Removes sessions which can't be refreshed anymore
+  pub fn remove_stale(db: &dyn Storage, user_id: UserId) -> Result<(), DbError> {
+    let keys = Self::list(db, user_id)?
+      .into_iter()
+      .filter(|session| session.refresh_token_expired())
+      .map(|session| (session.primary_key, session.id))
+      .collect::<Vec<_>>();
+    if keys.is_empty() {
+      return Ok(());
+    }
+    Self::batch_delete(db, keys)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::MemoryStorage;
+
+  #[test]
+  fn refresh_tokens_can_only_be_used_once() {
+    let db = MemoryStorage::new();
+    let user_id = String::from("USER_jane");
+    let (session, tokens) = Session::start(&db, &user_id, "Firefox").unwrap();
+
+    let (refreshed, new_tokens) = session.refresh(&db).unwrap();
+    assert!(refreshed.refresh_token_matches(&new_tokens.refresh_token));
+    assert!(!refreshed.refresh_token_matches(&tokens.refresh_token));
+    // Another request that read the session before the first refresh
+    match session.refresh(&db) {
+      Err(DbError::ConditionFailed) => (),
+      _ => panic!("the refresh token has been used twice"),
+    }
+  }
+}
@@ -0,0 +1,256 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{Read, Write};
+
+use chrono::Utc;
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use maplit::hashmap;
+use serde::{Serialize, Deserialize};
+
+use crate::models::comment::{Comment, CommentId};
+use crate::models::reaction::ReactionType;
+use crate::models::user::{User, UserId};
+use crate::storage::{number_attribute, Condition, Storage};
+use crate::utils::config;
+use crate::utils::db::{
+  CommentableId,
+  DynamoDbModel,
+  DynamoDbListableModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  IntoDynamoDbAttributes,
+  VERSION_FIELD_NAME,
+};
+
+pub static SNAPSHOT_ID: &str = "SNAPSHOT";
+// DynamoDB items are limited to 400 KB, bigger threads are listed without a snapshot
+static MAX_SNAPSHOT_BYTES: usize = 350 * 1024;
+// Concurrent changes to the same thread are retried a few times before the snapshot is dropped
+static MAX_UPDATE_ATTEMPTS: usize = 3;
+
+// Everything comments/list needs to display a thread, except for reactions of the current user
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Thread {
+  pub comments: BTreeMap<CommentId, ThreadComment>,
+  pub users: HashMap<UserId, ThreadUser>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreadComment {
+  pub body: String,
+  pub user_id: Option<UserId>,
+  pub replies_to: Option<CommentId>,
+  pub reaction_counts: HashMap<ReactionType, i64>,
+  // Missing from snapshots built before edits were tracked
+  #[serde(default)]
+  pub edited_at: Option<String>,
+  // Missing from snapshots built before comments were versioned
+  #[serde(default)]
+  pub version: i64,
+  pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ThreadUser {
+  pub name: String,
+  pub picture_url: String,
+}
+
+// A materialized view of all comments of a commentable, stored as compressed JSON in a single record.
+// Writes patch the snapshot after saving their changes, conditioned on its version so that concurrent
+// patches don't overwrite each other. Snapshots rebuilt while comments change may still end up out of
+// date - rebuilding them again (see bin/rebuild-snapshots.rs) brings them back in sync.
+#[derive(Serialize, Debug)]
+pub struct Snapshot {
+  pub primary_key: CommentableId,
+  pub id: String,
+  // Threads too large to be snapshotted are only marked as such, so listings don't rebuild them every time
+  pub thread: Option<Thread>,
+  pub version: i64,
+  pub built_at: String,
+}
+
+impl DynamoDbModel for Snapshot {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    let oversized = attributes.optional_bool("oversized").unwrap_or(false);
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      thread: if oversized { None } else { Some(decompress(&attributes.binary("thread")?)?) },
+      // Missing from snapshots saved before they were versioned
+      version: attributes.optional_number(VERSION_FIELD_NAME).unwrap_or(0),
+      built_at: attributes.string("built_at")?,
+    })
+  }
+}
+
+impl Thread {
+  pub fn add_comment(&mut self, comment: &Comment, user: &User) {
+    self.users.insert(user.id.clone(), ThreadUser {
+      name: user.name.clone(),
+      picture_url: user.picture_url.clone(),
+    });
+    self.comments.insert(comment.id.clone(), ThreadComment {
+      body: comment.body.clone(),
+      user_id: comment.user_id.clone(),
+      replies_to: comment.replies_to.clone(),
+      reaction_counts: comment.reaction_counts.clone(),
+      edited_at: comment.edited_at.map(|edited_at| edited_at.to_string()),
+      version: comment.version,
+      created_at: comment.created_at.to_string(),
+    });
+  }
+
+  pub fn edit_comment(&mut self, comment: &Comment) {
+    if let Some(thread_comment) = self.comments.get_mut(&comment.id) {
+      thread_comment.body = comment.body.clone();
+      thread_comment.edited_at = comment.edited_at.map(|edited_at| edited_at.to_string());
+      thread_comment.version = comment.version;
+    }
+  }
+
+  // Erased comments keep their place in the thread (see Comment#erase)
+  pub fn delete_comment(&mut self, comment_id: &CommentId, erased: Option<&Comment>) {
+    match erased {
+      Some(comment) => if let Some(thread_comment) = self.comments.get_mut(comment_id) {
+        thread_comment.body = comment.body.clone();
+        thread_comment.user_id = None;
+        thread_comment.edited_at = None;
+        thread_comment.version = comment.version;
+        thread_comment.reaction_counts = HashMap::new();
+      },
+      None => {
+        self.comments.remove(comment_id);
+      },
+    }
+  }
+
+  pub fn count_reaction(&mut self, comment_id: &CommentId, reaction_type: &ReactionType, value: i64) {
+    if let Some(thread_comment) = self.comments.get_mut(comment_id) {
+      // Just like Comment#count_reaction, counting reactions bumps the version
+      thread_comment.version += 1;
+      let count = thread_comment.reaction_counts.entry(reaction_type.clone()).or_insert(0);
+      *count += value;
+      if *count <= 0 {
+        thread_comment.reaction_counts.remove(reaction_type);
+      }
+    }
+  }
+}
+
+impl Snapshot {
+  pub fn find_for_commentable(db: &dyn Storage, commentable_id: CommentableId) -> Result<Option<Self>, DbError> {
+    db.get(commentable_id, SNAPSHOT_ID.to_string())?
+      .map(Self::new)
+      .transpose()
+  }
+
+  // Builds the thread from comments & users and saves it as the new snapshot.
+  // Returns None when the thread is too large to be snapshotted.
+  pub fn rebuild(db: &dyn Storage, commentable_id: CommentableId) -> Result<Option<Thread>, DbError> {
+    // Read before the comments, so the snapshot isn't saved over changes patched in the meantime
+    let version = db.get(commentable_id.clone(), SNAPSHOT_ID.to_string())?
+      .map(|snapshot| number_attribute(&snapshot, VERSION_FIELD_NAME).unwrap_or(0));
+    let comments = Comment::list(db, commentable_id.clone())?;
+    let user_ids = comments.iter().filter_map(|comment| comment.user_id.as_ref()).collect::<HashSet<&UserId>>();
+    let users = User::batch_get(db, user_ids)?;
+
+    let mut thread = Thread::default();
+    for user in users {
+      thread.users.insert(user.id, ThreadUser { name: user.name, picture_url: user.picture_url });
+    }
+    for comment in comments {
+      thread.comments.insert(comment.id, ThreadComment {
+        body: comment.body,
+        user_id: comment.user_id,
+        replies_to: comment.replies_to,
+        reaction_counts: comment.reaction_counts,
+        edited_at: comment.edited_at.map(|edited_at| edited_at.to_string()),
+        version: comment.version,
+        created_at: comment.created_at.to_string(),
+      });
+    }
+
+    let saved = match version {
+      Some(version) => Self::save(db, commentable_id, &thread, version),
+      None => Self::save_new(db, commentable_id, &thread),
+    };
+    match saved {
+      // The thread is just as fresh as the snapshot saved in the meantime
+      Ok(true) | Err(DbError::ConditionFailed) => Ok(Some(thread)),
+      Ok(false) => Ok(None),
+      Err(err) => Err(err),
+    }
+  }
+
+  // Applies a change to an existing snapshot (missing snapshots are built when comments are listed).
+  // Does nothing unless snapshots are enabled.
+  pub fn update<F>(db: &dyn Storage, commentable_id: CommentableId, change: F) -> Result<(), DbError>
+  where F: Fn(&mut Thread) {
+    if !config::snapshots_enabled() {
+      return Ok(());
+    }
+    let mut result = Err(DbError::ConditionFailed);
+    for _ in 0..MAX_UPDATE_ATTEMPTS {
+      result = Self::find_for_commentable(db, commentable_id.clone()).and_then(|snapshot| match snapshot {
+        Some(Snapshot { thread: Some(mut thread), version, .. }) => {
+          change(&mut thread);
+          Self::save(db, commentable_id.clone(), &thread, version).map(|_| ())
+        },
+        _ => Ok(()),
+      });
+      match result {
+        Err(DbError::ConditionFailed) => continue,
+        _ => break,
+      }
+    }
+    // Make sure an outdated snapshot isn't used, it will be rebuilt by the next listing
+    if result.is_err() {
+      let _ = db.delete(commentable_id, SNAPSHOT_ID.to_string());
+    }
+    result
+  }
+
+  // Replaces the snapshot at the given version, returns false when the thread was too large to be saved
+  fn save(db: &dyn Storage, commentable_id: CommentableId, thread: &Thread, version: i64) -> Result<bool, DbError> {
+    let (item, saved) = Self::item(commentable_id, thread, version + 1)?;
+    db.put_if(item, Condition::NumberEquals(VERSION_FIELD_NAME.to_string(), version)).map(|_| saved)
+  }
+
+  fn save_new(db: &dyn Storage, commentable_id: CommentableId, thread: &Thread) -> Result<bool, DbError> {
+    let (item, saved) = Self::item(commentable_id, thread, 1)?;
+    db.put_if(item, Condition::NotExists).map(|_| saved)
+  }
+
+  fn item(commentable_id: CommentableId, thread: &Thread, version: i64) -> Result<(DynamoDbAttributes, bool), DbError> {
+    let thread = compress(thread)?;
+    let mut attributes = hashmap!{
+      String::from("primary_key") => commentable_id.into(),
+      String::from("id") => SNAPSHOT_ID.to_string().into(),
+      String::from(VERSION_FIELD_NAME) => version.into(),
+      String::from("built_at") => Utc::now().to_rfc3339().into(),
+    };
+    let saved = thread.len() <= MAX_SNAPSHOT_BYTES;
+    if saved {
+      attributes.insert(String::from("thread"), thread.into());
+    } else {
+      attributes.insert(String::from("oversized"), true.into());
+    }
+    Ok((IntoDynamoDbAttributes { attributes }.into(), saved))
+  }
+}
+
+fn compress(thread: &Thread) -> Result<Vec<u8>, DbError> {
+  let json = serde_json::to_vec(thread).map_err(|err| DbError::Error(err.to_string()))?;
+  let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+  encoder.write_all(&json).map_err(|err| DbError::Error(err.to_string()))?;
+  encoder.finish().map_err(|err| DbError::Error(err.to_string()))
+}
+
+fn decompress(bytes: &[u8]) -> Result<Thread, DbError> {
+  let mut json = Vec::new();
+  GzDecoder::new(bytes).read_to_end(&mut json).map_err(|err| DbError::RecordInvalid(err.to_string()))?;
+  serde_json::from_slice(&json).map_err(|err| DbError::RecordInvalid(err.to_string()))
+}
@@ -0,0 +1,73 @@
+use chrono::{Duration, Utc};
+use maplit::hashmap;
+use serde::Serialize;
+
+use crate::models::comment::CommentId;
+use crate::storage::{Filter, Query, Storage};
+use crate::utils::db::{
+  CommentableId,
+  DynamoDbModel,
+  DynamoDbListableModel,
+  DynamoDbAttributes,
+  DynamoDbRecord,
+  DbError,
+  IntoDynamoDbAttributes,
+  sortable_timestamp,
+};
+
+pub type TombstoneId = String;
+
+pub static TOMBSTONE_ID_PREFIX: &str = "DELETED_";
+// Tombstones expire after this many days (using DynamoDB TTL), so sync tokens can't be older than that
+pub static TOMBSTONE_TTL_DAYS: i64 = 7;
+
+// Marks a comment as deleted for incremental syncs of comments/list.
+// The deleted comment ID isn't stored as "comment_id", which would add tombstones to the reactions index.
+#[derive(Serialize, Debug)]
+pub struct Tombstone {
+  pub primary_key: CommentableId,
+  pub id: TombstoneId,
+  pub deleted_id: CommentId,
+  pub changed_at: String,
+}
+
+impl DynamoDbModel for Tombstone {
+  fn new(mut attributes: DynamoDbAttributes) -> Result<Self, DbError> {
+    Ok(Self {
+      primary_key: attributes.string("primary_key")?,
+      id: attributes.string("id")?,
+      deleted_id: attributes.string("deleted_id")?,
+      changed_at: attributes.string("changed_at")?,
+    })
+  }
+}
+
+impl DynamoDbListableModel for Tombstone {
+  fn id_prefix() -> String {
+    TOMBSTONE_ID_PREFIX.to_string()
+  }
+}
+
+impl Tombstone {
+  // Saved in the same transaction as the deletion of the comment
+  pub fn attributes_for_comment(commentable_id: CommentableId, comment_id: CommentId) -> IntoDynamoDbAttributes {
+    let now = Utc::now();
+    IntoDynamoDbAttributes {
+      attributes: hashmap!{
+        String::from("primary_key") => commentable_id.into(),
+        String::from("id") => format!("{}{}", TOMBSTONE_ID_PREFIX, comment_id).into(),
+        String::from("deleted_id") => comment_id.into(),
+        String::from("changed_at") => sortable_timestamp(now).into(),
+        String::from("expires_at") => (now + Duration::days(TOMBSTONE_TTL_DAYS)).timestamp().into(),
+      }
+    }
+  }
+
+  pub fn list_since(db: &dyn Storage, commentable_id: CommentableId, since: String) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::id_prefix(commentable_id, Self::id_prefix())
+      .filter(Filter::AttributeGreaterThan(String::from("changed_at"), since)))?
+      .into_iter()
+      .map(Self::new)
+      .collect()
+  }
+}
@@ -2,22 +2,16 @@ use std::fmt;
 use std::collections::HashSet;
 
 use chrono::{DateTime, Utc};
-use maplit::hashmap;
-use rusoto_dynamodb::{
-  DynamoDb,
-  DynamoDbClient,
-  BatchGetItemInput,
-  KeysAndAttributes,
-};
+use ring::constant_time::verify_slices_are_equal;
 use serde::Serialize;
 
+use crate::storage::{Condition, Storage, Update};
+use crate::utils::config;
 use crate::utils::db::{
-  attribute_value,
   DynamoDbModel,
   DynamoDbAttributes,
   DynamoDbRecord,
   DbError,
-  COMMENTABLE_RS_TABLE_NAME,
 };
 
 pub type UserId = String;
@@ -32,8 +26,11 @@ pub struct User {
   pub email: String,
   pub name: String,
   pub picture_url: String,
-  pub auth_token: String,
   pub created_at: DateTime<Utc>,
+  // Users signed in before sessions were introduced still store their plain token, which stays valid
+  // until they sign in again
+  #[serde(skip)]
+  pub legacy_auth_token: Option<AuthToken>,
 }
 
 impl DynamoDbModel for User {
@@ -43,41 +40,48 @@ impl DynamoDbModel for User {
       id: attributes.string("id")?,
       email: attributes.string("email")?,
       name: attributes.string("name")?,
-      auth_token: attributes.string("auth_token")?,
       picture_url: attributes.string("picture_url")?,
-      created_at: attributes.timestamp("created_at")?
+      created_at: attributes.timestamp("created_at")?,
+      legacy_auth_token: attributes.optional_string("auth_token"),
     })
   }
 }
 
+// The user a token issued before sessions were introduced belongs to: "<hashed email>-=#=-<hashed timestamp>"
+// (see session::parse_token for the current tokens)
+pub fn parse_legacy_token(token: &str) -> Option<UserId> {
+  match token.split(TOKEN_DELIMITER).collect::<Vec<&str>>().as_slice() {
+    [user_hash, secret] if !user_hash.is_empty() && !secret.is_empty() => Some(format!("USER_{}", user_hash)),
+    _ => None,
+  }
+}
+
 impl User {
-  pub fn batch_get(db: &DynamoDbClient, mut ids: HashSet<&UserId>) -> Result<Vec<Self>, DbError> {
-    let mut users: Vec<Self> = vec![];
-    /* 100 is the maximum amount of records allowed
-       per single BatchGetItem operation in DynamoDB */
-    for slice in ids.drain().collect::<Vec<_>>().chunks(100) {
-      users.append(
-        &mut db.batch_get_item(BatchGetItemInput {
-          request_items: hashmap! {
-            String::from(COMMENTABLE_RS_TABLE_NAME) => KeysAndAttributes {
-              keys: slice.iter().map(|id| hashmap! {
-                String::from("primary_key") => attribute_value(id.to_string()),
-                String::from("id") => attribute_value(id.to_string()),
-              }).collect(),
-              ..Default::default()
-            }
-          },
-          ..Default::default()
-        }).sync()
-          .map_err(|err| DbError::Error(err.to_string()))?
-          .responses.unwrap()
-          .remove(COMMENTABLE_RS_TABLE_NAME).unwrap()
-          .drain(..)
-          .map(|user_attributes| User::new(user_attributes))
-          .collect::<Result<Vec<Self>, DbError>>()?
-      );
-    }
-    Ok(users)
+  pub fn batch_get(db: &dyn Storage, mut ids: HashSet<&UserId>) -> Result<Vec<Self>, DbError> {
+    db.batch_get(ids.drain().map(|id| (id.to_string(), id.to_string())).collect())?
+      .drain(..)
+      .map(User::new)
+      .collect::<Result<Vec<Self>, DbError>>()
+  }
+
+  // Compares in constant time, so that the time of the comparison doesn't reveal how much of the token matched
+  pub fn legacy_auth_token_matches(&self, token: &str) -> bool {
+    self.legacy_auth_token
+      .as_ref()
+      .is_some_and(|legacy_token| verify_slices_are_equal(legacy_token.as_bytes(), token.as_bytes()).is_ok())
+  }
+
+  // Ends the session the user had before sessions were introduced
+  pub fn remove_legacy_tokens(&mut self, db: &dyn Storage) -> Result<(), DbError> {
+    let update = Update::default().remove("auth_token").condition(Condition::Exists);
+    db.update(self.primary_key.clone(), self.id.clone(), update)?;
+    self.legacy_auth_token = None;
+    Ok(())
+  }
+
+  pub fn is_admin(&self) -> bool {
+    let admins = config::admins();
+    admins.contains(&self.email.to_lowercase()) || admins.contains(&self.id.to_lowercase())
   }
 }
 
@@ -86,3 +90,41 @@ impl fmt::Display for User {
     write!(f, "{} ({})", self.name, self.id)
   }
 }
+
+#[cfg(test)]
+mod tests {
+  use maplit::hashmap;
+
+  use super::*;
+  use crate::storage::MemoryStorage;
+  use crate::utils::db::{attribute_value, hash};
+
+  // Users signed in on the baseline, with a token generated like bin/auth.rs used to
+  fn create_legacy_user(db: &dyn Storage) -> AuthToken {
+    let auth_token = format!("{}{}{}", "jane", TOKEN_DELIMITER, hash(&Utc::now().to_string()));
+    db.put(hashmap!{
+      String::from("primary_key") => attribute_value(String::from("USER_jane")),
+      String::from("id") => attribute_value(String::from("USER_jane")),
+      String::from("email") => attribute_value(String::from("jane@example.com")),
+      String::from("name") => attribute_value(String::from("Jane")),
+      String::from("picture_url") => attribute_value(String::from("")),
+      String::from("auth_token") => attribute_value(auth_token.clone()),
+      String::from("created_at") => attribute_value(Utc::now().to_rfc3339()),
+    }).unwrap();
+    auth_token
+  }
+
+  #[test]
+  fn legacy_tokens_are_removed() {
+    let db = MemoryStorage::new();
+    let auth_token = create_legacy_user(&db);
+    let mut user = User::find(&db, String::from("USER_jane"), String::from("USER_jane")).unwrap().unwrap();
+    assert!(user.legacy_auth_token_matches(&auth_token));
+    assert!(!user.legacy_auth_token_matches("jane-=#=-guess"));
+
+    user.remove_legacy_tokens(&db).unwrap();
+    let item = db.get(String::from("USER_jane"), String::from("USER_jane")).unwrap().unwrap();
+    assert!(!item.contains_key("auth_token"));
+    assert!(!User::new(item).unwrap().legacy_auth_token_matches(&auth_token));
+  }
+}
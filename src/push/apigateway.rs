@@ -0,0 +1,61 @@
+use std::time::Duration;
+
+use futures::future::{self, Future};
+use rusoto_core::{Client, Region, RusotoError};
+use rusoto_core::request::HttpResponse;
+use rusoto_core::signature::SignedRequest;
+
+use crate::models::connection::ConnectionId;
+use crate::push::{Push, PushError};
+
+// Pushes to connections that don't respond in time are given up, so they can't stall the writes sending them
+static SEND_TIMEOUT_MILLIS: u64 = 1000;
+
+// Pushes messages through the connection management API of an API Gateway WebSocket API
+pub struct ApiGatewayPush {
+  client: Client,
+  region: Region,
+  hostname: String,
+  stage: String,
+}
+
+impl ApiGatewayPush {
+  // Expects the URL of the API stage, i.e. https://{api-id}.execute-api.{region}.amazonaws.com/{stage}
+  pub fn new(endpoint: &str) -> Result<Self, PushError> {
+    let url = endpoint.trim_start_matches("https://").trim_end_matches('/');
+    let mut parts = url.splitn(2, '/');
+    match (parts.next(), parts.next()) {
+      (Some(hostname), Some(stage)) if !hostname.is_empty() && !stage.is_empty() => Ok(Self {
+        client: Client::shared(),
+        region: Region::default(),
+        hostname: hostname.to_string(),
+        stage: stage.to_string(),
+      }),
+      _ => Err(PushError::Error(format!("Invalid WebSocket API endpoint: {}", endpoint))),
+    }
+  }
+}
+
+fn response_status(response: HttpResponse) -> Box<dyn Future<Item = u16, Error = RusotoError<()>> + Send> {
+  Box::new(future::ok(response.status.as_u16()))
+}
+
+impl Push for ApiGatewayPush {
+  fn send(&self, connection_id: &ConnectionId, message: &str) -> Result<(), PushError> {
+    let path = format!("/{}/@connections/{}", self.stage, connection_id);
+    let mut request = SignedRequest::new("POST", "execute-api", &self.region, &path);
+    request.set_hostname(Some(self.hostname.clone()));
+    request.set_content_type(String::from("application/json"));
+    request.set_payload(Some(message.as_bytes().to_vec()));
+
+    let response = self.client.sign_and_dispatch(request, response_status)
+      .with_timeout(Duration::from_millis(SEND_TIMEOUT_MILLIS))
+      .sync();
+    match response {
+      Ok(200) => Ok(()),
+      Ok(410) => Err(PushError::Gone),
+      Ok(status) => Err(PushError::Error(format!("Unexpected response status: {}", status))),
+      Err(err) => Err(PushError::Error(format!("{:?}", err))),
+    }
+  }
+}
@@ -0,0 +1,41 @@
+use std::collections::HashSet;
+use std::sync::Mutex;
+
+use crate::models::connection::ConnectionId;
+use crate::push::{Push, PushError};
+
+// A push backend recording sent messages instead of delivering them.
+// Connections can be marked as closed to simulate clients that went away.
+#[derive(Default)]
+pub struct MemoryPush {
+  messages: Mutex<Vec<(ConnectionId, String)>>,
+  closed: Mutex<HashSet<ConnectionId>>,
+}
+
+impl MemoryPush {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  pub fn close(&self, connection_id: &str) {
+    self.closed.lock().unwrap().insert(connection_id.to_string());
+  }
+
+  // Returns and forgets all messages sent so far
+  pub fn take_messages(&self) -> Vec<(ConnectionId, String)> {
+    self.messages.lock().unwrap().drain(..).collect()
+  }
+}
+
+impl Push for MemoryPush {
+  fn send(&self, connection_id: &ConnectionId, message: &str) -> Result<(), PushError> {
+    if self.closed.lock().map_err(|err| PushError::Error(err.to_string()))?.contains(connection_id) {
+      return Err(PushError::Gone);
+    }
+    self.messages
+      .lock()
+      .map_err(|err| PushError::Error(err.to_string()))?
+      .push((connection_id.clone(), message.to_string()));
+    Ok(())
+  }
+}
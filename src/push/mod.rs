@@ -0,0 +1,230 @@
+// Real-time updates pushed to WebSocket clients subscribed to a commentable.
+//
+// Events are delivered through a `Push` backend: API Gateway's connection management API
+// in production, or an in-memory fake which records messages for local development & tests.
+
+use std::env;
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+
+use lazy_static::lazy_static;
+use serde::Serialize;
+
+use crate::models::comment::{Comment, CommentId};
+use crate::models::commentable::Commentable;
+use crate::models::connection::{Connection, ConnectionId};
+use crate::models::reaction::ReactionType;
+use crate::models::user::UserId;
+use crate::storage::Storage;
+use crate::utils::db::{CommentableId, DbError};
+
+pub mod apigateway;
+pub mod memory;
+
+pub use self::apigateway::ApiGatewayPush;
+pub use self::memory::MemoryPush;
+
+// Name of the ENV variable used to select the push backend ("apigateway", "memory" or "none")
+pub static PUSH_ENV_VAR: &str = "COMMENTABLE_RS_PUSH";
+// Name of the ENV variable containing the URL of the WebSocket API stage
+// (https://{api-id}.execute-api.{region}.amazonaws.com/{stage})
+pub static WEBSOCKET_ENDPOINT_ENV_VAR: &str = "COMMENTABLE_RS_WEBSOCKET_ENDPOINT";
+
+// Subscribers are pushed to from a few threads at once, so writes don't wait for each of them in turn
+static MAX_SEND_THREADS: usize = 8;
+
+lazy_static! {
+  // The in-memory backend is shared by every handler running in the same process
+  static ref MEMORY_PUSH: Arc<MemoryPush> = Arc::new(MemoryPush::new());
+}
+
+#[derive(Debug)]
+pub enum PushError {
+  // The client has disconnected, so the connection can be forgotten
+  Gone,
+  Error(String),
+}
+
+impl fmt::Display for PushError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      PushError::Gone => write!(f, "PushError::Gone"),
+      PushError::Error(msg) => write!(f, "PushError::Error -> {}", msg),
+    }
+  }
+}
+
+pub trait Push: Send + Sync {
+  fn send(&self, connection_id: &ConnectionId, message: &str) -> Result<(), PushError>;
+}
+
+// Used when real-time updates are disabled
+pub struct NoPush;
+
+impl Push for NoPush {
+  fn send(&self, _connection_id: &ConnectionId, _message: &str) -> Result<(), PushError> {
+    Ok(())
+  }
+}
+
+// Returns the push backend selected with the COMMENTABLE_RS_PUSH ENV variable
+pub fn connect() -> Result<Arc<dyn Push>, PushError> {
+  match env::var(PUSH_ENV_VAR).ok().as_deref() {
+    None | Some("") | Some("none") => Ok(Arc::new(NoPush)),
+    Some("apigateway") => {
+      let endpoint = env::var(WEBSOCKET_ENDPOINT_ENV_VAR)
+        .map_err(|_| PushError::Error(format!("{} is required by the apigateway push backend", WEBSOCKET_ENDPOINT_ENV_VAR)))?;
+      Ok(Arc::new(ApiGatewayPush::new(&endpoint)?))
+    },
+    Some("memory") => Ok(MEMORY_PUSH.clone()),
+    Some(backend) => Err(PushError::Error(format!("Unknown push backend: {}", backend))),
+  }
+}
+
+pub fn memory() -> Arc<MemoryPush> {
+  MEMORY_PUSH.clone()
+}
+
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event<'a> {
+  CommentAdded { comment: &'a Comment },
+  CommentEdited { comment: &'a Comment },
+  // Comments with replies are erased instead of being deleted
+  CommentDeleted { comment_id: &'a CommentId, erased: bool },
+  ReactionAdded { comment_id: &'a CommentId, reaction_type: &'a ReactionType, user_id: &'a UserId },
+  ReactionRemoved { comment_id: &'a CommentId, reaction_type: &'a ReactionType, user_id: &'a UserId },
+  CommentableUpdated { commentable: &'a Commentable },
+  CommentPinned { comment_id: &'a CommentId, pinned: bool },
+}
+
+#[derive(Serialize)]
+struct Message<'a> {
+  commentable_id: &'a CommentableId,
+  #[serde(flatten)]
+  event: &'a Event<'a>,
+}
+
+// Sends the event to every connection subscribed to the commentable, unsubscribing closed connections.
+// Errors are only logged, as the change has already been saved when events are pushed.
+pub fn broadcast(db: &dyn Storage, push: &dyn Push, commentable_id: &CommentableId, event: &Event) {
+  if let Err(err) = try_broadcast(db, push, commentable_id, event) {
+    eprintln!("Error pushing events of {}: {}", commentable_id, err);
+  }
+}
+
+fn try_broadcast(db: &dyn Storage, push: &dyn Push, commentable_id: &CommentableId, event: &Event) -> Result<(), DbError> {
+  let message = serde_json::to_string(&Message { commentable_id, event })
+    .map_err(|err| DbError::Error(err.to_string()))?;
+
+  let connections = Connection::list_subscribers(db, commentable_id.clone())?;
+  if connections.is_empty() {
+    return Ok(());
+  }
+  let chunk_size = connections.len().div_ceil(MAX_SEND_THREADS);
+  let message = message.as_str();
+  let closed = thread::scope(|scope| {
+    let senders = connections
+      .chunks(chunk_size)
+      .map(|connections| scope.spawn(move || send_all(push, connections, message)))
+      .collect::<Vec<_>>();
+    senders
+      .into_iter()
+      .flat_map(|sender| sender.join().unwrap_or_default())
+      .collect::<Vec<ConnectionId>>()
+  });
+
+  // Closed connections are unsubscribed once everyone else got the event
+  for connection_id in closed {
+    if let Err(err) = Connection::disconnect(db, connection_id.clone()) {
+      eprintln!("Error unsubscribing closed connection {}: {}", connection_id, err);
+    }
+  }
+  Ok(())
+}
+
+// Returns the connections which turned out to be closed
+fn send_all(push: &dyn Push, connections: &[Connection], message: &str) -> Vec<ConnectionId> {
+  connections
+    .iter()
+    .filter(|connection| match push.send(&connection.connection_id, message) {
+      Ok(()) => false,
+      Err(PushError::Gone) => true,
+      Err(err) => {
+        eprintln!("Error pushing to connection {}: {}", connection.connection_id, err);
+        false
+      },
+    })
+    .map(|connection| connection.connection_id.clone())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::MemoryStorage;
+
+  fn subscribe(db: &MemoryStorage, connection_ids: &[&str]) {
+    for connection_id in connection_ids {
+      Connection::subscribe(db, connection_id.to_string(), String::from("thread")).unwrap();
+    }
+  }
+
+  fn pin_event(db: &MemoryStorage, push: &MemoryPush) {
+    let comment_id = String::from("COMMENT_1");
+    broadcast(db, push, &String::from("thread"), &Event::CommentPinned { comment_id: &comment_id, pinned: true });
+  }
+
+  fn subscribers(db: &MemoryStorage) -> Vec<ConnectionId> {
+    let mut connection_ids = Connection::list_subscribers(db, String::from("thread"))
+      .unwrap()
+      .into_iter()
+      .map(|connection| connection.connection_id)
+      .collect::<Vec<ConnectionId>>();
+    connection_ids.sort();
+    connection_ids
+  }
+
+  #[test]
+  fn events_are_sent_to_every_subscriber() {
+    let (db, push) = (MemoryStorage::new(), MemoryPush::new());
+    let connection_ids = (0..20).map(|i| format!("connection-{:02}", i)).collect::<Vec<String>>();
+    subscribe(&db, &connection_ids.iter().map(String::as_str).collect::<Vec<&str>>());
+    Connection::subscribe(&db, String::from("elsewhere"), String::from("other-thread")).unwrap();
+
+    pin_event(&db, &push);
+
+    let mut messages = push.take_messages();
+    messages.sort();
+    assert_eq!(messages.iter().map(|(connection_id, _)| connection_id.clone()).collect::<Vec<String>>(), connection_ids);
+    let message = serde_json::from_str::<serde_json::Value>(&messages[0].1).unwrap();
+    assert_eq!(message["commentable_id"], "thread");
+    assert_eq!(message["type"], "comment_pinned");
+    assert_eq!(message["comment_id"], "COMMENT_1");
+  }
+
+  #[test]
+  fn closed_connections_are_unsubscribed() {
+    let (db, push) = (MemoryStorage::new(), MemoryPush::new());
+    subscribe(&db, &["open-1", "closed", "open-2"]);
+    push.close("closed");
+
+    pin_event(&db, &push);
+
+    let mut recipients = push.take_messages().into_iter().map(|(connection_id, _)| connection_id).collect::<Vec<String>>();
+    recipients.sort();
+    assert_eq!(recipients, vec!["open-1", "open-2"]);
+    assert_eq!(subscribers(&db), vec!["open-1", "open-2"]);
+
+    pin_event(&db, &push);
+    assert_eq!(push.take_messages().len(), 2);
+  }
+
+  #[test]
+  fn broadcasting_without_subscribers_sends_nothing() {
+    let (db, push) = (MemoryStorage::new(), MemoryPush::new());
+    pin_event(&db, &push);
+    assert!(push.take_messages().is_empty());
+  }
+}
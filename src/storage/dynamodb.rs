@@ -0,0 +1,469 @@
+use std::collections::HashMap;
+use std::thread;
+use std::time::Duration;
+
+use maplit::hashmap;
+use rusoto_core::Region;
+use rusoto_core::RusotoError;
+use rusoto_dynamodb::{
+  DynamoDb,
+  DynamoDbClient,
+  GetItemInput,
+  QueryInput,
+  ScanInput,
+  PutItemInput,
+  PutItemError,
+  UpdateItemInput,
+  UpdateItemError,
+  DeleteItemInput,
+  BatchGetItemInput,
+  BatchWriteItemInput,
+  KeysAndAttributes,
+  WriteRequest,
+  DeleteRequest,
+  TransactWriteItemsInput,
+  TransactWriteItemsError,
+  TransactWriteItem,
+  Put,
+  Delete,
+};
+
+use crate::storage::{item_key, Condition, Filter, KeyCondition, Query, QueryPage, ScanPage, Storage, Update, Write};
+use crate::utils::db::{
+  COMMENTABLE_RS_TABLE_NAME,
+  DynamoDbAttributes,
+  DbError,
+  PrimaryKey,
+  SortKey,
+  attribute_value,
+};
+
+// Batch operations retry the records DynamoDB didn't process (e.g. when throttled) this many times in total,
+// waiting twice as long before each attempt
+static MAX_BATCH_ATTEMPTS: u32 = 5;
+static BATCH_RETRY_DELAY_MILLIS: u64 = 50;
+
+pub struct DynamoDbStorage {
+  client: DynamoDbClient,
+  table_name: String,
+}
+
+impl DynamoDbStorage {
+  pub fn new(region: Region) -> Self {
+    Self {
+      client: DynamoDbClient::new(region),
+      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
+    }
+  }
+
+  fn key(primary_key: PrimaryKey, id: SortKey) -> DynamoDbAttributes {
+    hashmap!{
+      String::from("primary_key") => attribute_value(primary_key),
+      String::from("id") => attribute_value(id),
+    }
+  }
+
+  fn query_input(&self, query: Query) -> Result<QueryInput, DbError> {
+    let Query { primary_key, condition, filter, start_after, descending, .. } = query;
+    let (filter_expression, filter_names, filter_value) = match filter {
+      Some(Filter::AttributeNotExists(name)) => (
+        Some(String::from("attribute_not_exists(#f1)")),
+        Some(hashmap!{ String::from("#f1") => name }),
+        None,
+      ),
+      Some(Filter::AttributeGreaterThan(name, value)) => (
+        Some(String::from("#f1 > :f1")),
+        Some(hashmap!{ String::from("#f1") => name }),
+        Some(attribute_value(value)),
+      ),
+      None => (None, None, None),
+    };
+    let exclusive_start_key = match start_after {
+      Some(id) => {
+        let mut key = Self::key(primary_key.clone(), id.clone());
+        // Index queries also need the index key of the last evaluated record
+        match &condition {
+          KeyCondition::IndexEquals(index, value) => {
+            key.insert(index.attribute().to_string(), attribute_value(value.clone()));
+          },
+          KeyCondition::InIndex(index) => {
+            let value = self.get(primary_key.clone(), id)?
+              .and_then(|mut item| item.remove(index.attribute()))
+              .ok_or_else(|| DbError::Error(String::from("The last evaluated record has been removed from the index")))?;
+            key.insert(index.attribute().to_string(), value);
+          },
+          KeyCondition::IdBeginsWith(_) => (),
+        }
+        Some(key)
+      },
+      None => None,
+    };
+
+    let mut query_input = match condition {
+      KeyCondition::IdBeginsWith(prefix) => QueryInput {
+        table_name: self.table_name.clone(),
+        key_condition_expression: String::from("primary_key = :v1 and begins_with(id, :v2)").into(),
+        expression_attribute_values: hashmap!{
+          String::from(":v1") => attribute_value(primary_key.clone()),
+          String::from(":v2") => attribute_value(prefix),
+        }.into(),
+        ..Default::default()
+      },
+      KeyCondition::IndexEquals(index, value) => QueryInput {
+        table_name: self.table_name.clone(),
+        index_name: Some(index.name().to_string()),
+        key_condition_expression: format!("primary_key = :v1 and {} = :v2", index.attribute()).into(),
+        expression_attribute_values: hashmap!{
+          String::from(":v1") => attribute_value(primary_key.clone()),
+          String::from(":v2") => attribute_value(value),
+        }.into(),
+        ..Default::default()
+      },
+      KeyCondition::InIndex(index) => QueryInput {
+        table_name: self.table_name.clone(),
+        index_name: Some(index.name().to_string()),
+        key_condition_expression: String::from("primary_key = :v1").into(),
+        expression_attribute_values: hashmap!{
+          String::from(":v1") => attribute_value(primary_key.clone()),
+        }.into(),
+        ..Default::default()
+      },
+    };
+
+    if let (Some(values), Some(value)) = (query_input.expression_attribute_values.as_mut(), filter_value) {
+      values.insert(String::from(":f1"), value);
+    }
+
+    Ok(QueryInput {
+      filter_expression,
+      expression_attribute_names: filter_names,
+      exclusive_start_key,
+      scan_index_forward: Some(!descending),
+      ..query_input
+    })
+  }
+}
+
+// Builds an update expression (and the condition expression of conditional updates)
+// using placeholders for both names and values, so that reserved words (like "type") can be used as attribute names
+fn update_expression(update: Update) -> (String, Option<String>, HashMap<String, String>, DynamoDbAttributes) {
+  let mut names = HashMap::new();
+  let mut values = HashMap::new();
+  let mut clauses = vec![];
+
+  if !update.set.is_empty() {
+    let assignments = update.set.into_iter().enumerate().map(|(i, (name, value))| {
+      names.insert(format!("#s{}", i), name);
+      values.insert(format!(":s{}", i), value);
+      format!("#s{} = :s{}", i, i)
+    }).collect::<Vec<String>>();
+    clauses.push(format!("SET {}", assignments.join(", ")));
+  }
+  if !update.remove.is_empty() {
+    let removals = update.remove.into_iter().enumerate().map(|(i, name)| {
+      names.insert(format!("#r{}", i), name);
+      format!("#r{}", i)
+    }).collect::<Vec<String>>();
+    clauses.push(format!("REMOVE {}", removals.join(", ")));
+  }
+  if !update.add.is_empty() {
+    let additions = update.add.into_iter().enumerate().map(|(i, (name, value))| {
+      names.insert(format!("#a{}", i), name);
+      values.insert(format!(":a{}", i), attribute_value(value));
+      format!("#a{} :a{}", i, i)
+    }).collect::<Vec<String>>();
+    clauses.push(format!("ADD {}", additions.join(", ")));
+  }
+
+  let condition = update.condition.map(|condition| condition_expression(condition, &mut names, &mut values));
+
+  (clauses.join(" "), condition, names, values)
+}
+
+// Adds the placeholders used by the condition to the expression names & values
+fn condition_expression(condition: Condition, names: &mut HashMap<String, String>, values: &mut DynamoDbAttributes) -> String {
+  match condition {
+    Condition::NotExists => String::from("attribute_not_exists(id)"),
+    Condition::Exists => String::from("attribute_exists(id)"),
+    Condition::NumberEquals(name, value) => {
+      names.insert(String::from("#c1"), name);
+      values.insert(String::from(":c1"), attribute_value(value));
+      if value == 0 {
+        String::from("attribute_exists(id) AND (#c1 = :c1 OR attribute_not_exists(#c1))")
+      } else {
+        String::from("#c1 = :c1")
+      }
+    },
+    Condition::StringEquals(name, value) => {
+      names.insert(String::from("#c1"), name);
+      values.insert(String::from(":c1"), attribute_value(value));
+      String::from("#c1 = :c1")
+    },
+  }
+}
+
+// Waits before retrying unprocessed records of a batch operation, longer after each attempt
+fn backoff(attempt: u32) {
+  thread::sleep(Duration::from_millis(BATCH_RETRY_DELAY_MILLIS << attempt));
+}
+
+// Expression names & values are only allowed when they are used by an expression
+fn non_empty<T>(map: HashMap<String, T>) -> Option<HashMap<String, T>> {
+  if map.is_empty() { None } else { Some(map) }
+}
+
+impl Storage for DynamoDbStorage {
+  fn get(&self, key: PrimaryKey, id: SortKey) -> Result<Option<DynamoDbAttributes>, DbError> {
+    self.client.get_item(GetItemInput {
+      key: Self::key(key, id),
+      table_name: self.table_name.clone(),
+      ..Default::default()
+    }).sync()
+      .map_err(|err| DbError::Error(err.to_string()))
+      .map(|output| output.item)
+  }
+
+  fn query_page(&self, query: Query) -> Result<QueryPage, DbError> {
+    let limit = query.limit;
+    let query_input = self.query_input(query)?;
+    let mut results: Vec<DynamoDbAttributes> = vec![];
+    let mut last_evaluated_key = query_input.exclusive_start_key.clone();
+
+    /* DynamoDB applies the limit before filtering records, so keep querying
+       until we get one record more than requested (to know if there are more) */
+    'pagination: loop {
+      self.client.query(QueryInput {
+        exclusive_start_key: last_evaluated_key.clone(),
+        limit: limit.map(|limit| (limit + 1 - results.len()) as i64),
+        ..query_input.clone()
+      }).sync()
+        .map_err(|err| DbError::Error(err.to_string()))
+        .map(|query_output| {
+          results.append(query_output.items.unwrap_or_default().as_mut());
+          last_evaluated_key = query_output.last_evaluated_key;
+        })?;
+
+      if last_evaluated_key.is_none() || limit.filter(|limit| results.len() > *limit).is_some() {
+        break 'pagination;
+      }
+    }
+
+    match limit {
+      Some(limit) if results.len() > limit => {
+        results.truncate(limit);
+        let last_evaluated_id = results.last().and_then(|item| item.get("id")).and_then(|id| id.s.clone());
+        Ok(QueryPage { items: results, last_evaluated_id })
+      },
+      _ => Ok(QueryPage { items: results, last_evaluated_id: None }),
+    }
+  }
+
+  // Pages are limited by DynamoDB (up to 1 MB of records each)
+  fn scan_page(&self, start_after: Option<(PrimaryKey, SortKey)>) -> Result<ScanPage, DbError> {
+    let output = self.client.scan(ScanInput {
+      table_name: self.table_name.clone(),
+      exclusive_start_key: start_after.map(|(key, id)| Self::key(key, id)),
+      ..Default::default()
+    }).sync()
+      .map_err(|err| DbError::Error(err.to_string()))?;
+    Ok(ScanPage {
+      items: output.items.unwrap_or_default(),
+      last_evaluated_key: output.last_evaluated_key.as_ref().map(item_key).transpose()?,
+    })
+  }
+
+  fn put(&self, item: DynamoDbAttributes) -> Result<(), DbError> {
+    self.client.put_item(PutItemInput {
+      item,
+      table_name: self.table_name.clone(),
+      ..Default::default()
+    }).sync()
+      .map_err(|err| DbError::Error(err.to_string()))?;
+
+    Ok(())
+  }
+
+  fn put_if(&self, item: DynamoDbAttributes, condition: Condition) -> Result<(), DbError> {
+    let (mut names, mut values) = (HashMap::new(), HashMap::new());
+    let condition_expression = condition_expression(condition, &mut names, &mut values);
+    self.client.put_item(PutItemInput {
+      item,
+      table_name: self.table_name.clone(),
+      condition_expression: Some(condition_expression),
+      expression_attribute_names: non_empty(names),
+      expression_attribute_values: non_empty(values),
+      ..Default::default()
+    }).sync()
+      .map_err(|err| match err {
+        RusotoError::Service(PutItemError::ConditionalCheckFailed(_)) => DbError::ConditionFailed,
+        err => DbError::Error(err.to_string()),
+      })?;
+
+    Ok(())
+  }
+
+  fn update(&self, key: PrimaryKey, id: SortKey, update: Update) -> Result<DynamoDbAttributes, DbError> {
+    let (expression, condition_expression, names, values) = update_expression(update);
+    self.client.update_item(UpdateItemInput {
+      table_name: self.table_name.clone(),
+      key: Self::key(key, id),
+      update_expression: Some(expression),
+      condition_expression,
+      expression_attribute_names: Some(names),
+      expression_attribute_values: non_empty(values),
+      return_values: Some(String::from("ALL_NEW")),
+      ..Default::default()
+    }).sync()
+      .map_err(|err| match err {
+        RusotoError::Service(UpdateItemError::ConditionalCheckFailed(_)) => DbError::ConditionFailed,
+        err => DbError::Error(err.to_string()),
+      })
+      .map(|output| output.attributes.unwrap_or_default())
+  }
+
+  fn delete(&self, key: PrimaryKey, id: SortKey) -> Result<(), DbError> {
+    self.client.delete_item(DeleteItemInput {
+      key: Self::key(key, id),
+      table_name: self.table_name.clone(),
+      ..Default::default()
+    }).sync()
+      .map_err(|err| DbError::Error(err.to_string()))?;
+
+    Ok(())
+  }
+
+  fn batch_get(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<Vec<DynamoDbAttributes>, DbError> {
+    let mut items: Vec<DynamoDbAttributes> = vec![];
+    /* 100 is the maximum amount of records allowed
+       per single BatchGetItem operation in DynamoDB */
+    for slice in keys.chunks(100) {
+      let mut request_items = hashmap! {
+        self.table_name.clone() => KeysAndAttributes {
+          keys: slice.iter().map(|(primary_key, id)| Self::key(primary_key.clone(), id.clone())).collect(),
+          ..Default::default()
+        }
+      };
+      // Keys which weren't processed have to be requested again, otherwise their records would be silently missing
+      for attempt in 0.. {
+        if attempt == MAX_BATCH_ATTEMPTS {
+          return Err(DbError::Error(String::from("Couldn't get all records of a batch, try again later.")));
+        } else if attempt > 0 {
+          backoff(attempt);
+        }
+        let output = self.client.batch_get_item(BatchGetItemInput {
+          request_items: request_items.clone(),
+          ..Default::default()
+        }).sync()
+          .map_err(|err| DbError::Error(err.to_string()))?;
+        items.append(&mut output.responses.and_then(|mut responses| responses.remove(&self.table_name)).unwrap_or_default());
+        request_items = output.unprocessed_keys.unwrap_or_default();
+        if request_items.is_empty() {
+          break;
+        }
+      }
+    }
+    Ok(items)
+  }
+
+  fn batch_delete(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<(), DbError> {
+    // Each request can delete max 25 items
+    for slice in keys.chunks(25) {
+      let mut request_items: HashMap<String, Vec<WriteRequest>> = hashmap!{
+        self.table_name.clone() =>
+          slice
+            .iter()
+            .map(|(primary_key, sort_key)| WriteRequest {
+              delete_request: Some(DeleteRequest {
+                key: Self::key(primary_key.clone(), sort_key.clone()),
+              }),
+              ..Default::default()
+            }).collect(),
+      };
+      // Items which weren't processed have to be deleted again, so that callers don't assume they are gone
+      // (e.g. a cleanup is only removed from the queue once everything has been deleted)
+      for attempt in 0.. {
+        if attempt == MAX_BATCH_ATTEMPTS {
+          return Err(DbError::Error(String::from("Couldn't delete all records of a batch, try again later.")));
+        } else if attempt > 0 {
+          backoff(attempt);
+        }
+        request_items = self.client.batch_write_item(BatchWriteItemInput {
+          request_items: request_items.clone(),
+          ..Default::default()
+        }).sync()
+          .map_err(|err| DbError::Error(err.to_string()))?
+          .unprocessed_items
+          .unwrap_or_default();
+        if request_items.is_empty() {
+          break;
+        }
+      }
+    }
+
+    Ok(())
+  }
+
+  fn transact_write(&self, writes: Vec<Write>) -> Result<(), DbError> {
+    let transact_items = writes.into_iter().map(|write| {
+      let (mut names, mut values) = (HashMap::new(), HashMap::new());
+      match write {
+        Write::Put(item, condition) => {
+          let condition_expression = condition.map(|condition| condition_expression(condition, &mut names, &mut values));
+          TransactWriteItem {
+            put: Some(Put {
+              item,
+              table_name: self.table_name.clone(),
+              condition_expression,
+              expression_attribute_names: non_empty(names),
+              expression_attribute_values: non_empty(values),
+              ..Default::default()
+            }),
+            ..Default::default()
+          }
+        },
+        Write::Update(key, id, update) => {
+          let (expression, condition_expression, names, values) = update_expression(update);
+          TransactWriteItem {
+            update: Some(rusoto_dynamodb::Update {
+              key: Self::key(key, id),
+              table_name: self.table_name.clone(),
+              update_expression: expression,
+              condition_expression,
+              expression_attribute_names: non_empty(names),
+              expression_attribute_values: non_empty(values),
+              ..Default::default()
+            }),
+            ..Default::default()
+          }
+        },
+        Write::Delete(key, id, condition) => {
+          let condition_expression = condition.map(|condition| condition_expression(condition, &mut names, &mut values));
+          TransactWriteItem {
+            delete: Some(Delete {
+              key: Self::key(key, id),
+              table_name: self.table_name.clone(),
+              condition_expression,
+              expression_attribute_names: non_empty(names),
+              expression_attribute_values: non_empty(values),
+              ..Default::default()
+            }),
+            ..Default::default()
+          }
+        },
+      }
+    }).collect();
+
+    self.client.transact_write_items(TransactWriteItemsInput {
+      transact_items,
+      ..Default::default()
+    }).sync()
+      .map_err(|err| match err {
+        // The reasons of cancelled transactions are only listed in the error message
+        RusotoError::Service(TransactWriteItemsError::TransactionCanceled(ref message))
+          if message.contains("ConditionalCheckFailed") => DbError::ConditionFailed,
+        err => DbError::Error(err.to_string()),
+      })?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,308 @@
+use std::collections::BTreeMap;
+use std::ops::Bound;
+use std::sync::{Mutex, MutexGuard};
+
+use crate::storage::{
+  item_key,
+  new_item,
+  string_attribute,
+  Condition,
+  KeyCondition,
+  Query,
+  QueryPage,
+  ScanPage,
+  Storage,
+  Update,
+  Write,
+  SCAN_PAGE_SIZE,
+};
+use crate::utils::db::{
+  DynamoDbAttributes,
+  DbError,
+  PrimaryKey,
+  SortKey,
+};
+
+type Items = BTreeMap<(PrimaryKey, SortKey), DynamoDbAttributes>;
+
+// A storage backend keeping all records in memory.
+// Useful for tests and local development, as it doesn't require AWS credentials.
+#[derive(Default)]
+pub struct MemoryStorage {
+  items: Mutex<Items>,
+}
+
+impl MemoryStorage {
+  pub fn new() -> Self {
+    Default::default()
+  }
+
+  fn items(&self) -> Result<MutexGuard<'_, Items>, DbError> {
+    self.items.lock().map_err(|err| DbError::Error(err.to_string()))
+  }
+}
+
+impl Storage for MemoryStorage {
+  fn get(&self, key: PrimaryKey, id: SortKey) -> Result<Option<DynamoDbAttributes>, DbError> {
+    Ok(self.items()?.get(&(key, id)).cloned())
+  }
+
+  fn query_page(&self, query: Query) -> Result<QueryPage, DbError> {
+    let items = self.items()?;
+    let partition = items
+      .iter()
+      .filter(|((key, _), _)| *key == query.primary_key);
+
+    let results = match &query.condition {
+      KeyCondition::IdBeginsWith(prefix) =>
+        partition
+          .filter(|((_, id), _)| id.starts_with(prefix.as_str()))
+          .map(|(_, item)| item.clone())
+          .collect(),
+      KeyCondition::IndexEquals(index, value) => {
+        let mut results = partition
+          .filter(|(_, item)| string_attribute(item, index.attribute()) == Some(value.as_str()))
+          .map(|(_, item)| item.clone())
+          .collect::<Vec<DynamoDbAttributes>>();
+        // Mimic DynamoDB, which orders index queries by the sort key of the index
+        results.sort_by(|a, b| string_attribute(a, index.attribute()).cmp(&string_attribute(b, index.attribute())));
+        results
+      },
+      KeyCondition::InIndex(index) => {
+        let mut results = partition
+          .filter(|(_, item)| item.contains_key(index.attribute()))
+          .map(|(_, item)| item.clone())
+          .collect::<Vec<DynamoDbAttributes>>();
+        results.sort_by(|a, b| string_attribute(a, index.attribute()).cmp(&string_attribute(b, index.attribute())));
+        results
+      },
+    };
+
+    Ok(query.paginate(results))
+  }
+
+  fn scan_page(&self, start_after: Option<(PrimaryKey, SortKey)>) -> Result<ScanPage, DbError> {
+    let items = self.items()?;
+    let start = start_after.map_or(Bound::Unbounded, Bound::Excluded);
+    ScanPage::cut(items.range((start, Bound::Unbounded)).take(SCAN_PAGE_SIZE + 1).map(|(_, item)| item.clone()).collect())
+  }
+
+  fn put(&self, item: DynamoDbAttributes) -> Result<(), DbError> {
+    self.items()?.insert(item_key(&item)?, item);
+    Ok(())
+  }
+
+  fn put_if(&self, item: DynamoDbAttributes, condition: Condition) -> Result<(), DbError> {
+    let key = item_key(&item)?;
+    let mut items = self.items()?;
+    if !condition.matches(items.get(&key)) {
+      return Err(DbError::ConditionFailed);
+    }
+    items.insert(key, item);
+    Ok(())
+  }
+
+  fn update(&self, key: PrimaryKey, id: SortKey, update: Update) -> Result<DynamoDbAttributes, DbError> {
+    let mut items = self.items()?;
+    update.check(items.get(&(key.clone(), id.clone())))?;
+    // Just like DynamoDB, create the record if it doesn't exist yet
+    let item = items.entry((key.clone(), id.clone())).or_insert_with(|| new_item(key, id));
+    update.apply(item);
+    Ok(item.clone())
+  }
+
+  fn delete(&self, key: PrimaryKey, id: SortKey) -> Result<(), DbError> {
+    self.items()?.remove(&(key, id));
+    Ok(())
+  }
+
+  fn batch_get(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<Vec<DynamoDbAttributes>, DbError> {
+    let items = self.items()?;
+    Ok(keys.iter().filter_map(|key| items.get(key).cloned()).collect())
+  }
+
+  fn batch_delete(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<(), DbError> {
+    let mut items = self.items()?;
+    for key in keys {
+      items.remove(&key);
+    }
+    Ok(())
+  }
+
+  fn transact_write(&self, writes: Vec<Write>) -> Result<(), DbError> {
+    let mut items = self.items()?;
+    // All writes are checked before saving any of them, so failed transactions don't change anything
+    let mut changes = vec![];
+    for write in writes {
+      let key = write.key()?;
+      let item = write.apply(items.get(&key).cloned())?;
+      changes.push((key, item));
+    }
+    for (key, item) in changes {
+      match item {
+        Some(item) => items.insert(key, item),
+        None => items.remove(&key),
+      };
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use maplit::hashmap;
+
+  use super::*;
+  use crate::storage::{number_attribute, Index};
+  use crate::utils::db::attribute_value;
+
+  fn comment(id: &str, replies_to: Option<&str>) -> DynamoDbAttributes {
+    let mut item = new_item(String::from("thread"), id.to_string());
+    match replies_to {
+      Some(parent_id) => item.insert(String::from("replies_to"), attribute_value(parent_id.to_string())),
+      None => item.insert(String::from("top_level_id"), attribute_value(id.to_string())),
+    };
+    item
+  }
+
+  fn ids(items: Vec<DynamoDbAttributes>) -> Vec<String> {
+    items.iter().filter_map(|item| string_attribute(item, "id")).map(String::from).collect()
+  }
+
+  fn storage() -> MemoryStorage {
+    let db = MemoryStorage::new();
+    for item in [
+      comment("COMMENT_1", None),
+      comment("COMMENT_2", Some("COMMENT_1")),
+      comment("COMMENT_3", None),
+      comment("COMMENT_4", Some("COMMENT_1")),
+      new_item(String::from("thread"), String::from("COUNTERS")),
+      comment("COMMENT_5", None),
+    ] {
+      db.put(item).unwrap();
+    }
+    db.put(new_item(String::from("other-thread"), String::from("COMMENT_6"))).unwrap();
+    db
+  }
+
+  #[test]
+  fn queries_by_id_prefix() {
+    let db = storage();
+    let query = Query::id_prefix(String::from("thread"), String::from("COMMENT_"));
+    assert_eq!(ids(db.query(query.clone()).unwrap()), vec!["COMMENT_1", "COMMENT_2", "COMMENT_3", "COMMENT_4", "COMMENT_5"]);
+
+    let page = db.query_page(query.clone().page(Some(2), Some(String::from("COMMENT_2")))).unwrap();
+    assert_eq!(ids(page.items), vec!["COMMENT_3", "COMMENT_4"]);
+    assert_eq!(page.last_evaluated_id.as_deref(), Some("COMMENT_4"));
+
+    // Paging through all records in small pages returns all of them
+    assert_eq!(db.query(query.page(Some(1), None)).unwrap().len(), 5);
+  }
+
+  #[test]
+  fn queries_indexes() {
+    let db = storage();
+    let replies = db.query(Query::index(Index::Replies, String::from("thread"), String::from("COMMENT_1"))).unwrap();
+    assert_eq!(ids(replies), vec!["COMMENT_2", "COMMENT_4"]);
+
+    let top_level = db.query_page(Query::in_index(Index::TopLevel, String::from("thread")).descending(true).page(Some(2), None)).unwrap();
+    assert_eq!(ids(top_level.items), vec!["COMMENT_5", "COMMENT_3"]);
+    assert_eq!(top_level.last_evaluated_id.as_deref(), Some("COMMENT_3"));
+  }
+
+  #[test]
+  fn conditional_puts() {
+    let db = storage();
+    let mut item = comment("COMMENT_1", None);
+    item.insert(String::from("body"), attribute_value(String::from("Replaced")));
+
+    assert!(matches!(db.put_if(item.clone(), Condition::NotExists), Err(DbError::ConditionFailed)));
+    assert!(!db.get(String::from("thread"), String::from("COMMENT_1")).unwrap().unwrap().contains_key("body"));
+    db.put_if(item, Condition::Exists).unwrap();
+    assert!(db.get(String::from("thread"), String::from("COMMENT_1")).unwrap().unwrap().contains_key("body"));
+    db.put_if(comment("COMMENT_7", None), Condition::NotExists).unwrap();
+  }
+
+  #[test]
+  fn updates_create_missing_records_unless_conditioned() {
+    let db = MemoryStorage::new();
+    let counters = db.update(String::from("thread"), String::from("COUNTERS"), Update::default().add("total_comments", 1)).unwrap();
+    assert_eq!(number_attribute(&counters, "total_comments"), Some(1));
+
+    let update = Update::default().add("version", 1).condition(Condition::Exists);
+    assert!(matches!(db.update(String::from("thread"), String::from("COMMENT_1"), update), Err(DbError::ConditionFailed)));
+    assert!(db.get(String::from("thread"), String::from("COMMENT_1")).unwrap().is_none());
+  }
+
+  #[test]
+  fn failed_updates_dont_change_the_record() {
+    let db = storage();
+    let update = Update::set(hashmap!{ String::from("body") => attribute_value(String::from("Edited")) })
+      .add("version", 1)
+      .condition(Condition::NumberEquals(String::from("version"), 1));
+    assert!(matches!(db.update(String::from("thread"), String::from("COMMENT_1"), update), Err(DbError::ConditionFailed)));
+    assert_eq!(db.get(String::from("thread"), String::from("COMMENT_1")).unwrap(), Some(comment("COMMENT_1", None)));
+  }
+
+  #[test]
+  fn transactions_write_everything() {
+    let db = storage();
+    db.transact_write(vec![
+      Write::Delete(String::from("thread"), String::from("COMMENT_3"), Some(Condition::NumberEquals(String::from("version"), 0))),
+      Write::Update(String::from("thread"), String::from("COUNTERS"), Update::default().add("total_comments", -1)),
+      Write::Put(new_item(String::from("thread"), String::from("TOMBSTONE_3")), Some(Condition::NotExists)),
+    ]).unwrap();
+
+    assert!(db.get(String::from("thread"), String::from("COMMENT_3")).unwrap().is_none());
+    let counters = db.get(String::from("thread"), String::from("COUNTERS")).unwrap().unwrap();
+    assert_eq!(number_attribute(&counters, "total_comments"), Some(-1));
+    assert!(db.get(String::from("thread"), String::from("TOMBSTONE_3")).unwrap().is_some());
+  }
+
+  #[test]
+  fn failed_transactions_write_nothing() {
+    let db = storage();
+    let result = db.transact_write(vec![
+      Write::Delete(String::from("thread"), String::from("COMMENT_3"), None),
+      Write::Update(String::from("thread"), String::from("COUNTERS"), Update::default().add("total_comments", -1)),
+      // Already exists
+      Write::Put(comment("COMMENT_5", None), Some(Condition::NotExists)),
+    ]);
+
+    assert!(matches!(result, Err(DbError::ConditionFailed)));
+    assert!(db.get(String::from("thread"), String::from("COMMENT_3")).unwrap().is_some());
+    let counters = db.get(String::from("thread"), String::from("COUNTERS")).unwrap().unwrap();
+    assert_eq!(number_attribute(&counters, "total_comments"), None);
+  }
+
+  #[test]
+  fn batch_operations_skip_missing_records() {
+    let db = storage();
+    let keys = vec![
+      (String::from("thread"), String::from("COMMENT_1")),
+      (String::from("thread"), String::from("COMMENT_9")),
+      (String::from("other-thread"), String::from("COMMENT_6")),
+    ];
+    let mut found = ids(db.batch_get(keys.clone()).unwrap());
+    found.sort();
+    assert_eq!(found, vec!["COMMENT_1", "COMMENT_6"]);
+
+    db.batch_delete(keys.clone()).unwrap();
+    assert!(db.batch_get(keys).unwrap().is_empty());
+  }
+
+  #[test]
+  fn scans_return_every_record_page_by_page() {
+    let db = MemoryStorage::new();
+    for i in 0..SCAN_PAGE_SIZE + 1 {
+      db.put(new_item(format!("thread-{}", i % 3), format!("COMMENT_{:04}", i))).unwrap();
+    }
+
+    let page = db.scan_page(None).unwrap();
+    assert_eq!(page.items.len(), SCAN_PAGE_SIZE);
+    let next_page = db.scan_page(page.last_evaluated_key).unwrap();
+    assert_eq!(next_page.items.len(), 1);
+    assert_eq!(next_page.last_evaluated_key, None);
+    assert_eq!(item_key(&next_page.items[0]).unwrap(), (String::from("thread-2"), String::from("COMMENT_0998")));
+  }
+}
@@ -0,0 +1,595 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use maplit::hashmap;
+use rusoto_core::Region;
+
+use crate::models::comment::TOP_LEVEL_ID_FIELD_NAME;
+use crate::utils::db::{
+  DynamoDbAttributes,
+  DbError,
+  PrimaryKey,
+  SortKey,
+  attribute_value,
+  REPLIES_INDEX_NAME,
+  REACTIONS_INDEX_NAME,
+  TOP_LEVEL_INDEX_NAME,
+  USERS_INDEX_NAME,
+};
+
+pub mod dynamodb;
+pub mod memory;
+pub mod sql;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
+#[cfg(feature = "postgres")]
+pub mod postgres;
+
+pub use self::dynamodb::DynamoDbStorage;
+pub use self::memory::MemoryStorage;
+#[cfg(feature = "sqlite")]
+pub use self::sqlite::SqliteStorage;
+#[cfg(feature = "postgres")]
+pub use self::postgres::PostgresStorage;
+
+// Name of the ENV variable used to select the storage backend ("dynamodb", "memory", "sqlite" or "postgres")
+pub static STORAGE_ENV_VAR: &str = "COMMENTABLE_RS_STORAGE";
+// Name of the ENV variable containing the SQLite database path or the PostgreSQL connection URL
+pub static DATABASE_URL_ENV_VAR: &str = "COMMENTABLE_RS_DATABASE_URL";
+// Number of records in each page of a scan, for backends which don't limit pages by size like DynamoDB
+pub static SCAN_PAGE_SIZE: usize = 1000;
+
+lazy_static! {
+  // The in-memory backend is shared by every handler running in the same process
+  static ref MEMORY_STORAGE: Arc<MemoryStorage> = Arc::new(MemoryStorage::new());
+  // SQL backends keep a single connection per process
+  static ref SQL_STORAGE: Mutex<Option<Arc<dyn Storage>>> = Mutex::new(None);
+}
+
+// Secondary indexes available for queries
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Index {
+  // Comments by the comment they reply to
+  Replies,
+  // Reactions by the comment they belong to
+  Reactions,
+  // Comments & reactions by their author (also returns the comment_id, type and created_at of reactions)
+  Users,
+  // Top-level comments only, ordered by ID (the index attribute is a copy of the ID, missing from replies)
+  TopLevel,
+}
+
+impl Index {
+  pub fn name(&self) -> &'static str {
+    match self {
+      Index::Replies => REPLIES_INDEX_NAME,
+      Index::Reactions => REACTIONS_INDEX_NAME,
+      Index::Users => USERS_INDEX_NAME,
+      Index::TopLevel => TOP_LEVEL_INDEX_NAME,
+    }
+  }
+
+  // Name of the attribute used as the sort key of the index
+  pub fn attribute(&self) -> &'static str {
+    match self {
+      Index::Replies => "replies_to",
+      Index::Reactions => "comment_id",
+      Index::Users => "user_id",
+      Index::TopLevel => TOP_LEVEL_ID_FIELD_NAME,
+    }
+  }
+}
+
+#[derive(Clone, Debug)]
+pub enum KeyCondition {
+  // Records with an ID starting with the given prefix
+  IdBeginsWith(String),
+  // Records with the index attribute equal to the given value.
+  // Index queries are only guaranteed to return the key attributes of a record.
+  IndexEquals(Index, String),
+  // Every record in the index, i.e. records with the index attribute (with the same guarantees as above)
+  InIndex(Index),
+}
+
+#[derive(Clone, Debug)]
+pub enum Filter {
+  // Records without the given attribute
+  AttributeNotExists(String),
+  // Records with the given string attribute greater than the value
+  AttributeGreaterThan(String, String),
+}
+
+impl Filter {
+  pub fn matches(&self, item: &DynamoDbAttributes) -> bool {
+    match self {
+      Filter::AttributeNotExists(field_name) => !item.contains_key(field_name),
+      Filter::AttributeGreaterThan(field_name, value) =>
+        string_attribute(item, field_name).filter(|attribute| *attribute > value.as_str()).is_some(),
+    }
+  }
+}
+
+// A backend-agnostic description of a query within a single partition
+#[derive(Clone, Debug)]
+pub struct Query {
+  pub primary_key: PrimaryKey,
+  pub condition: KeyCondition,
+  pub filter: Option<Filter>,
+  // Maximum number of records returned by #query_page
+  pub limit: Option<usize>,
+  // Only return records placed after the record with this ID (see QueryPage#last_evaluated_id)
+  pub start_after: Option<SortKey>,
+  // Return records in the reverse order of the sort key
+  pub descending: bool,
+}
+
+impl Query {
+  pub fn id_prefix(primary_key: PrimaryKey, prefix: String) -> Self {
+    Self {
+      primary_key,
+      condition: KeyCondition::IdBeginsWith(prefix),
+      filter: None,
+      limit: None,
+      start_after: None,
+      descending: false,
+    }
+  }
+
+  pub fn index(index: Index, primary_key: PrimaryKey, value: String) -> Self {
+    Self {
+      primary_key,
+      condition: KeyCondition::IndexEquals(index, value),
+      filter: None,
+      limit: None,
+      start_after: None,
+      descending: false,
+    }
+  }
+
+  pub fn in_index(index: Index, primary_key: PrimaryKey) -> Self {
+    Self {
+      primary_key,
+      condition: KeyCondition::InIndex(index),
+      filter: None,
+      limit: None,
+      start_after: None,
+      descending: false,
+    }
+  }
+
+  pub fn filter(mut self, filter: Filter) -> Self {
+    self.filter = Some(filter);
+    self
+  }
+
+  pub fn page(mut self, limit: Option<usize>, start_after: Option<SortKey>) -> Self {
+    self.limit = limit;
+    self.start_after = start_after;
+    self
+  }
+
+  pub fn descending(mut self, descending: bool) -> Self {
+    self.descending = descending;
+    self
+  }
+
+  // Applies the order, filter and pagination to records ordered by ID (used by non-DynamoDB backends)
+  pub fn paginate<I>(&self, items: I) -> QueryPage
+  where I: IntoIterator<Item = DynamoDbAttributes> {
+    let mut items = items.into_iter().collect::<Vec<DynamoDbAttributes>>();
+    if self.descending {
+      items.reverse();
+    }
+
+    let mut matching = items
+      .into_iter()
+      .filter(|item| match &self.start_after {
+        Some(start_after) => string_attribute(item, "id")
+          .filter(|id| if self.descending { *id < start_after.as_str() } else { *id > start_after.as_str() })
+          .is_some(),
+        None => true,
+      })
+      .filter(|item| match &self.filter {
+        Some(filter) => filter.matches(item),
+        None => true,
+      });
+
+    match self.limit {
+      Some(limit) => {
+        let items = matching.by_ref().take(limit).collect::<Vec<DynamoDbAttributes>>();
+        let last_evaluated_id = if matching.next().is_some() {
+          items.last().and_then(|item| string_attribute(item, "id")).map(|id| id.to_string())
+        } else {
+          None
+        };
+        QueryPage { items, last_evaluated_id }
+      },
+      None => QueryPage { items: matching.collect(), last_evaluated_id: None },
+    }
+  }
+}
+
+// A condition of a conditional write, which fails with DbError::ConditionFailed when it isn't met
+#[derive(Clone, Debug)]
+pub enum Condition {
+  // The record doesn't exist yet
+  NotExists,
+  // The record exists (so that updates don't recreate deleted records)
+  Exists,
+  // The record exists and its numeric attribute equals the value (missing attributes count as 0)
+  NumberEquals(String, i64),
+  // The record exists and its string attribute equals the value (e.g. a token that can only be used once)
+  StringEquals(String, String),
+}
+
+impl Condition {
+  pub fn matches(&self, item: Option<&DynamoDbAttributes>) -> bool {
+    match (self, item) {
+      (Condition::NotExists, item) => item.is_none(),
+      (Condition::Exists, item) => item.is_some(),
+      (Condition::NumberEquals(field_name, value), Some(item)) => number_attribute(item, field_name).unwrap_or(0) == *value,
+      (Condition::StringEquals(field_name, value), Some(item)) => string_attribute(item, field_name) == Some(value.as_str()),
+      (Condition::NumberEquals(..), None) | (Condition::StringEquals(..), None) => false,
+    }
+  }
+}
+
+pub struct QueryPage {
+  pub items: Vec<DynamoDbAttributes>,
+  // Pass it as Query#start_after to fetch the next page, None if there are no more records
+  pub last_evaluated_id: Option<SortKey>,
+}
+
+pub struct ScanPage {
+  pub items: Vec<DynamoDbAttributes>,
+  // Pass it to Storage#scan_page to fetch the next page, None if there are no more records
+  pub last_evaluated_key: Option<(PrimaryKey, SortKey)>,
+}
+
+impl ScanPage {
+  // Cuts a page from records read in key order, which has one record more than the page size when there are
+  // more pages (used by non-DynamoDB backends)
+  pub fn cut(mut items: Vec<DynamoDbAttributes>) -> Result<Self, DbError> {
+    if items.len() <= SCAN_PAGE_SIZE {
+      return Ok(Self { items, last_evaluated_key: None });
+    }
+    items.truncate(SCAN_PAGE_SIZE);
+    // The unwrap is safe, because the page isn't empty
+    let last_evaluated_key = Some(item_key(items.last().unwrap())?);
+    Ok(Self { items, last_evaluated_key })
+  }
+}
+
+// A backend-agnostic description of an update of a single record
+#[derive(Clone, Debug, Default)]
+pub struct Update {
+  pub set: DynamoDbAttributes,
+  pub remove: Vec<String>,
+  // Atomically added to numeric attributes (missing attributes start at 0)
+  pub add: HashMap<String, i64>,
+  // The update is only applied when the record meets the condition
+  pub condition: Option<Condition>,
+}
+
+impl Update {
+  pub fn set(attributes: DynamoDbAttributes) -> Self {
+    Self { set: attributes, ..Default::default() }
+  }
+
+  pub fn remove(mut self, field_name: &str) -> Self {
+    self.remove.push(field_name.to_string());
+    self
+  }
+
+  pub fn add(mut self, field_name: &str, value: i64) -> Self {
+    *self.add.entry(field_name.to_string()).or_insert(0) += value;
+    self
+  }
+
+  pub fn condition(mut self, condition: Condition) -> Self {
+    self.condition = Some(condition);
+    self
+  }
+
+  // Checks the condition against the current record (used by non-DynamoDB backends)
+  pub fn check(&self, item: Option<&DynamoDbAttributes>) -> Result<(), DbError> {
+    match &self.condition {
+      Some(condition) if !condition.matches(item) => Err(DbError::ConditionFailed),
+      _ => Ok(()),
+    }
+  }
+
+  // Applies the update to a record loaded into memory (used by non-DynamoDB backends)
+  pub fn apply(self, item: &mut DynamoDbAttributes) {
+    item.extend(self.set);
+    for field_name in self.remove {
+      item.remove(&field_name);
+    }
+    for (field_name, value) in self.add {
+      let current = number_attribute(item, &field_name).unwrap_or(0);
+      item.insert(field_name, attribute_value(current + value));
+    }
+  }
+}
+
+// A single write of a transaction (see Storage#transact_write)
+#[derive(Clone, Debug)]
+pub enum Write {
+  // Creates or replaces a record, only when the current record meets the condition (if any)
+  Put(DynamoDbAttributes, Option<Condition>),
+  // Updates a record, creating it when missing (see Update#condition)
+  Update(PrimaryKey, SortKey, Update),
+  // Deletes a record, only when it meets the condition (if any)
+  Delete(PrimaryKey, SortKey, Option<Condition>),
+}
+
+impl Write {
+  pub fn key(&self) -> Result<(PrimaryKey, SortKey), DbError> {
+    match self {
+      Write::Put(item, _) => item_key(item),
+      Write::Update(key, id, _) | Write::Delete(key, id, _) => Ok((key.clone(), id.clone())),
+    }
+  }
+
+  // Applies the write to the current record loaded into memory, returning the record to save
+  // (None for deleted records). Used by non-DynamoDB backends.
+  pub fn apply(self, item: Option<DynamoDbAttributes>) -> Result<Option<DynamoDbAttributes>, DbError> {
+    let condition = match &self {
+      Write::Put(_, condition) | Write::Delete(_, _, condition) => condition.as_ref(),
+      Write::Update(_, _, update) => update.condition.as_ref(),
+    };
+    if condition.filter(|condition| !condition.matches(item.as_ref())).is_some() {
+      return Err(DbError::ConditionFailed);
+    }
+    match self {
+      Write::Put(new_item, _) => Ok(Some(new_item)),
+      Write::Update(key, id, update) => {
+        let mut item = item.unwrap_or_else(|| new_item(key, id));
+        update.apply(&mut item);
+        Ok(Some(item))
+      },
+      Write::Delete(..) => Ok(None),
+    }
+  }
+}
+
+// Main trait implemented by storage backends.
+// Records are identified by a (primary_key, id) pair, just like in the DynamoDB table.
+pub trait Storage: Send + Sync {
+  fn get(&self, key: PrimaryKey, id: SortKey) -> Result<Option<DynamoDbAttributes>, DbError>;
+
+  // Returns a single page of matching records, ordered by the sort key of the queried table or index
+  fn query_page(&self, query: Query) -> Result<QueryPage, DbError>;
+
+  // Returns all matching records (the query limit is used as the page size)
+  fn query(&self, query: Query) -> Result<Vec<DynamoDbAttributes>, DbError> {
+    let mut results = vec![];
+    let mut query = query;
+
+    'pagination: loop {
+      let mut page = self.query_page(query.clone())?;
+      results.append(&mut page.items);
+
+      match page.last_evaluated_id {
+        Some(id) => query.start_after = Some(id),
+        None => break 'pagination,
+      }
+    }
+
+    Ok(results)
+  }
+
+  // Returns a page of all records of the table, starting after the given key.
+  // Reads the whole table page by page, so it's only meant for maintenance tools (see bin/repair-counters.rs).
+  fn scan_page(&self, start_after: Option<(PrimaryKey, SortKey)>) -> Result<ScanPage, DbError>;
+
+  // Creates a new record or replaces an existing one
+  fn put(&self, item: DynamoDbAttributes) -> Result<(), DbError>;
+
+  // Creates or replaces a record only when the current one meets the condition.
+  // Fails with DbError::ConditionFailed otherwise (e.g. Condition::NotExists with an existing record).
+  fn put_if(&self, item: DynamoDbAttributes, condition: Condition) -> Result<(), DbError>;
+
+  // Updates a record (creating it when missing) and returns all of its attributes.
+  // Fails with DbError::ConditionFailed without changing the record when the update condition isn't met.
+  fn update(&self, key: PrimaryKey, id: SortKey, update: Update) -> Result<DynamoDbAttributes, DbError>;
+
+  fn delete(&self, key: PrimaryKey, id: SortKey) -> Result<(), DbError>;
+
+  // Applies all of the writes or none of them, each write has to change a different record.
+  // Fails with DbError::ConditionFailed when any of the conditions isn't met.
+  // Use utils::db::Transaction, which checks the limits of DynamoDB transactions.
+  fn transact_write(&self, writes: Vec<Write>) -> Result<(), DbError>;
+
+  // Missing records are skipped, the order of the results is not guaranteed
+  fn batch_get(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<Vec<DynamoDbAttributes>, DbError>;
+
+  // Fails unless every record has been deleted (missing records count as deleted)
+  fn batch_delete(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<(), DbError>;
+}
+
+// Creates an empty record with the given key, used when updating records that don't exist yet
+pub fn new_item(key: PrimaryKey, id: SortKey) -> DynamoDbAttributes {
+  hashmap!{
+    String::from("primary_key") => attribute_value(key),
+    String::from("id") => attribute_value(id),
+  }
+}
+
+// The (primary_key, id) pair identifying the record
+pub fn item_key(item: &DynamoDbAttributes) -> Result<(PrimaryKey, SortKey), DbError> {
+  let key = string_attribute(item, "primary_key")
+    .ok_or_else(|| DbError::RecordInvalid(String::from("Missing field 'primary_key'.")))?;
+  let id = string_attribute(item, "id")
+    .ok_or_else(|| DbError::RecordInvalid(String::from("Missing field 'id'.")))?;
+  Ok((key.to_string(), id.to_string()))
+}
+
+pub fn number_attribute(item: &DynamoDbAttributes, field_name: &str) -> Option<i64> {
+  item.get(field_name).and_then(|value| value.n.as_ref()).and_then(|number| number.parse().ok())
+}
+
+pub fn string_attribute<'a>(item: &'a DynamoDbAttributes, field_name: &str) -> Option<&'a str> {
+  item.get(field_name).and_then(|value| value.s.as_deref())
+}
+
+// Returns the storage backend selected by the COMMENTABLE_RS_STORAGE ENV variable (DynamoDB by default)
+pub fn connect() -> Result<Arc<dyn Storage>, DbError> {
+  match env::var(STORAGE_ENV_VAR).ok().as_deref() {
+    None | Some("") | Some("dynamodb") => Ok(Arc::new(DynamoDbStorage::new(Region::default()))),
+    Some("memory") => Ok(MEMORY_STORAGE.clone()),
+    #[cfg(feature = "sqlite")]
+    Some("sqlite") => shared_sql_storage(|url| Ok(Arc::new(SqliteStorage::open(url)?))),
+    #[cfg(feature = "postgres")]
+    Some("postgres") => shared_sql_storage(|url| Ok(Arc::new(PostgresStorage::connect(url)?))),
+    Some(backend) => Err(DbError::Error(format!("Unknown storage backend: {}", backend))),
+  }
+}
+
+#[cfg(any(feature = "sqlite", feature = "postgres"))]
+fn shared_sql_storage<F>(connect: F) -> Result<Arc<dyn Storage>, DbError>
+where F: FnOnce(&str) -> Result<Arc<dyn Storage>, DbError> {
+  let mut storage = SQL_STORAGE.lock().map_err(|err| DbError::Error(err.to_string()))?;
+  if storage.is_none() {
+    let url = env::var(DATABASE_URL_ENV_VAR)
+      .map_err(|_| DbError::Error(format!("{} is required by the SQL storage backends", DATABASE_URL_ENV_VAR)))?;
+    *storage = Some(connect(&url)?);
+  }
+  // The unwrap is safe, because the storage has been initialized above
+  Ok(storage.as_ref().unwrap().clone())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn item(id: &str) -> DynamoDbAttributes {
+    new_item(String::from("thread"), id.to_string())
+  }
+
+  fn ids(items: &[DynamoDbAttributes]) -> Vec<&str> {
+    items.iter().filter_map(|item| string_attribute(item, "id")).collect()
+  }
+
+  fn items() -> Vec<DynamoDbAttributes> {
+    vec![item("a"), item("b"), item("c"), item("d"), item("e")]
+  }
+
+  fn query() -> Query {
+    Query::id_prefix(String::from("thread"), String::new())
+  }
+
+  #[test]
+  fn paginate_returns_pages_with_the_id_of_the_last_record() {
+    let page = query().page(Some(2), None).paginate(items());
+    assert_eq!(ids(&page.items), vec!["a", "b"]);
+    assert_eq!(page.last_evaluated_id.as_deref(), Some("b"));
+
+    let page = query().page(Some(2), page.last_evaluated_id).paginate(items());
+    assert_eq!(ids(&page.items), vec!["c", "d"]);
+
+    let page = query().page(Some(2), page.last_evaluated_id).paginate(items());
+    assert_eq!(ids(&page.items), vec!["e"]);
+    assert_eq!(page.last_evaluated_id, None);
+  }
+
+  #[test]
+  fn paginate_doesnt_return_a_cursor_for_exactly_full_last_pages() {
+    let page = query().page(Some(5), None).paginate(items());
+    assert_eq!(page.items.len(), 5);
+    assert_eq!(page.last_evaluated_id, None);
+
+    let page = query().paginate(items());
+    assert_eq!(page.items.len(), 5);
+    assert_eq!(page.last_evaluated_id, None);
+  }
+
+  #[test]
+  fn paginate_descending() {
+    let page = query().descending(true).page(Some(2), Some(String::from("d"))).paginate(items());
+    assert_eq!(ids(&page.items), vec!["c", "b"]);
+    assert_eq!(page.last_evaluated_id.as_deref(), Some("b"));
+  }
+
+  #[test]
+  fn paginate_filters_before_limiting() {
+    let mut items = items();
+    for item in items.iter_mut().filter(|item| string_attribute(item, "id") < Some("c")) {
+      item.insert(String::from("is_deleted"), attribute_value(true));
+    }
+    let page = query()
+      .filter(Filter::AttributeNotExists(String::from("is_deleted")))
+      .page(Some(2), None)
+      .paginate(items);
+    assert_eq!(ids(&page.items), vec!["c", "d"]);
+    assert_eq!(page.last_evaluated_id.as_deref(), Some("d"));
+  }
+
+  #[test]
+  fn conditions_match_records() {
+    let mut record = item("a");
+    record.insert(String::from("version"), attribute_value(3));
+    record.insert(String::from("token"), attribute_value(String::from("secret")));
+
+    assert!(Condition::NotExists.matches(None));
+    assert!(!Condition::NotExists.matches(Some(&record)));
+    assert!(Condition::Exists.matches(Some(&record)));
+    assert!(!Condition::Exists.matches(None));
+    assert!(Condition::NumberEquals(String::from("version"), 3).matches(Some(&record)));
+    assert!(!Condition::NumberEquals(String::from("version"), 2).matches(Some(&record)));
+    assert!(Condition::StringEquals(String::from("token"), String::from("secret")).matches(Some(&record)));
+    assert!(!Condition::StringEquals(String::from("token"), String::from("other")).matches(Some(&record)));
+    assert!(!Condition::StringEquals(String::from("missing"), String::from("secret")).matches(Some(&record)));
+  }
+
+  #[test]
+  fn number_conditions_count_missing_attributes_as_zero_but_require_the_record() {
+    let record = item("a");
+    assert!(Condition::NumberEquals(String::from("version"), 0).matches(Some(&record)));
+    assert!(!Condition::NumberEquals(String::from("version"), 1).matches(Some(&record)));
+    assert!(!Condition::NumberEquals(String::from("version"), 0).matches(None));
+  }
+
+  #[test]
+  fn updates_set_remove_and_add_attributes() {
+    let mut record = item("a");
+    record.insert(String::from("body"), attribute_value(String::from("Hi")));
+    record.insert(String::from("edited_at"), attribute_value(String::from("2020-01-01T00:00:00Z")));
+    record.insert(String::from("version"), attribute_value(2));
+
+    Update::set(hashmap!{ String::from("body") => attribute_value(String::from("Hello")) })
+      .remove("edited_at")
+      .add("version", 1)
+      .add("edit_count", 1)
+      .add("edit_count", 1)
+      .apply(&mut record);
+
+    assert_eq!(string_attribute(&record, "body"), Some("Hello"));
+    assert!(!record.contains_key("edited_at"));
+    assert_eq!(number_attribute(&record, "version"), Some(3));
+    assert_eq!(number_attribute(&record, "edit_count"), Some(2));
+    assert_eq!(string_attribute(&record, "id"), Some("a"));
+  }
+
+  #[test]
+  fn updates_check_their_condition() {
+    let update = Update::default().add("version", 1).condition(Condition::NumberEquals(String::from("version"), 1));
+    assert!(update.check(Some(&item("a"))).is_err());
+    assert!(Update::default().check(None).is_ok());
+  }
+
+  #[test]
+  fn writes_create_missing_records_unless_their_condition_fails() {
+    let write = Write::Update(String::from("thread"), String::from("COUNTERS"), Update::default().add("total_comments", 1));
+    let record = write.apply(None).unwrap().unwrap();
+    assert_eq!(item_key(&record).unwrap(), (String::from("thread"), String::from("COUNTERS")));
+    assert_eq!(number_attribute(&record, "total_comments"), Some(1));
+
+    let write = Write::Delete(String::from("thread"), String::from("a"), Some(Condition::Exists));
+    assert!(matches!(write.apply(None), Err(DbError::ConditionFailed)));
+    let write = Write::Delete(String::from("thread"), String::from("a"), Some(Condition::Exists));
+    assert!(write.apply(Some(item("a"))).unwrap().is_none());
+  }
+}
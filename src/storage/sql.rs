@@ -0,0 +1,136 @@
+// Helpers shared by the relational (SQLite & PostgreSQL) storage backends.
+//
+// Records are kept in a single `items` table mirroring the DynamoDB table:
+//   primary_key, id                  - the primary key, i.e. the partition & sort key of the DynamoDB table
+//   replies_to, comment_id, user_id, - the sort keys of the secondary indexes (see storage::Index),
+//   top_level_id                       indexed on (primary_key, <column>, id) like the DynamoDB indexes
+//   attributes                       - the whole record, serialized to JSON (DynamoDB's attribute value format)
+// Queries are ordered by id and cut by the database (see #query_page), so they never load a whole partition.
+
+use crate::storage::{string_attribute, KeyCondition, Query, QueryPage, Index, SCAN_PAGE_SIZE};
+use crate::utils::db::{DynamoDbAttributes, DbError};
+
+pub enum Migration {
+  // The SQL has to stay compatible with both SQLite and PostgreSQL
+  Sql(&'static str),
+  // Saves every record again, which fills index columns added after it was saved
+  Reindex,
+}
+
+// Schema migrations, applied in order and recorded in the `schema_migrations` table
+pub static MIGRATIONS: &[(i64, Migration)] = &[
+  (1, Migration::Sql("CREATE TABLE items (
+         primary_key TEXT NOT NULL,
+         id TEXT NOT NULL,
+         replies_to TEXT,
+         comment_id TEXT,
+         attributes TEXT NOT NULL,
+         PRIMARY KEY (primary_key, id)
+       )")),
+  (2, Migration::Sql("CREATE INDEX replies_index ON items (primary_key, replies_to)")),
+  (3, Migration::Sql("CREATE INDEX reactions_index ON items (primary_key, comment_id)")),
+  // Records saved before this migration are indexed by migration 14
+  (4, Migration::Sql("ALTER TABLE items ADD COLUMN user_id TEXT")),
+  (5, Migration::Sql("CREATE INDEX users_index ON items (primary_key, user_id)")),
+  // Index queries are ordered by id, so pages are read straight from the indexes
+  (6, Migration::Sql("DROP INDEX replies_index")),
+  (7, Migration::Sql("CREATE INDEX replies_index ON items (primary_key, replies_to, id)")),
+  (8, Migration::Sql("DROP INDEX reactions_index")),
+  (9, Migration::Sql("CREATE INDEX reactions_index ON items (primary_key, comment_id, id)")),
+  (10, Migration::Sql("DROP INDEX users_index")),
+  (11, Migration::Sql("CREATE INDEX users_index ON items (primary_key, user_id, id)")),
+  // Comments saved before this migration get the top_level_id attribute from bin/repair-counters.rs
+  (12, Migration::Sql("ALTER TABLE items ADD COLUMN top_level_id TEXT")),
+  (13, Migration::Sql("CREATE INDEX top_level_index ON items (primary_key, top_level_id)")),
+  // Indexes records saved before migration 4 by their authors
+  (14, Migration::Reindex),
+];
+
+pub static CREATE_MIGRATIONS_TABLE: &str =
+  "CREATE TABLE IF NOT EXISTS schema_migrations (version BIGINT PRIMARY KEY)";
+
+pub fn encode(item: &DynamoDbAttributes) -> Result<String, DbError> {
+  serde_json::to_string(item).map_err(|err| DbError::Error(err.to_string()))
+}
+
+pub fn decode(attributes: &str) -> Result<DynamoDbAttributes, DbError> {
+  serde_json::from_str(attributes).map_err(|err| DbError::Error(err.to_string()))
+}
+
+pub fn key_column(item: &DynamoDbAttributes, field_name: &str) -> Result<String, DbError> {
+  string_attribute(item, field_name)
+    .map(|value| value.to_string())
+    .ok_or_else(|| DbError::RecordInvalid(format!("Missing field '{}'.", field_name)))
+}
+
+pub fn index_column(item: &DynamoDbAttributes, index: Index) -> Option<String> {
+  string_attribute(item, index.attribute()).map(|value| value.to_string())
+}
+
+pub fn sql_error<T: ToString>(err: T) -> DbError {
+  DbError::Error(err.to_string())
+}
+
+// The condition of the query on the key columns, along with the value bound to the placeholder (if any)
+pub fn key_condition(query: &Query, placeholder: &str) -> (String, Option<String>) {
+  match &query.condition {
+    KeyCondition::IdBeginsWith(prefix) =>
+      (format!("substr(id, 1, length({0})) = {0}", placeholder), Some(prefix.clone())),
+    KeyCondition::IndexEquals(index, value) => (format!("{} = {}", index.attribute(), placeholder), Some(value.clone())),
+    KeyCondition::InIndex(index) => (format!("{} IS NOT NULL", index.attribute()), None),
+  }
+}
+
+// The ordering, cursor & limit of a query, appended to its WHERE clause.
+// The ID the page starts after is bound to the `cursor` placeholder, when there is one.
+// Records with the same index value are ordered by ID, so every query is ordered by ID.
+pub fn page_clauses(query: &Query, cursor: Option<&str>, limit: Option<usize>) -> String {
+  let (operator, order) = if query.descending { ("<", "DESC") } else { (">", "ASC") };
+  let mut clauses = String::new();
+  if let Some(cursor) = cursor {
+    clauses.push_str(&format!(" AND id {} {}", operator, cursor));
+  }
+  clauses.push_str(&format!(" ORDER BY id {}", order));
+  if let Some(limit) = limit {
+    clauses.push_str(&format!(" LIMIT {}", limit));
+  }
+  clauses
+}
+
+// Selects a page of a scan of the table ordered by key, with one record more than the page size
+// (see ScanPage#cut). The key the page starts after is bound to the `key` & `id` placeholders, when there is one.
+pub fn scan_sql(start_after: Option<(&str, &str)>) -> String {
+  let condition = match start_after {
+    Some((key, id)) => format!(" WHERE primary_key > {0} OR (primary_key = {0} AND id > {1})", key, id),
+    None => String::new(),
+  };
+  format!("SELECT attributes FROM items{} ORDER BY primary_key, id LIMIT {}", condition, SCAN_PAGE_SIZE + 1)
+}
+
+// Reads a page of the query in batches of limit + 1 records, which are ordered & cut by the database
+// (`fetch` receives the ID to start after and the batch size, see #page_clauses).
+// Filters are applied to each batch, so that filtered out records don't shorten the page.
+pub fn query_page<F>(query: &Query, mut fetch: F) -> Result<QueryPage, DbError>
+where F: FnMut(Option<&str>, Option<usize>) -> Result<Vec<DynamoDbAttributes>, DbError> {
+  let batch_size = query.limit.map(|limit| limit + 1);
+  let mut start_after = query.start_after.clone();
+  let mut items = vec![];
+
+  loop {
+    let batch = fetch(start_after.as_deref(), batch_size)?;
+    let exhausted = batch_size.is_none_or(|size| batch.len() < size);
+    start_after = batch.last().and_then(|item| string_attribute(item, "id")).map(String::from);
+    items.extend(batch.into_iter().filter(|item| query.filter.as_ref().is_none_or(|filter| filter.matches(item))));
+
+    match query.limit {
+      // The extra record means that there's at least one more page
+      Some(limit) if items.len() > limit => {
+        items.truncate(limit);
+        let last_evaluated_id = items.last().and_then(|item| string_attribute(item, "id")).map(String::from);
+        return Ok(QueryPage { items, last_evaluated_id });
+      },
+      _ if exhausted => return Ok(QueryPage { items, last_evaluated_id: None }),
+      _ => (),
+    }
+  }
+}
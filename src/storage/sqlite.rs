@@ -0,0 +1,264 @@
+use std::sync::{Mutex, MutexGuard};
+
+use rusqlite::{params, Connection, OptionalExtension, ToSql, NO_PARAMS};
+
+use crate::storage::{item_key, new_item, Condition, Query, QueryPage, ScanPage, Storage, Update, Write, Index};
+use crate::storage::sql::{
+  self,
+  decode,
+  encode,
+  index_column,
+  key_column,
+  sql_error,
+  CREATE_MIGRATIONS_TABLE,
+  MIGRATIONS,
+  Migration,
+};
+use crate::utils::db::{
+  DynamoDbAttributes,
+  DbError,
+  PrimaryKey,
+  SortKey,
+};
+
+// A storage backend using a single SQLite database file
+pub struct SqliteStorage {
+  connection: Mutex<Connection>,
+}
+
+impl SqliteStorage {
+  // Opens (or creates) the database and applies pending migrations
+  pub fn open(path: &str) -> Result<Self, DbError> {
+    let storage = Self {
+      connection: Mutex::new(Connection::open(path).map_err(sql_error)?),
+    };
+    storage.migrate()?;
+    Ok(storage)
+  }
+
+  pub fn migrate(&self) -> Result<(), DbError> {
+    let mut connection = self.connection()?;
+    connection.execute(CREATE_MIGRATIONS_TABLE, NO_PARAMS).map_err(sql_error)?;
+
+    for (version, migration) in MIGRATIONS {
+      let transaction = connection.transaction().map_err(sql_error)?;
+      let applied = transaction
+        .query_row("SELECT version FROM schema_migrations WHERE version = ?1", params![version], |row| row.get::<_, i64>(0))
+        .optional()
+        .map_err(sql_error)?;
+
+      if applied.is_none() {
+        match migration {
+          Migration::Sql(sql) => transaction.execute(sql, NO_PARAMS).map(|_| ()).map_err(sql_error)?,
+          Migration::Reindex => reindex(&transaction)?,
+        }
+        transaction.execute("INSERT INTO schema_migrations (version) VALUES (?1)", params![version]).map_err(sql_error)?;
+      }
+      transaction.commit().map_err(sql_error)?;
+    }
+
+    Ok(())
+  }
+
+  fn connection(&self) -> Result<MutexGuard<'_, Connection>, DbError> {
+    self.connection.lock().map_err(|err| DbError::Error(err.to_string()))
+  }
+}
+
+fn get(connection: &Connection, key: &str, id: &str) -> Result<Option<DynamoDbAttributes>, DbError> {
+  connection
+    .query_row("SELECT attributes FROM items WHERE primary_key = ?1 AND id = ?2", params![key, id], |row| row.get::<_, String>(0))
+    .optional()
+    .map_err(sql_error)?
+    .map(|attributes| decode(&attributes))
+    .transpose()
+}
+
+fn put(connection: &Connection, item: &DynamoDbAttributes) -> Result<(), DbError> {
+  connection.execute(
+    "INSERT OR REPLACE INTO items (primary_key, id, replies_to, comment_id, user_id, top_level_id, attributes) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+    params![
+      key_column(item, "primary_key")?,
+      key_column(item, "id")?,
+      index_column(item, Index::Replies),
+      index_column(item, Index::Reactions),
+      index_column(item, Index::Users),
+      index_column(item, Index::TopLevel),
+      encode(item)?,
+    ],
+  ).map_err(sql_error)?;
+
+  Ok(())
+}
+
+fn reindex(connection: &Connection) -> Result<(), DbError> {
+  let items = connection
+    .prepare("SELECT attributes FROM items").map_err(sql_error)?
+    .query_map(NO_PARAMS, |row| row.get::<_, String>(0)).map_err(sql_error)?
+    .collect::<Result<Vec<String>, _>>().map_err(sql_error)?;
+  for item in items {
+    put(connection, &decode(&item)?)?;
+  }
+  Ok(())
+}
+
+impl Storage for SqliteStorage {
+  fn get(&self, key: PrimaryKey, id: SortKey) -> Result<Option<DynamoDbAttributes>, DbError> {
+    get(&*self.connection()?, &key, &id)
+  }
+
+  fn query_page(&self, query: Query) -> Result<QueryPage, DbError> {
+    let connection = self.connection()?;
+    let (condition, value) = sql::key_condition(&query, "?2");
+
+    sql::query_page(&query, |start_after, limit| {
+      let mut params: Vec<&dyn ToSql> = vec![&query.primary_key];
+      if let Some(value) = &value {
+        params.push(value);
+      }
+      let cursor = start_after.map(|_| format!("?{}", params.len() + 1));
+      if let Some(start_after) = &start_after {
+        params.push(start_after);
+      }
+      let sql = format!(
+        "SELECT attributes FROM items WHERE primary_key = ?1 AND {}{}",
+        condition,
+        sql::page_clauses(&query, cursor.as_deref(), limit),
+      );
+
+      let mut statement = connection.prepare(&sql).map_err(sql_error)?;
+      let rows = statement
+        .query_map(params, |row| row.get::<_, String>(0))
+        .map_err(sql_error)?;
+      rows
+        .map(|attributes| decode(&attributes.map_err(sql_error)?))
+        .collect()
+    })
+  }
+
+  fn scan_page(&self, start_after: Option<(PrimaryKey, SortKey)>) -> Result<ScanPage, DbError> {
+    let connection = self.connection()?;
+    let sql = sql::scan_sql(start_after.as_ref().map(|_| ("?1", "?2")));
+    let mut statement = connection.prepare(&sql).map_err(sql_error)?;
+    let params: Vec<&dyn ToSql> = match &start_after {
+      Some((key, id)) => vec![key, id],
+      None => vec![],
+    };
+    let items = statement
+      .query_map(params, |row| row.get::<_, String>(0))
+      .map_err(sql_error)?
+      .map(|attributes| decode(&attributes.map_err(sql_error)?))
+      .collect::<Result<Vec<DynamoDbAttributes>, DbError>>()?;
+    ScanPage::cut(items)
+  }
+
+  fn put(&self, item: DynamoDbAttributes) -> Result<(), DbError> {
+    put(&*self.connection()?, &item)
+  }
+
+  fn put_if(&self, item: DynamoDbAttributes, condition: Condition) -> Result<(), DbError> {
+    let (key, id) = item_key(&item)?;
+    let mut connection = self.connection()?;
+    let transaction = connection.transaction().map_err(sql_error)?;
+
+    if !condition.matches(get(&transaction, &key, &id)?.as_ref()) {
+      return Err(DbError::ConditionFailed);
+    }
+    put(&transaction, &item)?;
+
+    transaction.commit().map_err(sql_error)?;
+    Ok(())
+  }
+
+  fn update(&self, key: PrimaryKey, id: SortKey, update: Update) -> Result<DynamoDbAttributes, DbError> {
+    let mut connection = self.connection()?;
+    let transaction = connection.transaction().map_err(sql_error)?;
+
+    let item = get(&transaction, &key, &id)?;
+    update.check(item.as_ref())?;
+    let mut item = item.unwrap_or_else(|| new_item(key, id));
+    update.apply(&mut item);
+    put(&transaction, &item)?;
+
+    transaction.commit().map_err(sql_error)?;
+    Ok(item)
+  }
+
+  fn delete(&self, key: PrimaryKey, id: SortKey) -> Result<(), DbError> {
+    self.connection()?
+      .execute("DELETE FROM items WHERE primary_key = ?1 AND id = ?2", params![key, id])
+      .map_err(sql_error)?;
+
+    Ok(())
+  }
+
+  fn batch_get(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<Vec<DynamoDbAttributes>, DbError> {
+    let connection = self.connection()?;
+    let mut items = vec![];
+    for (key, id) in keys {
+      if let Some(item) = get(&connection, &key, &id)? {
+        items.push(item);
+      }
+    }
+    Ok(items)
+  }
+
+  fn batch_delete(&self, keys: Vec<(PrimaryKey, SortKey)>) -> Result<(), DbError> {
+    let mut connection = self.connection()?;
+    let transaction = connection.transaction().map_err(sql_error)?;
+    for (key, id) in keys {
+      transaction
+        .execute("DELETE FROM items WHERE primary_key = ?1 AND id = ?2", params![key, id])
+        .map_err(sql_error)?;
+    }
+    transaction.commit().map_err(sql_error)
+  }
+
+  // Rolled back by dropping the transaction when any of the writes fails
+  fn transact_write(&self, writes: Vec<Write>) -> Result<(), DbError> {
+    let mut connection = self.connection()?;
+    let transaction = connection.transaction().map_err(sql_error)?;
+
+    for write in writes {
+      let (key, id) = write.key()?;
+      let item = get(&transaction, &key, &id)?;
+      match write.apply(item)? {
+        Some(item) => put(&transaction, &item)?,
+        None => {
+          transaction.execute("DELETE FROM items WHERE primary_key = ?1 AND id = ?2", params![key, id]).map_err(sql_error)?;
+        },
+      }
+    }
+
+    transaction.commit().map_err(sql_error)?;
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::storage::string_attribute;
+  use crate::utils::db::attribute_value;
+
+  #[test]
+  fn reindexing_indexes_records_saved_before_index_columns() {
+    let db = SqliteStorage::open(":memory:").unwrap();
+    let mut reaction = new_item(String::from("thread"), String::from("REACTION_1"));
+    reaction.insert(String::from("user_id"), attribute_value(String::from("USER_1")));
+    {
+      // Saved like before the user_id column was added
+      let connection = db.connection().unwrap();
+      connection.execute(
+        "INSERT INTO items (primary_key, id, attributes) VALUES (?1, ?2, ?3)",
+        params![String::from("thread"), String::from("REACTION_1"), encode(&reaction).unwrap()],
+      ).unwrap();
+      connection.execute("DELETE FROM schema_migrations WHERE version = 14", NO_PARAMS).unwrap();
+    }
+    assert!(db.query(Query::index(Index::Users, String::from("thread"), String::from("USER_1"))).unwrap().is_empty());
+
+    db.migrate().unwrap();
+    let reactions = db.query(Query::index(Index::Users, String::from("thread"), String::from("USER_1"))).unwrap();
+    assert_eq!(reactions.iter().filter_map(|item| string_attribute(item, "id")).collect::<Vec<&str>>(), vec!["REACTION_1"]);
+  }
+}
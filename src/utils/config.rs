@@ -0,0 +1,102 @@
+// Site-wide settings, read from ENV variables
+use std::env;
+
+use crate::utils::oidc::Provider;
+
+// Max nesting depth of replies returned by comments/list (unlimited if missing)
+pub static MAX_DEPTH_ENV_VAR: &str = "COMMENTABLE_RS_MAX_DEPTH";
+
+pub fn max_depth() -> Option<usize> {
+  env::var(MAX_DEPTH_ENV_VAR).ok()
+    .and_then(|depth| depth.parse().ok())
+    .filter(|depth| *depth > 0)
+}
+
+// Set to "true" to list comments from materialized snapshots of whole threads (see models/snapshot.rs)
+pub static SNAPSHOTS_ENV_VAR: &str = "COMMENTABLE_RS_SNAPSHOTS";
+
+pub fn snapshots_enabled() -> bool {
+  env::var(SNAPSHOTS_ENV_VAR).ok().filter(|enabled| enabled == "true" || enabled == "1").is_some()
+}
+
+// Open commentables are closed to new comments this many days after being published (never if missing),
+// unless overridden for the commentable (see models/commentable.rs)
+pub static AUTO_CLOSE_DAYS_ENV_VAR: &str = "COMMENTABLE_RS_AUTO_CLOSE_DAYS";
+
+pub fn auto_close_days() -> Option<i64> {
+  env::var(AUTO_CLOSE_DAYS_ENV_VAR).ok()
+    .and_then(|days| days.parse().ok())
+    .filter(|days| *days > 0)
+}
+
+// Comma-separated emails or user IDs of site admins, who can manage every commentable (e.g. lock threads)
+pub static ADMINS_ENV_VAR: &str = "COMMENTABLE_RS_ADMINS";
+
+// Lowercase, as emails are compared case-insensitively
+pub fn admins() -> Vec<String> {
+  env::var(ADMINS_ENV_VAR).unwrap_or_default()
+    .split(',')
+    .map(|email| email.trim().to_lowercase())
+    .filter(|email| !email.is_empty())
+    .collect()
+}
+
+// Comments can only be edited this many minutes after being created (any time if missing)
+pub static EDIT_WINDOW_MINUTES_ENV_VAR: &str = "COMMENTABLE_RS_EDIT_WINDOW_MINUTES";
+
+pub fn edit_window_minutes() -> Option<i64> {
+  env::var(EDIT_WINDOW_MINUTES_ENV_VAR).ok()
+    .and_then(|minutes| minutes.parse().ok())
+    .filter(|minutes| *minutes > 0)
+}
+
+// Set to "true" to only allow editing comments without replies
+pub static EDIT_WITHOUT_REPLIES_ENV_VAR: &str = "COMMENTABLE_RS_EDIT_WITHOUT_REPLIES";
+
+pub fn edit_without_replies() -> bool {
+  env::var(EDIT_WITHOUT_REPLIES_ENV_VAR).ok().filter(|enabled| enabled == "true" || enabled == "1").is_some()
+}
+
+// Comma-separated OAuth client IDs of the apps allowed to sign in with Google (the "aud" of ID tokens).
+// ID tokens are rejected when none are configured.
+pub static GOOGLE_CLIENT_IDS_ENV_VAR: &str = "COMMENTABLE_RS_GOOGLE_CLIENT_IDS";
+
+pub fn google_client_ids() -> Vec<String> {
+  env::var(GOOGLE_CLIENT_IDS_ENV_VAR).unwrap_or_default()
+    .split(',')
+    .map(|client_id| client_id.trim().to_string())
+    .filter(|client_id| !client_id.is_empty())
+    .collect()
+}
+
+// JSON list of OpenID Connect providers users can sign in with, besides Google (see utils/oidc.rs), e.g.
+//   [{"name": "keycloak", "issuer": "https://sso.example.com/realms/intranet", "client_ids": ["commentable"]}]
+pub static OIDC_PROVIDERS_ENV_VAR: &str = "COMMENTABLE_RS_OIDC_PROVIDERS";
+
+pub fn oidc_providers() -> Result<Vec<Provider>, String> {
+  match env::var(OIDC_PROVIDERS_ENV_VAR) {
+    Ok(ref providers) if !providers.trim().is_empty() => serde_json::from_str(providers)
+      .map_err(|err| format!("Invalid {}: {}", OIDC_PROVIDERS_ENV_VAR, err)),
+    _ => Ok(vec![]),
+  }
+}
+
+// Access tokens (auth_token) expire this many minutes after being issued (60 if missing)
+pub static ACCESS_TOKEN_TTL_MINUTES_ENV_VAR: &str = "COMMENTABLE_RS_ACCESS_TOKEN_TTL_MINUTES";
+
+pub fn access_token_ttl_minutes() -> i64 {
+  env::var(ACCESS_TOKEN_TTL_MINUTES_ENV_VAR).ok()
+    .and_then(|minutes| minutes.parse().ok())
+    .filter(|minutes| *minutes > 0)
+    .unwrap_or(60)
+}
+
+// Refresh tokens expire this many days after being issued (30 if missing)
+pub static REFRESH_TOKEN_TTL_DAYS_ENV_VAR: &str = "COMMENTABLE_RS_REFRESH_TOKEN_TTL_DAYS";
+
+pub fn refresh_token_ttl_days() -> i64 {
+  env::var(REFRESH_TOKEN_TTL_DAYS_ENV_VAR).ok()
+    .and_then(|days| days.parse().ok())
+    .filter(|days| *days > 0)
+    .unwrap_or(30)
+}
@@ -1,6 +1,5 @@
-use rusoto_dynamodb::DynamoDbClient;
-
 use crate::{
+  storage::Storage,
   utils::{
     db::{CommentableId, DynamoDbModel},
     http::{not_found, internal_server_error, HttpError},
@@ -9,7 +8,7 @@ use crate::{
 };
 
 pub trait CurrentComment {
-  fn db(&self) -> &DynamoDbClient;
+  fn db(&self) -> &dyn Storage;
   fn comment_id(&self) -> CommentId;
   fn commentable_id(&self) -> CommentableId;
   fn set_current_comment(&mut self, comment: Comment);
@@ -0,0 +1,50 @@
+use crate::{
+  storage::Storage,
+  utils::{
+    db::CommentableId,
+    http::{forbidden, error_code, internal_server_error, HttpError},
+  },
+  models::commentable::Commentable,
+};
+
+pub trait CurrentCommentable {
+  fn db(&self) -> &dyn Storage;
+  fn commentable_id(&self) -> CommentableId;
+  fn current_commentable(&self) -> Option<&Commentable>;
+  fn set_current_commentable(&mut self, commentable: Option<Commentable>);
+
+  // Commentables without metadata are open, so a missing record isn't an error
+  fn fetch_current_commentable(&mut self) -> Result<&mut Self, HttpError> {
+    match Commentable::find_for_commentable(self.db(), self.commentable_id()) {
+      Ok(commentable) => self.set_current_commentable(commentable),
+      Err(err) => return Err(internal_server_error(err)),
+    }
+    Ok(self)
+  }
+
+  // New comments and replies can only be added to open commentables
+  fn check_accepts_comments(&mut self) -> Result<&mut Self, HttpError> {
+    match self.current_commentable() {
+      Some(commentable) if commentable.is_read_only() => Err(forbidden(locked_error())),
+      Some(commentable) if commentable.is_auto_closed() => Err(forbidden(
+        error_code("commentable_closed", "This thread was closed to new comments automatically.")
+      )),
+      Some(commentable) if !commentable.accepts_comments() => Err(forbidden(
+        error_code("commentable_closed", "This thread is closed to new comments.")
+      )),
+      _ => Ok(self),
+    }
+  }
+
+  // Comments and reactions of locked commentables can't be changed
+  fn check_not_locked(&mut self) -> Result<&mut Self, HttpError> {
+    match self.current_commentable() {
+      Some(commentable) if commentable.is_read_only() => Err(forbidden(locked_error())),
+      _ => Ok(self),
+    }
+  }
+}
+
+fn locked_error() -> String {
+  error_code("commentable_locked", "This thread is locked.")
+}
@@ -1,39 +1,73 @@
-use rusoto_dynamodb::DynamoDbClient;
-
 use crate::{
+  storage::Storage,
   utils::{
     db::DynamoDbModel,
-    http::{unauthorized, internal_server_error, HttpError},
+    http::{error_code, unauthorized, internal_server_error, HttpError},
+  },
+  models::{
+    session::{parse_token, Session, SessionId},
+    user::{parse_legacy_token, User, UserId},
   },
-  models::user::{TOKEN_DELIMITER, User},
 };
 
+pub fn invalid_token() -> HttpError {
+  unauthorized(error_code("invalid_token", "Invalid access token."))
+}
+
+// Clients should get new tokens from /auth/refresh instead of signing in again
+pub fn token_expired() -> HttpError {
+  unauthorized(error_code("token_expired", "The access token has expired."))
+}
+
 pub trait CurrentUser {
-  fn db(&self) -> &DynamoDbClient;
+  fn db(&self) -> &dyn Storage;
   fn auth_token(&self) -> Option<String>;
   fn set_current_user(&mut self, user: Option<User>);
 
-  fn user_id(&self) -> Result<String, HttpError> {
-    Ok(format!("USER_{}",
-      self.auth_token()
-        .ok_or(unauthorized("Invalid access token."))?
-        .split(TOKEN_DELIMITER)
-        .next()
-        .ok_or(unauthorized("Invalid access token."))?
-    ))
+  // The session is missing for tokens issued before sessions were introduced
+  fn session_key(&self) -> Result<(UserId, Option<SessionId>), HttpError> {
+    let token = self.auth_token().ok_or_else(invalid_token)?;
+    match parse_token(&token) {
+      Some((user_id, session_id)) => Ok((user_id, Some(session_id))),
+      None => parse_legacy_token(&token).map(|user_id| (user_id, None)).ok_or_else(invalid_token),
+    }
+  }
+
+  fn user_id(&self) -> Result<UserId, HttpError> {
+    self.session_key().map(|(user_id, _)| user_id)
+  }
+
+  fn session_id(&self) -> Result<Option<SessionId>, HttpError> {
+    self.session_key().map(|(_, session_id)| session_id)
   }
 
   fn fetch_current_user(&mut self) -> Result<&mut Self, HttpError> {
-    match User::find(self.db(), self.user_id()?.clone(), self.user_id()?) {
-      Ok(Some(user)) => {
-        // The unwrap is safe, because self.user_id() already checks for token presence
-        if user.auth_token == self.auth_token().unwrap() {
-          self.set_current_user(Some(user));
-        } else {
-          return Err(unauthorized("Invalid access token."));
-        }
-      },
-      Ok(None) => return Err(unauthorized("Invalid access token.")),
+    let (user_id, session_id) = self.session_key()?;
+    let is_legacy = session_id.is_none();
+    if let Some(session_id) = session_id {
+      match Session::find(self.db(), user_id.clone(), session_id) {
+        Ok(Some(session)) => {
+          // The unwrap is safe, because self.session_key() already checks for token presence
+          if !session.auth_token_matches(&self.auth_token().unwrap()) {
+            return Err(invalid_token());
+          } else if session.auth_token_expired() {
+            return Err(token_expired());
+          }
+          // Failing to mark the session as used shouldn't fail the request
+          if let Err(err) = session.touch(self.db()) {
+            eprintln!("Error updating session {}: {}", session.id, err);
+          }
+        },
+        Ok(None) => return Err(invalid_token()),
+        Err(err) => return Err(internal_server_error(err)),
+      };
+    }
+    match User::find(self.db(), user_id.clone(), user_id) {
+      // Legacy tokens are only valid until the user signs in again (see User#remove_legacy_tokens)
+      Ok(Some(ref user)) if is_legacy && !user.legacy_auth_token_matches(&self.auth_token().unwrap()) =>
+        return Err(invalid_token()),
+      Ok(Some(user)) => self.set_current_user(Some(user)),
+      Ok(None) => return Err(invalid_token()),
       Err(err) => return Err(internal_server_error(err)),
     };
     Ok(self)
@@ -1,25 +1,14 @@
 use std::fmt;
 use std::collections::HashMap;
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, SecondsFormat, Utc};
 use crypto::digest::Digest;
 use crypto::sha3::Sha3;
-use maplit::hashmap;
-use rusoto_dynamodb::{
-  DynamoDb,
-  DynamoDbClient,
-  GetItemInput,
-  QueryInput,
-  PutItemInput,
-  UpdateItemInput,
-  DeleteItemInput,
-  BatchWriteItemInput,
-  WriteRequest,
-  DeleteRequest,
-  AttributeValue
-};
+use rusoto_dynamodb::AttributeValue;
 use serde::Serialize;
 
+use crate::storage::{Condition, Query, Storage, Update, Write};
+
 pub type CommentableId = String;
 pub type PrimaryKey = String;
 pub type SortKey = String;
@@ -27,11 +16,20 @@ pub type SortKey = String;
 pub static COMMENTABLE_RS_TABLE_NAME: &str = "CommentableRsTable";
 pub static REPLIES_INDEX_NAME: &str = "replies-index";
 pub static REACTIONS_INDEX_NAME: &str = "reactions-index";
+pub static USERS_INDEX_NAME: &str = "users-index";
+pub static TOP_LEVEL_INDEX_NAME: &str = "top-level-index";
+// DynamoDB transactions can contain up to 25 writes
+pub static MAX_TRANSACTION_WRITES: usize = 25;
+// Numeric attribute bumped on every change of versioned records (comments & snapshots),
+// which conditional writes compare to detect concurrent changes
+pub static VERSION_FIELD_NAME: &str = "version";
 
 #[derive(Debug)]
 pub enum DbError {
   Error(String),
   RecordInvalid(String),
+  // The condition of a conditional write wasn't met
+  ConditionFailed,
 }
 
 impl fmt::Display for DbError {
@@ -39,6 +37,7 @@ impl fmt::Display for DbError {
     write!(f, "\"{}\"", match self {
       DbError::Error(msg) => format!("DbError::Error -> {}", msg),
       DbError::RecordInvalid(msg) => format!("DbError::RecordInvalid -> {}", msg),
+      DbError::ConditionFailed => String::from("DbError::ConditionFailed"),
     })
   }
 }
@@ -51,6 +50,11 @@ pub trait DynamoDbRecord {
   fn string(&mut self, field_name: &str) -> Result<String, DbError>;
   fn timestamp(&mut self, field_name: &str) -> Result<DateTime<Utc>, DbError>;
   fn optional_string(&mut self, field_name: &str) -> Option<String>;
+  fn optional_timestamp(&mut self, field_name: &str) -> Result<Option<DateTime<Utc>>, DbError>;
+  fn binary(&mut self, field_name: &str) -> Result<Vec<u8>, DbError>;
+  fn optional_number(&mut self, field_name: &str) -> Option<i64>;
+  fn optional_bool(&mut self, field_name: &str) -> Option<bool>;
+  fn string_set(&mut self, field_name: &str) -> Vec<String>;
 }
 
 impl DynamoDbRecord for DynamoDbAttributes {
@@ -69,13 +73,45 @@ impl DynamoDbRecord for DynamoDbAttributes {
           DbError::Error(format!("Error parsing timestamps in field '{}'", field_name))
         )
       ) // -> Result<DateTime<FixedOffset>, DbError>
-      .and_then(|datetime| Ok(datetime.with_timezone(&Utc))) // -> Result<DateTime<Utc>, DbError>
+      .map(|datetime| datetime.with_timezone(&Utc)) // -> Result<DateTime<Utc>, DbError>
   }
 
   fn optional_string(&mut self, field_name: &str) -> Option<String> {
     self.remove(field_name)
         .and_then(|value| value.s)
   }
+
+  fn optional_timestamp(&mut self, field_name: &str) -> Result<Option<DateTime<Utc>>, DbError> {
+    if self.contains_key(field_name) {
+      self.timestamp(field_name).map(Some)
+    } else {
+      Ok(None)
+    }
+  }
+
+  fn binary(&mut self, field_name: &str) -> Result<Vec<u8>, DbError> {
+    self.remove(field_name)
+        .and_then(|value| value.b)
+        .ok_or(DbError::RecordInvalid(format!("Missing field '{}'.", field_name)))
+  }
+
+  fn optional_number(&mut self, field_name: &str) -> Option<i64> {
+    self.remove(field_name)
+        .and_then(|value| value.n)
+        .and_then(|number| number.parse().ok())
+  }
+
+  fn optional_bool(&mut self, field_name: &str) -> Option<bool> {
+    self.remove(field_name)
+        .and_then(|value| value.bool)
+  }
+
+  // DynamoDB doesn't allow empty sets, so missing sets are empty
+  fn string_set(&mut self, field_name: &str) -> Vec<String> {
+    self.remove(field_name)
+        .and_then(|value| value.ss)
+        .unwrap_or_default()
+  }
 }
 
 // This struct allows us to easily create DynamoDbAttributes
@@ -105,130 +141,62 @@ pub trait DynamoDbModel where Self: Sized + Serialize {
   // #new is used internally to create structs from DynamoDB records
   fn new(attributes: DynamoDbAttributes) -> Result<Self, DbError>;
 
-  fn find(db: &DynamoDbClient, key: PrimaryKey, id: SortKey) -> Result<Option<Self>, DbError> {
-    db.get_item(GetItemInput {
-      key: hashmap!{
-        String::from("primary_key") => attribute_value(key),
-        String::from("id") => attribute_value(id),
-      },
-      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-      ..Default::default()
-    }).sync()
-      .map_err(|err| DbError::Error(err.to_string()))
-      .and_then(|output| {
-        Ok(output.item.and_then(|attributes| {
-          // The unwrapping below should be safe, as we're restoring the struct from an existing record
-          Some(Self::new(attributes).unwrap())
-        }))
-      })
-  }
-
-  fn query(db: &DynamoDbClient, query_input: QueryInput) -> Result<Vec<DynamoDbAttributes>, DbError> {
-    let mut results: Vec<DynamoDbAttributes> = vec![];
-    let mut last_evaluated_key = None;
-
-    'pagination: loop {
-      db.query(QueryInput {
-        exclusive_start_key: last_evaluated_key.clone(),
-        ..query_input.clone()
-      }).sync()
-        .map_err(|err| DbError::Error(err.to_string()))
-        .and_then(|query_output| {
-          results.append(query_output.items.unwrap_or(vec![]).as_mut());
-          last_evaluated_key = query_output.last_evaluated_key;
-          Ok(())
-        })?;
-
-      if last_evaluated_key == None {
-        break 'pagination;
-      }
+  fn find(db: &dyn Storage, key: PrimaryKey, id: SortKey) -> Result<Option<Self>, DbError> {
+    db.get(key, id)
+      .map(|item| item.map(|attributes| {
+        // The unwrapping below should be safe, as we're restoring the struct from an existing record
+        Self::new(attributes).unwrap()
+      }))
+  }
+
+  fn query(db: &dyn Storage, query: Query) -> Result<Vec<DynamoDbAttributes>, DbError> {
+    db.query(query)
+  }
+
+  // Returns a page of records along with the ID to pass as Query#start_after for the next page
+  fn query_page(db: &dyn Storage, query: Query) -> Result<(Vec<Self>, Option<SortKey>), DbError> {
+    let page = db.query_page(query)?;
+    let records = page.items
+      .into_iter()
+      .map(Self::new)
+      .collect::<Result<Vec<Self>, DbError>>()?;
+    Ok((records, page.last_evaluated_id))
+  }
+
+  fn batch_find(db: &dyn Storage, keys: Vec<(PrimaryKey, SortKey)>) -> Result<Vec<Self>, DbError> {
+    if keys.is_empty() {
+      return Ok(vec![]);
     }
+    db.batch_get(keys)?
+      .into_iter()
+      .map(Self::new)
+      .collect()
+  }
 
-    return Ok(results);
+  fn create(db: &dyn Storage, attributes: IntoDynamoDbAttributes) -> Result<Self, DbError> {
+    let attributes: DynamoDbAttributes = attributes.into();
+    db.put(attributes.clone())
+      .and_then(|_| Self::new(attributes))
   }
 
-  fn create(db: &DynamoDbClient, attributes: IntoDynamoDbAttributes) -> Result<Self, DbError> {
+  // Like #create, but never replaces an existing record.
+  // Returns DbError::ConditionFailed when a record with the same key already exists.
+  fn create_if_absent(db: &dyn Storage, attributes: IntoDynamoDbAttributes) -> Result<Self, DbError> {
     let attributes: DynamoDbAttributes = attributes.into();
-    db.put_item(PutItemInput {
-      item: attributes.clone(),
-      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-      ..Default::default()
-    }).sync()
-      .map_err(|err| DbError::Error(err.to_string()))
+    db.put_if(attributes.clone(), Condition::NotExists)
       .and_then(|_| Self::new(attributes))
   }
 
-  fn update(db: &DynamoDbClient, key: PrimaryKey, id: SortKey, expression: String, values: HashMap<String, AttributeValue>) -> Result<Self, DbError> {
-    db.update_item(UpdateItemInput {
-      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-      key: hashmap!{
-        String::from("primary_key") => attribute_value(key),
-        String::from("id") => attribute_value(id),
-      },
-      update_expression: Some(expression),
-      expression_attribute_values: Some(values),
-      return_values: Some(String::from("ALL_NEW")),
-      ..Default::default()
-    }).sync()
-      .map_err(|err| DbError::Error(err.to_string()))
-      .and_then(|output| Ok(
-        // The unwrapping below should be safe, as we're restoring the struct from an existing record
-        Self::new(output.attributes.unwrap()).unwrap()
-      ))
+  fn update(db: &dyn Storage, key: PrimaryKey, id: SortKey, update: Update) -> Result<Self, DbError> {
+    db.update(key, id, update).and_then(Self::new)
   }
 
-  fn delete(db: &DynamoDbClient, key: PrimaryKey, id: SortKey) -> Result<(), DbError> {
-    db.delete_item(DeleteItemInput {
-      key: hashmap!{
-        String::from("primary_key") => attribute_value(key),
-        String::from("id") => attribute_value(id),
-      },
-      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-      ..Default::default()
-    }).sync()
-      .map_err(|err| DbError::Error(err.to_string()))?;
-
-    Ok(())
-  }
-
-  fn batch_delete(db: &DynamoDbClient, mut keys: Vec<(PrimaryKey, SortKey)>) -> Result<(), DbError> {
-    let mut request_items: HashMap<String, Vec<WriteRequest>> = hashmap!{
-      String::from(COMMENTABLE_RS_TABLE_NAME) =>
-        keys
-          .drain(..)
-          .map(|(primary_key, sort_key)| WriteRequest {
-            delete_request: Some(DeleteRequest {
-              key: hashmap!{
-                String::from("primary_key") => attribute_value(primary_key),
-                String::from("id") => attribute_value(sort_key),
-              }
-            }),
-            ..Default::default()
-          }).collect(),
-    };
-    // Each request can delete max 25 items, we add 2 to account for any unexpected DB or Network errors
-    let max_iterations = request_items.get(COMMENTABLE_RS_TABLE_NAME).unwrap().len() as f32 / 25.0 + 2.0;
-    let mut current_iteration = 0.0;
-
-    'pagination: loop {
-      current_iteration += 1.0;
-
-      db.batch_write_item(BatchWriteItemInput {
-        request_items: request_items.clone(),
-        ..Default::default()
-      }).sync()
-        .map_err(|err| DbError::Error(err.to_string()))
-        .and_then(|output| {
-          request_items = output.unprocessed_items.unwrap();
-          Ok(())
-        })?;
-
-      if request_items.is_empty() || current_iteration > max_iterations {
-        break 'pagination;
-      }
-    }
+  fn delete(db: &dyn Storage, key: PrimaryKey, id: SortKey) -> Result<(), DbError> {
+    db.delete(key, id)
+  }
 
-    Ok(())
+  fn batch_delete(db: &dyn Storage, keys: Vec<(PrimaryKey, SortKey)>) -> Result<(), DbError> {
+    db.batch_delete(keys)
   }
 
   fn json(&self) -> String {
@@ -240,16 +208,8 @@ pub trait DynamoDbModel where Self: Sized + Serialize {
 pub trait DynamoDbListableModel where Self: DynamoDbModel {
   fn id_prefix() -> String;
 
-  fn list(db: &DynamoDbClient, commentable_id: CommentableId) -> Result<Vec<Self>, DbError> {
-    Self::query(&db, QueryInput {
-      table_name: COMMENTABLE_RS_TABLE_NAME.to_string(),
-      key_condition_expression: String::from("primary_key = :v1 and begins_with(id, :v2)").into(),
-      expression_attribute_values: hashmap!{
-        String::from(":v1") => attribute_value(commentable_id),
-        String::from(":v2") => attribute_value(Self::id_prefix()),
-      }.into(),
-      ..Default::default()
-    })?
+  fn list(db: &dyn Storage, commentable_id: CommentableId) -> Result<Vec<Self>, DbError> {
+    Self::query(db, Query::id_prefix(commentable_id, Self::id_prefix()))?
        .drain(..)
        .map(|attributes| Self::new(attributes))
        .collect::<Result<Vec<Self>, DbError>>()
@@ -276,12 +236,105 @@ impl From<bool> for IntoAttributeValue {
   }
 }
 
+impl From<i64> for IntoAttributeValue {
+  fn from(value: i64) -> Self {
+    let attribute_value = AttributeValue {
+      n: Some(value.to_string()),
+      ..Default::default()
+    };
+    IntoAttributeValue { attribute_value }
+  }
+}
+
+impl From<Vec<u8>> for IntoAttributeValue {
+  fn from(value: Vec<u8>) -> Self {
+    let attribute_value = AttributeValue {
+      b: Some(value),
+      ..Default::default()
+    };
+    IntoAttributeValue { attribute_value }
+  }
+}
+
+// Stored as a string set, which can't be empty
+impl From<Vec<String>> for IntoAttributeValue {
+  fn from(values: Vec<String>) -> Self {
+    let attribute_value = AttributeValue {
+      ss: Some(values),
+      ..Default::default()
+    };
+    IntoAttributeValue { attribute_value }
+  }
+}
+
 impl From<IntoAttributeValue> for AttributeValue {
   fn from(wrapper: IntoAttributeValue) -> Self {
     wrapper.attribute_value
   }
 }
 
+// Writes to different records, saved all at once or not at all:
+//   Transaction::default().delete(key, id, None).put(attributes, None).commit(db)?;
+#[derive(Default, Debug)]
+pub struct Transaction {
+  writes: Vec<Write>,
+}
+
+impl Transaction {
+  pub fn put(mut self, attributes: impl Into<DynamoDbAttributes>, condition: Option<Condition>) -> Self {
+    self.writes.push(Write::Put(attributes.into(), condition));
+    self
+  }
+
+  pub fn update(mut self, key: PrimaryKey, id: SortKey, update: Update) -> Self {
+    self.writes.push(Write::Update(key, id, update));
+    self
+  }
+
+  pub fn delete(mut self, key: PrimaryKey, id: SortKey, condition: Option<Condition>) -> Self {
+    self.writes.push(Write::Delete(key, id, condition));
+    self
+  }
+
+  pub fn delete_all(mut self, keys: Vec<(PrimaryKey, SortKey)>) -> Self {
+    self.writes.extend(keys.into_iter().map(|(key, id)| Write::Delete(key, id, None)));
+    self
+  }
+
+  pub fn len(&self) -> usize {
+    self.writes.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.writes.is_empty()
+  }
+
+  // Fails with DbError::ConditionFailed when any of the conditions isn't met,
+  // transactions with too many writes fail without saving anything
+  pub fn commit(self, db: &dyn Storage) -> Result<(), DbError> {
+    if self.writes.len() > MAX_TRANSACTION_WRITES {
+      Err(DbError::Error(format!("Transactions can contain up to {} writes, got {}.", MAX_TRANSACTION_WRITES, self.writes.len())))
+    } else if self.writes.is_empty() {
+      Ok(())
+    } else {
+      db.transact_write(self.writes)
+    }
+  }
+}
+
+// Extracts record keys from query results (DynamoDB indexes only project the keys)
+pub fn record_keys(items: Vec<DynamoDbAttributes>) -> Result<Vec<(PrimaryKey, SortKey)>, DbError> {
+  items
+    .into_iter()
+    .map(|mut item| Ok((item.string("primary_key")?, item.string("id")?)))
+    .collect()
+}
+
+// Fixed-width RFC 3339 timestamps, which can be compared as strings
+pub fn sortable_timestamp(time: DateTime<Utc>) -> String {
+  time.to_rfc3339_opts(SecondsFormat::Millis, true)
+}
+
 pub fn hash(text: &str) -> String {
   let mut hasher = Sha3::sha3_256();
   hasher.input_str(text);
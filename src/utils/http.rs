@@ -1,4 +1,5 @@
 use lambda_http::{Response, Body, http::StatusCode};
+use serde_json::json;
 
 pub type HttpError = Response<Body>;
 
@@ -27,6 +28,21 @@ where T: ToString {
   http_response(body.to_string(), StatusCode::NOT_FOUND)
 }
 
+pub fn conflict<T>(body: T) -> Response<Body>
+where T: ToString {
+  http_response(body.to_string(), StatusCode::CONFLICT)
+}
+
+pub fn method_not_allowed<T>(body: T) -> Response<Body>
+where T: ToString {
+  http_response(body.to_string(), StatusCode::METHOD_NOT_ALLOWED)
+}
+
+pub fn gone<T>(body: T) -> Response<Body>
+where T: ToString {
+  http_response(body.to_string(), StatusCode::GONE)
+}
+
 pub fn internal_server_error<T>(body: T) -> Response<Body>
 where T: ToString {
   http_response(body.to_string(), StatusCode::INTERNAL_SERVER_ERROR)
@@ -45,6 +61,11 @@ fn http_response(body: String, status: StatusCode) -> Response<Body> {
   }
 }
 
+// Errors which clients may need to tell apart are sent as {"error": code, "message": message}
+pub fn error_code(code: &str, message: &str) -> String {
+  json!({ "error": code, "message": message }).to_string()
+}
+
 pub fn missing_path_param(param: &str) -> String {
   format!("Invalid path parameters: {} is required", param)
 }
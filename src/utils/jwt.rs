@@ -0,0 +1,445 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use lazy_static::lazy_static;
+use ring::signature::{primitive::verify_rsa, RSA_PKCS1_2048_8192_SHA256};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+
+pub static GOOGLE_JWKS_URL: &str = "https://www.googleapis.com/oauth2/v3/certs";
+pub static GOOGLE_ISSUERS: &[&str] = &["accounts.google.com", "https://accounts.google.com"];
+
+// Keys are cached for an hour unless the JWKS response says otherwise (Google rotates them every few days)
+static DEFAULT_CACHE_SECONDS: i64 = 3600;
+// Unknown key IDs trigger a refetch (after a key rotation), but not more often than this
+static MIN_REFETCH_SECONDS: i64 = 60;
+// Allowed difference between our clock and the clock of the issuer
+static CLOCK_SKEW_SECONDS: i64 = 60;
+
+lazy_static! {
+  // Shared by every request handled by the same process, so warm lambdas don't refetch the keys
+  static ref GOOGLE_KEYS: Arc<JwksKeySource> = Arc::new(JwksKeySource::new(GOOGLE_JWKS_URL));
+}
+
+#[derive(Debug)]
+pub enum JwtError {
+  // The token is malformed, has an invalid signature or doesn't pass one of the checks
+  Invalid(String),
+  // The keys couldn't be fetched, so the token couldn't be verified
+  KeysUnavailable(String),
+}
+
+impl fmt::Display for JwtError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      JwtError::Invalid(msg) => write!(f, "Invalid ID token: {}", msg),
+      JwtError::KeysUnavailable(msg) => write!(f, "Couldn't fetch the keys verifying ID tokens: {}", msg),
+    }
+  }
+}
+
+pub fn invalid(msg: &str) -> JwtError {
+  JwtError::Invalid(msg.to_string())
+}
+
+// A public key from a JSON Web Key Set (only RSA keys are supported)
+#[derive(Deserialize, Clone, Debug)]
+pub struct Jwk {
+  pub kid: String,
+  pub kty: String,
+  // Base64url encoded modulus & exponent
+  pub n: String,
+  pub e: String,
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+  keys: Vec<Jwk>,
+}
+
+// Provides the public keys verifying ID token signatures.
+// Injectable, so that tokens signed with locally generated keys can be verified too (see StaticKeySource).
+pub trait KeySource: Send + Sync {
+  fn key(&self, kid: &str) -> Result<Option<Jwk>, JwtError>;
+}
+
+// A fixed set of keys, for tests & local development
+pub struct StaticKeySource {
+  pub keys: Vec<Jwk>,
+}
+
+impl KeySource for StaticKeySource {
+  fn key(&self, kid: &str) -> Result<Option<Jwk>, JwtError> {
+    Ok(self.keys.iter().find(|key| key.kid == kid).cloned())
+  }
+}
+
+struct CachedKeys {
+  keys: Vec<Jwk>,
+  fetched_at: DateTime<Utc>,
+  expires_at: DateTime<Utc>,
+}
+
+// Keys fetched from a JWKS URL and cached for as long as its Cache-Control header allows
+pub struct JwksKeySource {
+  url: String,
+  cache: Mutex<Option<CachedKeys>>,
+}
+
+impl JwksKeySource {
+  pub fn new(url: &str) -> Self {
+    Self { url: url.to_string(), cache: Mutex::new(None) }
+  }
+
+  fn fetch(&self) -> Result<CachedKeys, JwtError> {
+    let mut response = reqwest::get(&self.url)
+      .and_then(|response| response.error_for_status())
+      .map_err(|err| JwtError::KeysUnavailable(err.to_string()))?;
+    let max_age = response.headers()
+      .get(reqwest::header::CACHE_CONTROL)
+      .and_then(|value| value.to_str().ok())
+      .and_then(max_age)
+      .unwrap_or(DEFAULT_CACHE_SECONDS);
+    let jwks = response.json::<Jwks>().map_err(|err| JwtError::KeysUnavailable(err.to_string()))?;
+    let now = Utc::now();
+    Ok(CachedKeys { keys: jwks.keys, fetched_at: now, expires_at: now + Duration::seconds(max_age) })
+  }
+}
+
+impl JwksKeySource {
+  // The cached key, or None when the cache has to be refetched first
+  fn cached_key(&self, kid: &str) -> Result<Option<Option<Jwk>>, JwtError> {
+    let cache = self.cache.lock().map_err(|err| JwtError::KeysUnavailable(err.to_string()))?;
+    let now = Utc::now();
+    Ok(match cache.as_ref() {
+      Some(cached) if cached.expires_at > now => {
+        let key = cached.keys.iter().find(|key| key.kid == kid).cloned();
+        if key.is_some() || cached.fetched_at + Duration::seconds(MIN_REFETCH_SECONDS) > now {
+          Some(key)
+        } else {
+          None
+        }
+      },
+      _ => None,
+    })
+  }
+}
+
+impl KeySource for JwksKeySource {
+  fn key(&self, kid: &str) -> Result<Option<Jwk>, JwtError> {
+    if let Some(key) = self.cached_key(kid)? {
+      return Ok(key);
+    }
+    // The lock isn't held while fetching, so that a slow JWKS endpoint doesn't block requests with cached keys.
+    // Concurrent refetches are harmless, the last one wins.
+    let fetched = self.fetch()?;
+    let key = fetched.keys.iter().find(|key| key.kid == kid).cloned();
+    *self.cache.lock().map_err(|err| JwtError::KeysUnavailable(err.to_string()))? = Some(fetched);
+    Ok(key)
+  }
+}
+
+// "max-age=19970, must-revalidate" -> Some(19970)
+fn max_age(cache_control: &str) -> Option<i64> {
+  cache_control
+    .split(',')
+    .filter_map(|directive| directive.trim().strip_prefix("max-age="))
+    .find_map(|seconds| seconds.parse().ok())
+}
+
+#[derive(Deserialize)]
+struct Header {
+  alg: String,
+  kid: Option<String>,
+}
+
+// Claims of a verified ID token
+#[derive(Deserialize, Debug)]
+pub struct Claims {
+  pub iss: String,
+  #[serde(deserialize_with = "one_or_many")]
+  pub aud: Vec<String>,
+  pub sub: String,
+  pub exp: i64,
+  #[serde(default, deserialize_with = "bool_or_string")]
+  pub email_verified: bool,
+  // Other claims, whose names depend on the issuer (see oidc::ClaimNames)
+  #[serde(flatten)]
+  pub other: HashMap<String, Value>,
+}
+
+impl Claims {
+  // A non-empty string claim
+  pub fn string(&self, name: &str) -> Option<String> {
+    match self.other.get(name) {
+      Some(Value::String(value)) if !value.trim().is_empty() => Some(value.clone()),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Deserialize)]
+struct UnverifiedClaims {
+  iss: String,
+}
+
+// The issuer of a token, used to pick the keys verifying it (the token itself isn't verified)
+pub fn unverified_issuer(token: &str) -> Option<String> {
+  let payload = token.trim().split('.').nth(1)?;
+  serde_json::from_slice::<UnverifiedClaims>(&decode(payload).ok()?).ok().map(|claims| claims.iss)
+}
+
+// The audience can be a single string or an array of strings
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where D: Deserializer<'de> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+  }
+  Ok(match OneOrMany::deserialize(deserializer)? {
+    OneOrMany::One(audience) => vec![audience],
+    OneOrMany::Many(audiences) => audiences,
+  })
+}
+
+// Some issuers send booleans as strings
+fn bool_or_string<'de, D>(deserializer: D) -> Result<bool, D::Error>
+where D: Deserializer<'de> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum BoolOrString {
+    Bool(bool),
+    String(String),
+  }
+  Ok(match BoolOrString::deserialize(deserializer)? {
+    BoolOrString::Bool(value) => value,
+    BoolOrString::String(value) => value == "true",
+  })
+}
+
+// Verifies RS256 signed ID tokens offline, against keys of the issuer
+pub struct IdTokenVerifier {
+  pub keys: Arc<dyn KeySource>,
+  pub issuers: Vec<String>,
+  // Client IDs of our apps, tokens issued to other apps are rejected
+  pub audiences: Vec<String>,
+}
+
+impl IdTokenVerifier {
+  // Verifies Google ID tokens issued to the given client IDs (see config::google_client_ids)
+  pub fn google(audiences: Vec<String>) -> Self {
+    let keys: Arc<dyn KeySource> = GOOGLE_KEYS.clone();
+    Self {
+      keys,
+      issuers: GOOGLE_ISSUERS.iter().map(|issuer| issuer.to_string()).collect(),
+      audiences,
+    }
+  }
+
+  pub fn verify(&self, token: &str) -> Result<Claims, JwtError> {
+    let parts = token.trim().split('.').collect::<Vec<&str>>();
+    let (encoded_header, encoded_payload, signature) = match parts.as_slice() {
+      [header, payload, signature] => (*header, *payload, *signature),
+      _ => return Err(invalid("expected a JWT")),
+    };
+
+    let header = serde_json::from_slice::<Header>(&decode(encoded_header)?).map_err(|_| invalid("malformed header"))?;
+    if header.alg != "RS256" {
+      return Err(invalid("unsupported signing algorithm"));
+    }
+    let key = match header.kid {
+      Some(kid) => self.keys.key(&kid)?.ok_or_else(|| invalid("unknown signing key"))?,
+      None => return Err(invalid("missing key ID")),
+    };
+    if key.kty != "RSA" {
+      return Err(invalid("unsupported key type"));
+    }
+    // The signature covers the encoded header & payload, joined with a dot
+    let signed = format!("{}.{}", encoded_header, encoded_payload);
+    verify_rsa(
+      &RSA_PKCS1_2048_8192_SHA256,
+      (untrusted::Input::from(&decode(&key.n)?), untrusted::Input::from(&decode(&key.e)?)),
+      untrusted::Input::from(signed.as_bytes()),
+      untrusted::Input::from(&decode(signature)?),
+    ).map_err(|_| invalid("invalid signature"))?;
+
+    let claims = serde_json::from_slice::<Claims>(&decode(encoded_payload)?).map_err(|_| invalid("malformed claims"))?;
+    self.check_claims(&claims)?;
+    Ok(claims)
+  }
+
+  fn check_claims(&self, claims: &Claims) -> Result<(), JwtError> {
+    if !self.issuers.contains(&claims.iss) {
+      Err(invalid("unexpected issuer"))
+    } else if !claims.aud.iter().any(|audience| self.audiences.contains(audience)) {
+      Err(invalid("issued to another app"))
+    } else {
+      // Expiry times out of the range of DateTime are rejected instead of overflowing
+      let expires_at = Utc.timestamp_opt(claims.exp, 0).single()
+        .and_then(|exp| exp.checked_add_signed(Duration::seconds(CLOCK_SKEW_SECONDS)))
+        .ok_or_else(|| invalid("malformed expiration time"))?;
+      if expires_at < Utc::now() {
+        Err(invalid("expired"))
+      } else {
+        Ok(())
+      }
+    }
+  }
+}
+
+fn decode(part: &str) -> Result<Vec<u8>, JwtError> {
+  base64::decode_config(part, base64::URL_SAFE_NO_PAD).map_err(|_| invalid("malformed base64"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use ring::rand::SystemRandom;
+  use ring::signature::{RsaKeyPair, RSA_PKCS1_SHA256};
+  use serde_json::json;
+
+  // A 2048-bit key generated with `openssl genpkey -algorithm RSA` and converted to PKCS#1 DER for ring, and its public part as a JWK
+  static PRIVATE_KEY: &[u8] = include_bytes!("../../tests/fixtures/jwt_rsa_key.der");
+  static PUBLIC_KEY: &str = include_str!("../../tests/fixtures/jwt_rsa_key.jwk.json");
+
+  static ISSUER: &str = "https://issuer.example.com";
+  static AUDIENCE: &str = "our-app";
+
+  fn encode(bytes: &[u8]) -> String {
+    base64::encode_config(bytes, base64::URL_SAFE_NO_PAD)
+  }
+
+  fn sign(header: Value, claims: Value) -> String {
+    let key_pair = RsaKeyPair::from_der(untrusted::Input::from(PRIVATE_KEY)).unwrap();
+    let signed = format!("{}.{}", encode(header.to_string().as_bytes()), encode(claims.to_string().as_bytes()));
+    let mut signature = vec![0; key_pair.public_modulus_len()];
+    key_pair.sign(&RSA_PKCS1_SHA256, &SystemRandom::new(), signed.as_bytes(), &mut signature).unwrap();
+    format!("{}.{}", signed, encode(&signature))
+  }
+
+  fn header() -> Value {
+    json!({ "alg": "RS256", "kid": "test-key" })
+  }
+
+  fn claims() -> Value {
+    json!({
+      "iss": ISSUER,
+      "aud": AUDIENCE,
+      "sub": "1234",
+      "exp": (Utc::now() + Duration::minutes(10)).timestamp(),
+      "email": "jane@example.com",
+      "email_verified": "true",
+    })
+  }
+
+  fn verifier() -> IdTokenVerifier {
+    IdTokenVerifier {
+      keys: Arc::new(StaticKeySource { keys: vec![serde_json::from_str(PUBLIC_KEY).unwrap()] }),
+      issuers: vec![ISSUER.to_string()],
+      audiences: vec![AUDIENCE.to_string()],
+    }
+  }
+
+  fn assert_invalid(result: Result<Claims, JwtError>, expected: &str) {
+    match result {
+      Err(JwtError::Invalid(msg)) => assert_eq!(msg, expected),
+      Err(err) => panic!("expected an invalid token, got: {}", err),
+      Ok(claims) => panic!("expected an invalid token, got: {:?}", claims),
+    }
+  }
+
+  #[test]
+  fn verifies_tokens_signed_with_a_known_key() {
+    let claims = verifier().verify(&sign(header(), claims())).unwrap();
+    assert_eq!(claims.iss, ISSUER);
+    assert_eq!(claims.aud, vec![AUDIENCE.to_string()]);
+    assert_eq!(claims.sub, "1234");
+    assert!(claims.email_verified);
+    assert_eq!(claims.string("email"), Some("jane@example.com".to_string()));
+  }
+
+  #[test]
+  fn accepts_any_of_several_audiences() {
+    let mut claims = claims();
+    claims["aud"] = json!(["another-app", AUDIENCE]);
+    assert!(verifier().verify(&sign(header(), claims)).is_ok());
+  }
+
+  #[test]
+  fn rejects_tampered_tokens() {
+    let token = sign(header(), claims());
+    let mut parts = token.split('.').map(String::from).collect::<Vec<String>>();
+    let mut claims = claims();
+    claims["sub"] = json!("5678");
+    parts[1] = encode(claims.to_string().as_bytes());
+    assert_invalid(verifier().verify(&parts.join(".")), "invalid signature");
+  }
+
+  #[test]
+  fn rejects_unknown_keys() {
+    assert_invalid(verifier().verify(&sign(json!({ "alg": "RS256", "kid": "other-key" }), claims())), "unknown signing key");
+    assert_invalid(verifier().verify(&sign(json!({ "alg": "RS256" }), claims())), "missing key ID");
+  }
+
+  #[test]
+  fn rejects_other_algorithms() {
+    assert_invalid(verifier().verify(&sign(json!({ "alg": "none", "kid": "test-key" }), claims())), "unsupported signing algorithm");
+  }
+
+  #[test]
+  fn rejects_other_issuers() {
+    let mut claims = claims();
+    claims["iss"] = json!("https://evil.example.com");
+    assert_invalid(verifier().verify(&sign(header(), claims)), "unexpected issuer");
+  }
+
+  #[test]
+  fn rejects_tokens_issued_to_other_apps() {
+    let mut claims = claims();
+    claims["aud"] = json!("another-app");
+    assert_invalid(verifier().verify(&sign(header(), claims)), "issued to another app");
+  }
+
+  #[test]
+  fn rejects_expired_tokens() {
+    let mut claims = claims();
+    claims["exp"] = json!((Utc::now() - Duration::minutes(10)).timestamp());
+    assert_invalid(verifier().verify(&sign(header(), claims)), "expired");
+  }
+
+  #[test]
+  fn tolerates_clock_skew() {
+    let mut claims = claims();
+    claims["exp"] = json!((Utc::now() - Duration::seconds(CLOCK_SKEW_SECONDS / 2)).timestamp());
+    assert!(verifier().verify(&sign(header(), claims)).is_ok());
+  }
+
+  #[test]
+  fn rejects_expiration_times_out_of_range() {
+    let mut claims = claims();
+    claims["exp"] = json!(i64::MAX);
+    assert_invalid(verifier().verify(&sign(header(), claims)), "malformed expiration time");
+  }
+
+  #[test]
+  fn rejects_malformed_tokens() {
+    assert_invalid(verifier().verify("abc"), "expected a JWT");
+    assert_invalid(verifier().verify("a.b.c"), "malformed base64");
+  }
+
+  #[test]
+  fn reads_the_issuer_without_verifying() {
+    assert_eq!(unverified_issuer(&sign(header(), claims())), Some(ISSUER.to_string()));
+    assert_eq!(unverified_issuer("abc"), None);
+  }
+
+  #[test]
+  fn parses_max_age() {
+    assert_eq!(max_age("public, max-age=19970, must-revalidate"), Some(19970));
+    assert_eq!(max_age("no-cache"), None);
+  }
+}
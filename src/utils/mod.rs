@@ -2,3 +2,8 @@ pub mod db;
 pub mod http;
 pub mod current_user;
 pub mod current_comment;
+pub mod current_commentable;
+pub mod router;
+pub mod config;
+pub mod jwt;
+pub mod oidc;
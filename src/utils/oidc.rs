@@ -0,0 +1,201 @@
+// Login providers: Google and other OpenID Connect issuers (see config::oidc_providers)
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::models::user::UserId;
+use crate::utils::config;
+use crate::utils::db::hash;
+use crate::utils::jwt::{
+  invalid,
+  Claims,
+  IdTokenVerifier,
+  Jwk,
+  JwksKeySource,
+  JwtError,
+  KeySource,
+  GOOGLE_ISSUERS,
+};
+
+pub static GOOGLE_PROVIDER_NAME: &str = "google";
+
+lazy_static! {
+  // Keys of discovered issuers, shared by every request handled by the same process
+  static ref DISCOVERED_KEYS: Mutex<HashMap<String, Arc<DiscoveredKeySource>>> = Mutex::new(HashMap::new());
+}
+
+// Names of the claims holding the profile of the user
+#[derive(Deserialize, Clone, Debug)]
+pub struct ClaimNames {
+  #[serde(default = "default_email_claim")]
+  pub email: String,
+  #[serde(default = "default_name_claim")]
+  pub name: String,
+  #[serde(default = "default_picture_claim")]
+  pub picture: String,
+}
+
+fn default_email_claim() -> String { String::from("email") }
+fn default_name_claim() -> String { String::from("name") }
+fn default_picture_claim() -> String { String::from("picture") }
+
+impl Default for ClaimNames {
+  fn default() -> Self {
+    Self { email: default_email_claim(), name: default_name_claim(), picture: default_picture_claim() }
+  }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct Provider {
+  // Short name of the provider, e.g. "keycloak"
+  pub name: String,
+  // The "iss" of its ID tokens, the discovery document is read from <issuer>/.well-known/openid-configuration
+  pub issuer: String,
+  // Client IDs of our apps registered with the provider (the "aud" of ID tokens)
+  pub client_ids: Vec<String>,
+  #[serde(default)]
+  pub claims: ClaimNames,
+  // Users with a verified email share the account of that email with other providers (e.g. Google),
+  // instead of getting an account of their own. Tokens without a verified email are rejected.
+  #[serde(default)]
+  pub link_by_email: bool,
+  // Overrides the keys of the issuer (e.g. with keys generated locally for tests)
+  #[serde(skip)]
+  pub keys: Option<Arc<dyn KeySource>>,
+}
+
+// The user signing in, as described by a verified ID token
+pub struct AuthData {
+  pub user_id: UserId,
+  // Name of the provider the user signed in with
+  pub provider: String,
+  pub email: String,
+  pub name: String,
+  pub picture: String,
+}
+
+impl Provider {
+  // Google accounts are identified by their verified emails, as they always were
+  pub fn google(client_ids: Vec<String>) -> Self {
+    Self {
+      name: GOOGLE_PROVIDER_NAME.to_string(),
+      issuer: GOOGLE_ISSUERS[1].to_string(),
+      client_ids,
+      claims: ClaimNames::default(),
+      link_by_email: true,
+      keys: None,
+    }
+  }
+
+  fn is_google(&self) -> bool {
+    GOOGLE_ISSUERS.contains(&self.issuer.as_str())
+  }
+
+  pub fn accepts_issuer(&self, issuer: &str) -> bool {
+    if self.is_google() {
+      GOOGLE_ISSUERS.contains(&issuer)
+    } else {
+      self.issuer == issuer
+    }
+  }
+
+  pub fn verifier(&self) -> IdTokenVerifier {
+    let mut verifier = if self.is_google() {
+      IdTokenVerifier::google(self.client_ids.clone())
+    } else {
+      IdTokenVerifier {
+        keys: discovered_keys(&self.issuer),
+        issuers: vec![self.issuer.clone()],
+        audiences: self.client_ids.clone(),
+      }
+    };
+    if let Some(keys) = &self.keys {
+      verifier.keys = keys.clone();
+    }
+    verifier
+  }
+
+  pub fn auth_data(&self, claims: Claims) -> Result<AuthData, JwtError> {
+    // Unverified emails are never stored, so that they can't be used to impersonate site admins
+    let email = claims.string(&self.claims.email).filter(|_| claims.email_verified);
+    let user_id = match &email {
+      Some(email) if self.link_by_email => hash(email),
+      None if self.link_by_email => return Err(invalid("the email address isn't verified")),
+      // Subjects are only unique within their issuer
+      _ => hash(&format!("{}|{}", self.issuer, claims.sub)),
+    };
+    let email = email.unwrap_or_default();
+    Ok(AuthData {
+      user_id: format!("USER_{}", user_id),
+      provider: self.name.clone(),
+      name: claims.string(&self.claims.name)
+        .unwrap_or_else(|| if email.is_empty() { claims.sub.clone() } else { email.clone() }),
+      picture: claims.string(&self.claims.picture).unwrap_or_default(),
+      email,
+    })
+  }
+}
+
+// Google (when its client IDs are configured), followed by the configured OpenID Connect providers
+pub fn providers() -> Result<Vec<Provider>, String> {
+  let mut providers = vec![];
+  let google_client_ids = config::google_client_ids();
+  if !google_client_ids.is_empty() {
+    providers.push(Provider::google(google_client_ids));
+  }
+  providers.extend(config::oidc_providers()?);
+  Ok(providers)
+}
+
+#[derive(Deserialize)]
+struct Discovery {
+  issuer: String,
+  jwks_uri: String,
+}
+
+// Keys found through the discovery document of an issuer, which is only fetched once
+struct DiscoveredKeySource {
+  issuer: String,
+  jwks: Mutex<Option<Arc<JwksKeySource>>>,
+}
+
+impl DiscoveredKeySource {
+  fn discover(&self) -> Result<JwksKeySource, JwtError> {
+    let url = format!("{}/.well-known/openid-configuration", self.issuer.trim_end_matches('/'));
+    let discovery = reqwest::get(&url)
+      .and_then(|response| response.error_for_status())
+      .and_then(|mut response| response.json::<Discovery>())
+      .map_err(|err| JwtError::KeysUnavailable(err.to_string()))?;
+    if discovery.issuer != self.issuer {
+      return Err(JwtError::KeysUnavailable(format!("{} is discovered as {}", self.issuer, discovery.issuer)));
+    }
+    Ok(JwksKeySource::new(&discovery.jwks_uri))
+  }
+}
+
+impl KeySource for DiscoveredKeySource {
+  fn key(&self, kid: &str) -> Result<Option<Jwk>, JwtError> {
+    let discovered = self.jwks.lock().map_err(|err| JwtError::KeysUnavailable(err.to_string()))?.clone();
+    let jwks = match discovered {
+      Some(jwks) => jwks,
+      // Discovered without holding the lock, like the keys themselves (see JwksKeySource)
+      None => {
+        let jwks = Arc::new(self.discover()?);
+        let mut discovered = self.jwks.lock().map_err(|err| JwtError::KeysUnavailable(err.to_string()))?;
+        discovered.get_or_insert(jwks).clone()
+      },
+    };
+    jwks.key(kid)
+  }
+}
+
+fn discovered_keys(issuer: &str) -> Arc<dyn KeySource> {
+  // A poisoned lock only means another request panicked, the map itself is still usable
+  let mut keys = DISCOVERED_KEYS.lock().unwrap_or_else(|err| err.into_inner());
+  keys
+    .entry(issuer.to_string())
+    .or_insert_with(|| Arc::new(DiscoveredKeySource { issuer: issuer.to_string(), jwks: Mutex::new(None) }))
+    .clone()
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use lambda_http::{http::Method, request, Request, Response, Body, RequestExt};
+use maplit::hashmap;
+use percent_encoding::percent_decode_str;
+use serde::Serialize;
+use serde_json::json;
+
+use crate::handlers::{
+  auth::auth,
+  refresh_auth::refresh_auth,
+  list_sessions::ListSessions,
+  logout::Logout,
+  revoke_sessions::RevokeSessions,
+  options::options,
+  list_comments::ListComments,
+  add_comment::AddComment,
+  edit_comment::EditComment,
+  delete_comment::DeleteComment,
+  add_reaction::AddReaction,
+  delete_reaction::DeleteReaction,
+  count_comments::CountComments,
+  update_commentable::UpdateCommentable,
+  pin_comment::PinComment,
+  list_revisions::ListRevisions,
+};
+use crate::utils::http::{bad_request, method_not_allowed, not_found};
+
+// All endpoints defined in lambda/release/template.yml
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Endpoint {
+  Auth,
+  RefreshAuth,
+  ListSessions,
+  Logout,
+  RevokeSessions,
+  ListComments,
+  AddComment,
+  EditComment,
+  DeleteComment,
+  AddReaction,
+  DeleteReaction,
+  CountComments,
+  UpdateCommentable,
+  PinComment,
+  ListRevisions,
+}
+
+impl Endpoint {
+  // Matches the request path and extracts the path parameters
+  pub fn recognize(path: &str) -> Option<(Self, HashMap<String, String>)> {
+    let segments = path.trim_matches('/').split('/').collect::<Vec<&str>>();
+    let (endpoint, id) = match segments.as_slice() {
+      ["auth"] => return Some((Endpoint::Auth, hashmap!{})),
+      ["auth", "refresh"] => return Some((Endpoint::RefreshAuth, hashmap!{})),
+      ["auth", "sessions"] => return Some((Endpoint::ListSessions, hashmap!{})),
+      ["auth", "logout"] => return Some((Endpoint::Logout, hashmap!{})),
+      ["auth", "revoke"] => return Some((Endpoint::RevokeSessions, hashmap!{})),
+      ["comments", "count"] => return Some((Endpoint::CountComments, hashmap!{})),
+      ["commentable", id, "update"] => (Endpoint::UpdateCommentable, id),
+      ["commentable", id, "comments", "list"] => (Endpoint::ListComments, id),
+      ["commentable", id, "comments", "add"] => (Endpoint::AddComment, id),
+      ["commentable", id, "comments", "edit"] => (Endpoint::EditComment, id),
+      ["commentable", id, "comments", "delete"] => (Endpoint::DeleteComment, id),
+      ["commentable", id, "comments", "pin"] => (Endpoint::PinComment, id),
+      ["commentable", id, "comments", "revisions"] => (Endpoint::ListRevisions, id),
+      ["commentable", id, "reactions", "add"] => (Endpoint::AddReaction, id),
+      ["commentable", id, "reactions", "delete"] => (Endpoint::DeleteReaction, id),
+      _ => return None,
+    };
+    let id = percent_decode_str(id).decode_utf8().ok()?.to_string();
+    if id.is_empty() {
+      None
+    } else {
+      Some((endpoint, hashmap!{ String::from("id") => id }))
+    }
+  }
+
+  pub fn respond_to(self, request: Request) -> Response<Body> {
+    match self {
+      Endpoint::Auth => auth(request),
+      Endpoint::RefreshAuth => refresh_auth(request),
+      Endpoint::ListSessions => ListSessions::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::Logout => Logout::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::RevokeSessions => RevokeSessions::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::ListComments => ListComments::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::AddComment => AddComment::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::EditComment => EditComment::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::DeleteComment => DeleteComment::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::AddReaction => AddReaction::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::DeleteReaction => DeleteReaction::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::CountComments => CountComments::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::UpdateCommentable => UpdateCommentable::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::PinComment => PinComment::respond_to(request).unwrap_or_else(|error_response| error_response),
+      Endpoint::ListRevisions => ListRevisions::respond_to(request).unwrap_or_else(|error_response| error_response),
+    }
+  }
+}
+
+// Dispatches a request to the handler of a matching endpoint.
+// Every endpoint accepts POST requests and answers OPTIONS (CORS preflight) requests.
+pub fn route(request: Request) -> Response<Body> {
+  match Endpoint::recognize(request.uri().path()) {
+    None => not_found("Not found"),
+    Some(_) if request.method() == Method::OPTIONS => options(request),
+    Some(_) if request.method() != Method::POST => method_not_allowed("Method not allowed"),
+    Some((endpoint, path_parameters)) => match with_path_parameters(request, path_parameters) {
+      Ok(request) => endpoint.respond_to(request),
+      Err(error_response) => error_response,
+    },
+  }
+}
+
+// A minimal API Gateway proxy event, used to build requests outside of API Gateway
+// (lambda_http only allows setting path parameters when deserializing events)
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ProxyEvent {
+  pub path: String,
+  pub http_method: String,
+  pub headers: HashMap<String, String>,
+  pub query_string_parameters: HashMap<String, String>,
+  pub path_parameters: HashMap<String, String>,
+  pub body: Option<String>,
+  pub is_base64_encoded: bool,
+}
+
+impl ProxyEvent {
+  pub fn into_request(self) -> Result<Request, Response<Body>> {
+    let mut event = serde_json::to_value(&self).map_err(bad_request)?;
+    event["requestContext"] = json!({
+      "accountId": "",
+      "resourceId": "",
+      "stage": "",
+      "requestId": "",
+      "resourcePath": self.path,
+      "httpMethod": self.http_method,
+      "apiId": "",
+      "identity": { "sourceIp": "" },
+    });
+    request::from_str(&event.to_string()).map_err(bad_request)
+  }
+}
+
+fn with_path_parameters(request: Request, path_parameters: HashMap<String, String>) -> Result<Request, Response<Body>> {
+  let query_string_parameters = request
+    .query_string_parameters()
+    .iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()))
+    .collect();
+  let headers = request
+    .headers()
+    .iter()
+    .filter_map(|(name, value)| value.to_str().ok().map(|value| (name.to_string(), value.to_string())))
+    .collect();
+  let (body, is_base64_encoded) = match request.body() {
+    Body::Empty => (None, false),
+    Body::Text(text) => (Some(text.clone()), false),
+    Body::Binary(bytes) => (Some(base64::encode(bytes)), true),
+  };
+
+  ProxyEvent {
+    path: request.uri().path().to_string(),
+    http_method: request.method().to_string(),
+    headers,
+    query_string_parameters,
+    path_parameters,
+    body,
+    is_base64_encoded,
+  }.into_request()
+}
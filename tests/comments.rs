@@ -0,0 +1,245 @@
+// Flows of the comments endpoints, run through the router against the in-memory storage backend
+use std::collections::HashMap;
+use std::env;
+
+use chrono::Utc;
+use lambda_http::Body;
+use maplit::hashmap;
+use serde_json::{json, Value};
+
+use commentable_rs::models::session::Session;
+use commentable_rs::models::user::{User, TOKEN_DELIMITER};
+use commentable_rs::storage::{self, STORAGE_ENV_VAR};
+use commentable_rs::utils::db::{hash, DynamoDbModel, IntoDynamoDbAttributes};
+use commentable_rs::utils::router::{route, ProxyEvent};
+
+// Every test uses the same (shared) in-memory storage, so they use their own commentables & users
+fn sign_in(name: &str) -> String {
+  env::set_var(STORAGE_ENV_VAR, "memory");
+  let db = storage::connect().unwrap();
+  let user_id = format!("USER_{}", name);
+  User::create(db.as_ref(), IntoDynamoDbAttributes {
+    attributes: hashmap!{
+      String::from("primary_key") => user_id.clone().into(),
+      String::from("id") => user_id.clone().into(),
+      String::from("email") => format!("{}@example.com", name).into(),
+      String::from("name") => name.to_string().into(),
+      String::from("picture_url") => String::from("https://example.com/picture.png").into(),
+      String::from("created_at") => Utc::now().to_rfc3339().into(),
+    }
+  }).unwrap();
+  let (_, tokens) = Session::start(db.as_ref(), &user_id, "Tests").unwrap();
+  tokens.auth_token
+}
+
+fn post(path: &str, params: Value) -> (u16, Value) {
+  env::set_var(STORAGE_ENV_VAR, "memory");
+  let request = ProxyEvent {
+    path: path.to_string(),
+    http_method: String::from("POST"),
+    // lambda_http builds the request URI from the host
+    headers: hashmap!{
+      String::from("Host") => String::from("localhost"),
+      String::from("Content-Type") => String::from("application/json"),
+    },
+    query_string_parameters: HashMap::new(),
+    path_parameters: HashMap::new(),
+    body: Some(params.to_string()),
+    is_base64_encoded: false,
+  }.into_request().unwrap();
+
+  let response = route(request);
+  let body = match response.body() {
+    Body::Text(text) => serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.clone())),
+    Body::Binary(bytes) => serde_json::from_slice(bytes).unwrap(),
+    Body::Empty => Value::Null,
+  };
+  (response.status().as_u16(), body)
+}
+
+fn add_comment(commentable_id: &str, auth_token: &str, body: &str, replies_to: Option<&Value>) -> Value {
+  let (status, comment) = post(&format!("/commentable/{}/comments/add", commentable_id), json!({
+    "auth_token": auth_token,
+    "body": body,
+    "replies_to": replies_to,
+  }));
+  assert_eq!(status, 200, "{}", comment);
+  comment
+}
+
+fn list_comments(commentable_id: &str) -> Vec<Value> {
+  let (status, list) = post(&format!("/commentable/{}/comments/list", commentable_id), json!({}));
+  assert_eq!(status, 200, "{}", list);
+  list["comments"].as_array().unwrap().clone()
+}
+
+fn count_comments(commentable_id: &str) -> Value {
+  let (status, counts) = post("/comments/count", json!({ "commentable_ids": [commentable_id] }));
+  assert_eq!(status, 200, "{}", counts);
+  counts[commentable_id].clone()
+}
+
+fn bodies(comments: &[Value]) -> Vec<&str> {
+  comments.iter().map(|comment| comment["body"].as_str().unwrap()).collect()
+}
+
+#[test]
+fn comments_are_added_and_listed_with_their_replies() {
+  let auth_token = sign_in("alice");
+  let first = add_comment("flow-add", &auth_token, "First", None);
+  add_comment("flow-add", &auth_token, "Reply", Some(&first["id"]));
+  add_comment("flow-add", &auth_token, "Second", None);
+
+  let comments = list_comments("flow-add");
+  assert_eq!(bodies(&comments), vec!["First", "Second"]);
+  assert_eq!(bodies(comments[0]["replies"].as_array().unwrap()), vec!["Reply"]);
+  assert_eq!(comments[0]["user"]["name"], "alice");
+  assert_eq!(comments[0]["version"], 1);
+  assert_eq!(count_comments("flow-add"), json!({ "total": 3, "top_level": 2 }));
+}
+
+#[test]
+fn invalid_comments_are_rejected() {
+  let auth_token = sign_in("bob");
+  let (status, _) = post("/commentable/flow-invalid/comments/add", json!({ "auth_token": auth_token, "body": " " }));
+  assert_eq!(status, 400);
+  let (status, _) = post("/commentable/flow-invalid/comments/add", json!({ "auth_token": "bob-=#=-invalid", "body": "Hi" }));
+  assert_eq!(status, 401);
+  let (status, _) = post("/commentable/flow-invalid/comments/add", json!({
+    "auth_token": auth_token,
+    "body": "Hi",
+    "replies_to": "COMMENT_missing",
+  }));
+  assert_eq!(status, 400);
+
+  assert!(list_comments("flow-invalid").is_empty());
+  assert_eq!(count_comments("flow-invalid"), json!({ "total": 0, "top_level": 0 }));
+}
+
+#[test]
+fn comments_are_edited_keeping_their_revisions() {
+  let auth_token = sign_in("carol");
+  let comment = add_comment("flow-edit", &auth_token, "Helo", None);
+
+  let (status, edited) = post("/commentable/flow-edit/comments/edit", json!({
+    "auth_token": auth_token,
+    "comment_id": comment["id"],
+    "body": "Hello",
+    "version": 1,
+  }));
+  assert_eq!(status, 200, "{}", edited);
+  assert_eq!(edited["version"], 2);
+  assert_eq!(edited["edit_count"], 1);
+
+  // Edits based on an outdated version are rejected with the current one
+  let (status, conflict) = post("/commentable/flow-edit/comments/edit", json!({
+    "auth_token": auth_token,
+    "comment_id": comment["id"],
+    "body": "Hi",
+    "version": 1,
+  }));
+  assert_eq!(status, 409);
+  assert_eq!(conflict["body"], "Hello");
+  assert_eq!(conflict["version"], 2);
+
+  let (status, _) = post("/commentable/flow-edit/comments/edit", json!({
+    "auth_token": auth_token,
+    "comment_id": comment["id"],
+    "body": "Hello there",
+  }));
+  assert_eq!(status, 200);
+
+  let comments = list_comments("flow-edit");
+  assert_eq!(bodies(&comments), vec!["Hello there"]);
+  assert!(comments[0]["edited_at"].is_string());
+
+  let (status, revisions) = post("/commentable/flow-edit/comments/revisions", json!({
+    "auth_token": auth_token,
+    "comment_id": comment["id"],
+  }));
+  assert_eq!(status, 200, "{}", revisions);
+  assert_eq!(bodies(revisions.as_array().unwrap()), vec!["Helo", "Hello"]);
+}
+
+#[test]
+fn comments_can_only_be_edited_by_their_authors() {
+  let author_token = sign_in("dave");
+  let other_token = sign_in("eve");
+  let comment = add_comment("flow-forbidden", &author_token, "Mine", None);
+
+  let (status, _) = post("/commentable/flow-forbidden/comments/edit", json!({
+    "auth_token": other_token,
+    "comment_id": comment["id"],
+    "body": "Yours",
+  }));
+  assert_eq!(status, 403);
+  let (status, _) = post("/commentable/flow-forbidden/comments/delete", json!({
+    "auth_token": other_token,
+    "comment_id": comment["id"],
+  }));
+  assert_eq!(status, 403);
+
+  assert_eq!(bodies(&list_comments("flow-forbidden")), vec!["Mine"]);
+}
+
+#[test]
+fn comments_are_deleted_or_erased_when_they_have_replies() {
+  let auth_token = sign_in("frank");
+  let parent = add_comment("flow-delete", &auth_token, "Parent", None);
+  let reply = add_comment("flow-delete", &auth_token, "Reply", Some(&parent["id"]));
+  let other = add_comment("flow-delete", &auth_token, "Other", None);
+
+  // Comments with replies keep their place in the thread
+  let (status, _) = post("/commentable/flow-delete/comments/delete", json!({ "auth_token": auth_token, "comment_id": parent["id"] }));
+  assert_eq!(status, 200);
+  let comments = list_comments("flow-delete");
+  assert_eq!(bodies(&comments), vec!["This comment has been deleted.", "Other"]);
+  assert!(comments[0]["user"].is_null());
+  assert_eq!(bodies(comments[0]["replies"].as_array().unwrap()), vec!["Reply"]);
+
+  for comment in &[reply, other] {
+    let (status, _) = post("/commentable/flow-delete/comments/delete", json!({ "auth_token": auth_token, "comment_id": comment["id"] }));
+    assert_eq!(status, 200);
+  }
+  let comments = list_comments("flow-delete");
+  assert_eq!(bodies(&comments), vec!["This comment has been deleted."]);
+  assert!(comments[0]["replies"].as_array().unwrap().is_empty());
+  assert_eq!(count_comments("flow-delete"), json!({ "total": 0, "top_level": 0 }));
+
+  let (status, _) = post("/commentable/flow-delete/comments/delete", json!({ "auth_token": auth_token, "comment_id": "COMMENT_missing" }));
+  assert_eq!(status, 404);
+}
+
+#[test]
+fn tokens_issued_before_sessions_work_until_logging_out() {
+  env::set_var(STORAGE_ENV_VAR, "memory");
+  let db = storage::connect().unwrap();
+  // Users used to be signed in with a single token stored in their record
+  let user_hash = hash("grace@example.com");
+  let auth_token = format!("{}{}{}", user_hash, TOKEN_DELIMITER, hash(&Utc::now().to_string()));
+  User::create(db.as_ref(), IntoDynamoDbAttributes {
+    attributes: hashmap!{
+      String::from("primary_key") => format!("USER_{}", user_hash).into(),
+      String::from("id") => format!("USER_{}", user_hash).into(),
+      String::from("email") => String::from("grace@example.com").into(),
+      String::from("name") => String::from("grace").into(),
+      String::from("picture_url") => String::from("https://example.com/picture.png").into(),
+      String::from("auth_token") => auth_token.clone().into(),
+      String::from("created_at") => Utc::now().to_rfc3339().into(),
+    }
+  }).unwrap();
+
+  add_comment("flow-legacy", &auth_token, "Still signed in", None);
+  let (status, sessions) = post("/auth/sessions", json!({ "auth_token": auth_token }));
+  assert_eq!(status, 200, "{}", sessions);
+  assert_eq!(sessions, json!([]));
+  let wrong_token = format!("{}{}{}", user_hash, TOKEN_DELIMITER, hash("guess"));
+  let (status, _) = post("/commentable/flow-legacy/comments/add", json!({ "auth_token": wrong_token, "body": "Hi" }));
+  assert_eq!(status, 401);
+
+  let (status, _) = post("/auth/logout", json!({ "auth_token": auth_token }));
+  assert_eq!(status, 200);
+  let (status, _) = post("/commentable/flow-legacy/comments/add", json!({ "auth_token": auth_token, "body": "Hi" }));
+  assert_eq!(status, 401);
+  assert_eq!(bodies(&list_comments("flow-legacy")), vec!["Still signed in"]);
+}